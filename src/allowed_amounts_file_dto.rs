@@ -0,0 +1,82 @@
+use serde::{Deserialize, Serialize};
+use serde_json::Number;
+
+use crate::in_network_file_dto::{BillingClass, BillingCodeType, Tin};
+
+// follows the cms allowed-amounts schema, for out-of-network payments:
+// https://github.com/CMSgov/price-transparency-guide/tree/master/schemas/allowed-amounts
+
+#[derive(Deserialize, Debug, Serialize)]
+pub struct AllowedAmountsFile {
+    pub reporting_entity_name: String,
+    pub reporting_entity_type: String,
+    pub plan_name: Option<String>,
+    pub plan_id_type: Option<String>,
+    pub plan_id: Option<String>,
+    pub plan_market_type: Option<String>,
+    pub last_updated_on: String,
+    pub version: String,
+
+    pub out_of_network: Vec<OutOfNetworkObject>,
+}
+
+#[derive(Deserialize, Debug, Serialize)]
+pub struct OutOfNetworkObject {
+    pub name: String,
+    pub billing_code_type: BillingCodeType,
+    pub billing_code_type_version: String,
+    pub billing_code: String,
+    pub description: String,
+    pub allowed_amounts: Vec<AllowedAmount>,
+}
+
+#[derive(Deserialize, Debug, Serialize)]
+pub struct AllowedAmount {
+    pub tin: Tin,
+    pub service_code: Option<Vec<String>>,
+    pub billing_class: BillingClass,
+    pub payments: Vec<Payment>,
+}
+
+#[derive(Deserialize, Debug, Serialize)]
+pub struct Payment {
+    pub allowed_amount: Number,
+    pub billing_code_modifier: Option<Vec<String>>,
+    pub providers: Vec<ProviderPayment>,
+}
+
+// what a provider billed, for the payment it was allowed
+#[derive(Deserialize, Debug, Serialize)]
+pub struct ProviderPayment {
+    pub billed_charge: Number,
+    pub npi: Vec<Number>,
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use super::*;
+    use crate::{get_filtered_allowed_amounts_file, node_filters::NodeFilters};
+
+    #[test]
+    fn it_filters_allowed_amounts_files_by_billing_code() {
+        let bytes = fs::read("./tests/fixtures/allowed-amounts-sample.json").unwrap();
+
+        let all: AllowedAmountsFile = serde_json::from_slice(&bytes).unwrap();
+        assert_eq!(all.out_of_network.len(), 2);
+        let payment = &all.out_of_network[0].allowed_amounts[0].payments[0];
+        assert_eq!(payment.providers[1].billed_charge.as_f64(), Some(28000.5));
+
+        let filtered =
+            get_filtered_allowed_amounts_file(&bytes, &NodeFilters::new(vec!["99213".to_string()]));
+        let filtered: AllowedAmountsFile = serde_json::from_str(&filtered).unwrap();
+        assert_eq!(filtered.plan_id.as_deref(), Some("1111111111"));
+        let billing_codes: Vec<&str> = filtered
+            .out_of_network
+            .iter()
+            .map(|o| o.billing_code.as_str())
+            .collect();
+        assert_eq!(billing_codes, vec!["99213"]);
+    }
+}
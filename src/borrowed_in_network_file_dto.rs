@@ -0,0 +1,219 @@
+use std::borrow::Cow;
+
+use serde::{Deserialize, Serialize};
+use serde_json::Number;
+use serde_with::{serde_as, BorrowCow};
+
+use crate::in_network_file_dto::{
+    BillingClass, CoveredService, InNetworkRateObject, NegotiatedPrice, NegotiatedRate,
+    ProviderGroup, Tin, TinType,
+};
+use crate::node_filters::BillingCoded;
+
+// like the dtos in `in_network_file_dto`, but their strings borrow from the json they're read from
+// when it's a slice, e.g. a memory mapped file, rather than each being allocated.
+// strings with escapes still have to be allocated, hence `Cow`s rather than `&str`s.
+// the enumerated fields are kept as strings too, `From` turns everything into the owned dtos.
+
+#[derive(Deserialize, Debug, Serialize)]
+pub struct BorrowedInNetworkRateObject<'a> {
+    #[serde(borrow)]
+    pub negotiation_arrangement: Cow<'a, str>,
+    #[serde(borrow)]
+    pub name: Cow<'a, str>,
+    #[serde(borrow)]
+    pub billing_code_type: Cow<'a, str>,
+    #[serde(borrow)]
+    pub billing_code_type_version: Cow<'a, str>,
+    #[serde(borrow)]
+    pub billing_code: Cow<'a, str>,
+    #[serde(borrow)]
+    pub negotiated_rates: Vec<BorrowedNegotiatedRate<'a>>,
+    #[serde(borrow)]
+    pub description: Cow<'a, str>,
+    #[serde(borrow)]
+    pub bundled_codes: Option<Vec<BorrowedCoveredService<'a>>>,
+    #[serde(borrow)]
+    pub covered_services: Option<Vec<BorrowedCoveredService<'a>>>,
+}
+
+#[derive(Deserialize, Debug, Serialize)]
+pub struct BorrowedNegotiatedRate<'a> {
+    #[serde(borrow)]
+    pub negotiated_prices: Vec<BorrowedNegotiatedPrice<'a>>,
+    #[serde(borrow)]
+    pub provider_groups: Option<Vec<BorrowedProviderGroup<'a>>>,
+    pub provider_references: Option<Vec<Number>>,
+}
+
+#[serde_as]
+#[derive(Deserialize, Debug, Serialize)]
+pub struct BorrowedNegotiatedPrice<'a> {
+    pub negotiated_rate: Number,
+    #[serde(borrow)]
+    pub negotiated_type: Cow<'a, str>,
+    #[serde(borrow)]
+    pub expiration_date: Cow<'a, str>,
+    #[serde_as(as = "Option<Vec<BorrowCow>>")]
+    pub service_code: Option<Vec<Cow<'a, str>>>,
+    pub billing_class: BillingClass,
+    #[serde_as(as = "Option<Vec<BorrowCow>>")]
+    pub billing_code_modifier: Option<Vec<Cow<'a, str>>>,
+    #[serde_as(as = "Option<BorrowCow>")]
+    pub additional_information: Option<Cow<'a, str>>,
+}
+
+#[derive(Deserialize, Debug, Serialize)]
+pub struct BorrowedProviderGroup<'a> {
+    pub npi: Vec<Number>,
+    #[serde(borrow)]
+    pub tin: BorrowedTin<'a>,
+}
+
+#[derive(Deserialize, Debug, Serialize)]
+pub struct BorrowedTin<'a> {
+    #[serde(rename = "type")]
+    pub type_: TinType,
+    #[serde(borrow)]
+    pub value: Cow<'a, str>,
+}
+
+#[derive(Deserialize, Debug, Serialize)]
+pub struct BorrowedCoveredService<'a> {
+    #[serde(borrow)]
+    pub billing_code: Cow<'a, str>,
+    #[serde(borrow)]
+    pub billing_code_type: Cow<'a, str>,
+    #[serde(borrow)]
+    pub billing_code_type_version: Cow<'a, str>,
+    #[serde(borrow)]
+    pub description: Cow<'a, str>,
+}
+
+impl BillingCoded for BorrowedInNetworkRateObject<'_> {
+    fn billing_code(&self) -> &str {
+        &self.billing_code
+    }
+}
+
+impl From<BorrowedInNetworkRateObject<'_>> for InNetworkRateObject {
+    fn from(o: BorrowedInNetworkRateObject<'_>) -> Self {
+        InNetworkRateObject {
+            negotiation_arrangement: o.negotiation_arrangement.into_owned().into(),
+            name: o.name.into_owned(),
+            billing_code_type: o.billing_code_type.into_owned().into(),
+            billing_code_type_version: o.billing_code_type_version.into_owned(),
+            billing_code: o.billing_code.into_owned(),
+            negotiated_rates: o.negotiated_rates.into_iter().map(Into::into).collect(),
+            description: o.description.into_owned(),
+            bundled_codes: o
+                .bundled_codes
+                .map(|codes| codes.into_iter().map(Into::into).collect()),
+            covered_services: o
+                .covered_services
+                .map(|services| services.into_iter().map(Into::into).collect()),
+        }
+    }
+}
+
+impl From<BorrowedNegotiatedRate<'_>> for NegotiatedRate {
+    fn from(r: BorrowedNegotiatedRate<'_>) -> Self {
+        NegotiatedRate {
+            negotiated_prices: r.negotiated_prices.into_iter().map(Into::into).collect(),
+            provider_groups: r
+                .provider_groups
+                .map(|groups| groups.into_iter().map(Into::into).collect()),
+            provider_references: r.provider_references,
+        }
+    }
+}
+
+impl From<BorrowedNegotiatedPrice<'_>> for NegotiatedPrice {
+    fn from(p: BorrowedNegotiatedPrice<'_>) -> Self {
+        NegotiatedPrice {
+            negotiated_rate: p.negotiated_rate,
+            negotiated_type: p.negotiated_type.into_owned().into(),
+            expiration_date: p.expiration_date.into_owned(),
+            service_code: p.service_code.map(into_owned_strings),
+            billing_class: p.billing_class,
+            billing_code_modifier: p.billing_code_modifier.map(into_owned_strings),
+            additional_information: p.additional_information.map(Cow::into_owned),
+        }
+    }
+}
+
+impl From<BorrowedProviderGroup<'_>> for ProviderGroup {
+    fn from(g: BorrowedProviderGroup<'_>) -> Self {
+        ProviderGroup {
+            npi: g.npi,
+            tin: Tin {
+                type_: g.tin.type_,
+                value: g.tin.value.into_owned(),
+            },
+        }
+    }
+}
+
+impl From<BorrowedCoveredService<'_>> for CoveredService {
+    fn from(s: BorrowedCoveredService<'_>) -> Self {
+        CoveredService {
+            billing_code: s.billing_code.into_owned(),
+            billing_code_type: s.billing_code_type.into_owned().into(),
+            billing_code_type_version: s.billing_code_type_version.into_owned(),
+            description: s.description.into_owned(),
+        }
+    }
+}
+
+fn into_owned_strings(strings: Vec<Cow<'_, str>>) -> Vec<String> {
+    strings.into_iter().map(Cow::into_owned).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use super::*;
+    use crate::{
+        deserialize_filtered_borrowed_rate_objects, in_network_file_dto::BillingCodeType,
+        node_filters::NodeFilters,
+    };
+
+    #[test]
+    fn it_borrows_rate_objects_from_slices() {
+        let bytes = fs::read("./tests/fixtures/in-network-rates-sample.json").unwrap();
+
+        let all =
+            deserialize_filtered_borrowed_rate_objects(&bytes, &NodeFilters::new(vec![])).unwrap();
+        assert_eq!(all.len(), 3);
+        assert!(matches!(all[0].billing_code, Cow::Borrowed("27447")));
+        let price = &all[1].negotiated_rates[0].negotiated_prices[0];
+        assert!(matches!(price.expiration_date, Cow::Borrowed("2022-12-31")));
+        assert!(matches!(
+            price.service_code.as_ref().unwrap()[0],
+            Cow::Borrowed("11")
+        ));
+
+        let owned: InNetworkRateObject = all.into_iter().nth(1).unwrap().into();
+        assert_eq!(owned.billing_code, "99213");
+        assert_eq!(owned.billing_code_type, BillingCodeType::Cpt);
+        assert_eq!(
+            owned.negotiated_rates[0].negotiated_prices[0].service_code,
+            Some(vec!["11".to_string()])
+        );
+
+        let filters = NodeFilters::new(vec!["42820".to_string()]);
+        let filtered = deserialize_filtered_borrowed_rate_objects(&bytes, &filters).unwrap();
+        assert_eq!(filtered.len(), 1);
+        // still borrowed, both the fields read before the billing code matched and those after
+        assert!(matches!(filtered[0].billing_code, Cow::Borrowed("42820")));
+        assert!(matches!(
+            filtered[0].billing_code_type,
+            Cow::Borrowed("CPT")
+        ));
+        assert!(matches!(filtered[0].name, Cow::Borrowed(_)));
+        assert!(matches!(filtered[0].description, Cow::Borrowed(_)));
+        let price = &filtered[0].negotiated_rates[0].negotiated_prices[0];
+        assert!(matches!(price.negotiated_type, Cow::Borrowed(_)));
+    }
+}
@@ -0,0 +1,391 @@
+use std::{
+    error::Error,
+    fmt,
+    fs::{self, File},
+    io::{self, BufWriter, Read},
+    path::{Path, PathBuf},
+    thread,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use serde::Serialize;
+use sha2::{Digest, Sha256};
+
+use crate::{
+    download,
+    filtered_in_network_file::deserialize_filtered_in_network_file,
+    index_file_parsing::{
+        self,
+        meta_repository_trait::{FileRow, FileState, FileStatus, FileType, MetaRepository},
+    },
+    location::{self, LocationError},
+    node_filters::NodeFilters,
+};
+
+pub const MANIFEST_FILENAME: &str = "manifest.json";
+
+/// lists what a crawl wrote for each in-network file, and which plans the file is for.
+/// written to `manifest.json` in the output dir.
+#[derive(Serialize, Debug)]
+pub struct CrawlManifest {
+    pub index_file_id: usize,
+    pub index_file_url: String,
+    pub files: Vec<CrawledFile>,
+}
+
+#[derive(Serialize, Debug)]
+pub struct CrawledFile {
+    pub file_id: usize,
+    pub url: String,
+    /// ids of the file's plans in the `MetaRepository`
+    pub plan_ids: Vec<usize>,
+    /// `Parsed`, or `Failed` once every attempt has
+    pub status: FileStatus,
+    /// the filtered file, relative to the output dir. `None` when it couldn't be crawled.
+    pub output: Option<PathBuf>,
+    /// `None` when the file was parsed by an earlier crawl, and skipped by this one
+    pub num_rate_objects: Option<usize>,
+    pub error: Option<String>,
+    /// whether it failed every attempt, so later crawls won't try it again
+    /// unless `CrawlOptions::retry_exhausted` is set
+    pub exhausted: bool,
+}
+
+pub struct CrawlOptions {
+    /// how many times to try each file before recording it as failed,
+    /// counting attempts by earlier crawls. a file that's used them all isn't tried again.
+    pub max_attempts: u32,
+    /// how long to wait before the first retry, doubling before each one after that.
+    /// timed from the file's last attempt, even when an earlier crawl made it.
+    pub retry_backoff: Duration,
+    /// whether to try files that used every attempt in earlier crawls again,
+    /// starting their attempts over
+    pub retry_exhausted: bool,
+}
+
+impl Default for CrawlOptions {
+    fn default() -> Self {
+        CrawlOptions {
+            max_attempts: 3,
+            retry_backoff: Duration::from_secs(1),
+            retry_exhausted: false,
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum CrawlError {
+    Location(LocationError),
+    Io(io::Error),
+    Json(serde_json::Error),
+}
+
+impl fmt::Display for CrawlError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            CrawlError::Location(e) => write!(f, "{e}"),
+            CrawlError::Io(e) => write!(f, "{e}"),
+            CrawlError::Json(e) => write!(f, "{e}"),
+        }
+    }
+}
+
+impl Error for CrawlError {}
+
+impl From<LocationError> for CrawlError {
+    fn from(e: LocationError) -> Self {
+        CrawlError::Location(e)
+    }
+}
+
+impl From<io::Error> for CrawlError {
+    fn from(e: io::Error) -> Self {
+        CrawlError::Io(e)
+    }
+}
+
+impl From<serde_json::Error> for CrawlError {
+    fn from(e: serde_json::Error) -> Self {
+        CrawlError::Json(e)
+    }
+}
+
+/// parses the index file at `location` into `repo`, then downloads each in-network file it
+/// lists, once per url, keeping only the rate objects matching `filters`.
+/// each filtered file is written to `output_dir` as its `download::output_filename`,
+/// next to a manifest.
+/// a file that fails is recorded in the manifest with its error, and the crawl carries on.
+pub fn crawl_index_file(
+    location: impl AsRef<str>,
+    repo: &mut dyn MetaRepository,
+    filters: &NodeFilters,
+    output_dir: impl AsRef<Path>,
+) -> Result<CrawlManifest, CrawlError> {
+    crawl_index_file_with_options(
+        location,
+        repo,
+        filters,
+        output_dir,
+        &CrawlOptions::default(),
+    )
+}
+
+/// like `crawl_index_file`, but with control over retries.
+/// each file's progress is recorded with `MetaRepository::set_file_state`, so crawling
+/// the same index file again skips files that were parsed, and retries the rest.
+pub fn crawl_index_file_with_options(
+    location: impl AsRef<str>,
+    repo: &mut dyn MetaRepository,
+    filters: &NodeFilters,
+    output_dir: impl AsRef<Path>,
+    options: &CrawlOptions,
+) -> Result<CrawlManifest, CrawlError> {
+    let output_dir = output_dir.as_ref();
+    fs::create_dir_all(output_dir)?;
+
+    let results = index_file_parsing::parse_index_file(location.as_ref(), repo)?;
+    let mut manifest = CrawlManifest {
+        index_file_id: results.index_file_id,
+        index_file_url: location.as_ref().to_string(),
+        files: vec![],
+    };
+
+    // files shared between reporting structures are only stored, and so crawled, once
+    for file in repo.find_files_for_index_file(results.index_file_id) {
+        if file.file_type != FileType::InNetwork {
+            continue;
+        }
+        let plan_ids = repo
+            .find_plans_for_file(file.id)
+            .iter()
+            .map(|plan| plan.id)
+            .collect();
+        let output = PathBuf::from(download::output_filename(&file));
+        let crawled = crawl_file_with_retries(repo, &file, filters, output_dir, &output, options);
+        manifest.files.push(CrawledFile {
+            file_id: file.id,
+            url: file.url,
+            plan_ids,
+            status: crawled.status,
+            output: (crawled.status == FileStatus::Parsed).then_some(output),
+            num_rate_objects: crawled.num_rate_objects,
+            error: crawled.error,
+            exhausted: crawled.exhausted,
+        });
+    }
+
+    let manifest_file = BufWriter::new(File::create(output_dir.join(MANIFEST_FILENAME))?);
+    serde_json::to_writer_pretty(manifest_file, &manifest)?;
+    Ok(manifest)
+}
+
+struct CrawlOutcome {
+    status: FileStatus,
+    num_rate_objects: Option<usize>,
+    error: Option<String>,
+    exhausted: bool,
+}
+
+fn crawl_file_with_retries(
+    repo: &mut dyn MetaRepository,
+    file: &FileRow,
+    filters: &NodeFilters,
+    output_dir: &Path,
+    output: &Path,
+    options: &CrawlOptions,
+) -> CrawlOutcome {
+    let mut state = repo
+        .get_file_state(file.id)
+        .unwrap_or_else(|| FileState::pending(file.id));
+    if state.status == FileStatus::Parsed {
+        if output_dir.join(output).exists() {
+            println!("skipping {}, already parsed", file.url);
+            return CrawlOutcome {
+                status: FileStatus::Parsed,
+                num_rate_objects: None,
+                error: None,
+                exhausted: false,
+            };
+        }
+        // its output is gone, so it's crawled again with attempts to spare
+        state = FileState::pending(file.id);
+    }
+    if state.attempts >= options.max_attempts && options.retry_exhausted {
+        println!(
+            "retrying {}, which failed {} times",
+            file.url, state.attempts
+        );
+        state.attempts = 0;
+    } else if state.attempts >= options.max_attempts {
+        println!(
+            "skipping {}, which failed all {} attempts. set retry_exhausted to try it again",
+            file.url, state.attempts
+        );
+    }
+
+    while state.attempts < options.max_attempts {
+        wait_to_retry(&state, options);
+        state.status = FileStatus::Downloading;
+        state.attempts += 1;
+        state.last_attempt_at_ms = Some(now_ms());
+        state.bytes_processed = 0;
+        repo.set_file_state(&state);
+        // states are committed as they change, so they survive the crawl being interrupted
+        repo.commit();
+
+        let mut record_progress = |bytes| {
+            state.bytes_processed = bytes;
+            repo.set_file_state(&state);
+            repo.commit();
+        };
+        match crawl_file(
+            file,
+            filters,
+            &output_dir.join(output),
+            &mut record_progress,
+        ) {
+            Ok(crawled) => {
+                state.status = FileStatus::Parsed;
+                state.error = None;
+                state.bytes_processed = crawled.bytes;
+                state.content_hash = Some(crawled.content_hash);
+                state.etag = crawled.etag;
+                repo.set_file_state(&state);
+                repo.mark_file_processed(file.id);
+                repo.commit();
+                return CrawlOutcome {
+                    status: FileStatus::Parsed,
+                    num_rate_objects: Some(crawled.num_rate_objects),
+                    error: None,
+                    exhausted: false,
+                };
+            }
+            Err(e) => {
+                println!("failed to crawl {}: {e}", file.url);
+                state.status = FileStatus::Failed;
+                state.error = Some(e.to_string());
+                repo.set_file_state(&state);
+                repo.commit();
+            }
+        }
+    }
+    CrawlOutcome {
+        status: FileStatus::Failed,
+        num_rate_objects: None,
+        error: state.error,
+        exhausted: true,
+    }
+}
+
+// sleeps out the backoff after the file's last attempt, if it's been tried before
+fn wait_to_retry(state: &FileState, options: &CrawlOptions) {
+    let (Some(last_attempt_at_ms), Some(retries)) =
+        (state.last_attempt_at_ms, state.attempts.checked_sub(1))
+    else {
+        return;
+    };
+    let backoff = download::backoff(options.retry_backoff, retries);
+    let retry_at_ms = last_attempt_at_ms.saturating_add(backoff.as_millis() as u64);
+    if let Some(wait_ms) = retry_at_ms.checked_sub(now_ms()) {
+        thread::sleep(Duration::from_millis(wait_ms));
+    }
+}
+
+fn now_ms() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .expect("the clock to be after the unix epoch")
+        .as_millis() as u64
+}
+
+struct CrawledBytes {
+    num_rate_objects: usize,
+    bytes: u64,
+    content_hash: String,
+    etag: Option<String>,
+}
+
+// appended to an output file's whole name while it's written, e.g. `rates.json.crawl.partial`.
+// it differs from a download's, as both may write to the same dir
+const PARTIAL_SUFFIX: &str = ".crawl.partial";
+
+// writes the filtered file to `output_path`.
+// it's written next to it first, so a failed attempt doesn't leave a partial file behind.
+// `on_progress` is given how many bytes have been read every so often,
+// and once more when reading stops, even if it failed partway.
+fn crawl_file(
+    file: &FileRow,
+    filters: &NodeFilters,
+    output_path: &Path,
+    on_progress: &mut dyn FnMut(u64),
+) -> Result<CrawledBytes, CrawlError> {
+    println!("crawling {}", file.url);
+    let (reader, etag) = location::open_location(&file.url)?;
+    let mut reader = HashingReader::new(reader, on_progress);
+    let in_network_file = deserialize_filtered_in_network_file(&mut reader, filters);
+    reader.report_progress();
+    let in_network_file = in_network_file?;
+
+    let partial_path = download::with_suffix(output_path, PARTIAL_SUFFIX);
+    serde_json::to_writer(
+        BufWriter::new(File::create(&partial_path)?),
+        &in_network_file,
+    )?;
+    fs::rename(&partial_path, output_path)?;
+    Ok(CrawledBytes {
+        num_rate_objects: in_network_file.in_network.len(),
+        bytes: reader.bytes,
+        content_hash: reader.hex_digest(),
+        etag,
+    })
+}
+
+// how many bytes are read between reports of a file's progress
+const PROGRESS_INTERVAL_BYTES: u64 = 64 * 1024 * 1024;
+
+// counts and hashes everything read through it, reporting the count every so often
+struct HashingReader<'p, R> {
+    inner: R,
+    hasher: Sha256,
+    bytes: u64,
+    reported: u64,
+    on_progress: &'p mut dyn FnMut(u64),
+}
+
+impl<'p, R: Read> HashingReader<'p, R> {
+    fn new(inner: R, on_progress: &'p mut dyn FnMut(u64)) -> Self {
+        HashingReader {
+            inner,
+            hasher: Sha256::new(),
+            bytes: 0,
+            reported: 0,
+            on_progress,
+        }
+    }
+
+    fn report_progress(&mut self) {
+        self.reported = self.bytes;
+        (self.on_progress)(self.bytes);
+    }
+
+    fn hex_digest(&self) -> String {
+        self.hasher
+            .clone()
+            .finalize()
+            .iter()
+            .map(|byte| format!("{byte:02x}"))
+            .collect()
+    }
+}
+
+impl<R: Read> Read for HashingReader<'_, R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let n = self.inner.read(buf)?;
+        self.hasher.update(&buf[..n]);
+        self.bytes += n as u64;
+        if self.bytes - self.reported >= PROGRESS_INTERVAL_BYTES {
+            self.report_progress();
+        }
+        Ok(n)
+    }
+}
@@ -0,0 +1,569 @@
+use std::{
+    collections::HashMap,
+    error::Error,
+    fmt,
+    fs::{self, File, OpenOptions},
+    io::{self, BufWriter, Read, Write},
+    path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Condvar, Mutex,
+    },
+    thread,
+    time::Duration,
+};
+
+use sha2::{Digest, Sha256};
+use ureq::{http::Uri, Agent};
+
+use crate::index_file_parsing::meta_repository_trait::FileRow;
+
+pub struct DownloadOptions {
+    /// how many files to download at once, across every host
+    pub max_connections: usize,
+    /// how many files to download at once from any one host
+    pub max_per_host: usize,
+    /// how many times to try each file before giving up on it
+    pub max_attempts: u32,
+    /// how long to wait before the first retry, doubling before each one after that
+    pub retry_backoff: Duration,
+}
+
+impl Default for DownloadOptions {
+    fn default() -> Self {
+        DownloadOptions {
+            max_connections: 8,
+            max_per_host: 2,
+            max_attempts: 5,
+            retry_backoff: Duration::from_secs(1),
+        }
+    }
+}
+
+/// a file to download from `url` to `path`.
+/// the size and sha256 are checked once it's downloaded, when they're known beforehand.
+#[derive(Debug, Clone)]
+pub struct DownloadRequest {
+    pub url: String,
+    pub path: PathBuf,
+    pub expected_size: Option<u64>,
+    pub expected_sha256: Option<String>,
+}
+
+impl DownloadRequest {
+    pub fn new(url: impl Into<String>, path: impl Into<PathBuf>) -> Self {
+        DownloadRequest {
+            url: url.into(),
+            path: path.into(),
+            expected_size: None,
+            expected_sha256: None,
+        }
+    }
+
+    /// a linked file, downloaded into `dir` as its `output_filename`
+    pub fn for_file(file: &FileRow, dir: impl AsRef<Path>) -> Self {
+        let path = dir.as_ref().join(output_filename(file));
+        DownloadRequest::new(&file.url, path)
+    }
+}
+
+// the longest filename most filesystems allow, in bytes
+const MAX_FILENAME_LEN: usize = 255;
+
+/// what a linked file is written as: `<file id>-<filename>`, with the filename taken from
+/// its url made safe to write. query strings (e.g. a presigned url's signature) and
+/// fragments are dropped, escapes decoded, and characters paths can't hold replaced with `_`.
+/// too long names are shortened, keeping their extension, and `<file id>.json` is used
+/// when nothing's left of the filename.
+pub fn output_filename(file: &FileRow) -> String {
+    let filename = file.filename.split(['?', '#']).next().unwrap_or_default();
+    let filename: String = percent_decode(filename)
+        .chars()
+        .map(|c| match c {
+            '/' | '\\' | ':' | '*' | '?' | '"' | '<' | '>' | '|' => '_',
+            c if c.is_control() => '_',
+            c => c,
+        })
+        .collect();
+    let filename = filename.trim();
+    if filename.trim_matches('.').is_empty() {
+        return format!("{}.json", file.id);
+    }
+
+    let name = format!("{}-{filename}", file.id);
+    if name.len() <= MAX_FILENAME_LEN {
+        return name;
+    }
+    let (stem, extension) = match name.rsplit_once('.') {
+        Some((stem, extension)) if extension.len() < 16 => (stem, format!(".{extension}")),
+        _ => (name.as_str(), String::new()),
+    };
+    let mut end = MAX_FILENAME_LEN - extension.len();
+    while !stem.is_char_boundary(end) {
+        end -= 1;
+    }
+    format!("{}{extension}", &stem[..end])
+}
+
+// decodes `%xx` escapes, leaving malformed ones as they are
+fn percent_decode(s: &str) -> String {
+    let bytes = s.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        let escaped = bytes
+            .get(i + 1..i + 3)
+            .filter(|_| bytes[i] == b'%')
+            .and_then(|hex| u8::from_str_radix(std::str::from_utf8(hex).ok()?, 16).ok());
+        match escaped {
+            Some(byte) => {
+                decoded.push(byte);
+                i += 3;
+            }
+            None => {
+                decoded.push(bytes[i]);
+                i += 1;
+            }
+        }
+    }
+    String::from_utf8_lossy(&decoded).into_owned()
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Download {
+    pub url: String,
+    pub path: PathBuf,
+    pub bytes: u64,
+    pub sha256: String,
+    pub etag: Option<String>,
+    pub attempts: u32,
+    /// how many bytes were already on disk from earlier attempts when the last one started
+    pub resumed_from: u64,
+}
+
+#[derive(Debug)]
+pub enum DownloadError {
+    Http(Box<ureq::Error>),
+    Io(io::Error),
+    InvalidUrl(String),
+    SizeMismatch { expected: u64, actual: u64 },
+    ChecksumMismatch { expected: String, actual: String },
+}
+
+impl DownloadError {
+    // whether another attempt might succeed
+    fn is_transient(&self) -> bool {
+        match self {
+            DownloadError::Http(e) => match **e {
+                // a range past the end of the file means the partial file is bad, so it's
+                // dropped and downloaded again
+                ureq::Error::StatusCode(status) => {
+                    status >= 500 || status == 408 || status == 416 || status == 429
+                }
+                _ => true,
+            },
+            DownloadError::Io(_) => true,
+            DownloadError::InvalidUrl(_) => false,
+            DownloadError::SizeMismatch { .. } | DownloadError::ChecksumMismatch { .. } => true,
+        }
+    }
+}
+
+impl fmt::Display for DownloadError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            DownloadError::Http(e) => write!(f, "{e}"),
+            DownloadError::Io(e) => write!(f, "{e}"),
+            DownloadError::InvalidUrl(url) => write!(f, "can't download {url}, not an http(s) url"),
+            DownloadError::SizeMismatch { expected, actual } => {
+                write!(f, "expected {expected} bytes, downloaded {actual}")
+            }
+            DownloadError::ChecksumMismatch { expected, actual } => {
+                write!(f, "expected sha256 {expected}, downloaded {actual}")
+            }
+        }
+    }
+}
+
+impl Error for DownloadError {}
+
+impl From<ureq::Error> for DownloadError {
+    fn from(e: ureq::Error) -> Self {
+        DownloadError::Http(Box::new(e))
+    }
+}
+
+impl From<io::Error> for DownloadError {
+    fn from(e: io::Error) -> Self {
+        DownloadError::Io(e)
+    }
+}
+
+// appended to a file's whole name, e.g. `rates.json.partial`, while it's downloaded
+const PARTIAL_SUFFIX: &str = ".partial";
+
+/// downloads files over http(s), a few at a time.
+/// a file is written next to its path as `<name>.partial` until it's complete and verified,
+/// and a failed attempt is resumed from where it stopped with a `Range` request, as long as the
+/// file's etag hasn't changed. partial files are kept when every attempt fails, so downloading
+/// the same file later carries on from them too.
+pub struct DownloadManager {
+    agent: Agent,
+    options: DownloadOptions,
+    hosts: HostLimits,
+}
+
+impl DownloadManager {
+    pub fn new(options: DownloadOptions) -> Self {
+        DownloadManager {
+            agent: Agent::new_with_defaults(),
+            hosts: HostLimits::new(options.max_per_host),
+            options,
+        }
+    }
+
+    /// downloads every request, returning each one's result in the same order
+    pub fn download_all(
+        &self,
+        requests: &[DownloadRequest],
+    ) -> Vec<Result<Download, DownloadError>> {
+        let next = AtomicUsize::new(0);
+        let results: Mutex<Vec<Option<Result<Download, DownloadError>>>> =
+            Mutex::new(requests.iter().map(|_| None).collect());
+
+        let workers = self.options.max_connections.clamp(1, requests.len().max(1));
+        thread::scope(|scope| {
+            for _ in 0..workers {
+                scope.spawn(|| loop {
+                    let i = next.fetch_add(1, Ordering::SeqCst);
+                    let Some(request) = requests.get(i) else {
+                        break;
+                    };
+                    let result = self.download(request);
+                    results.lock().unwrap()[i] = Some(result);
+                });
+            }
+        });
+
+        results
+            .into_inner()
+            .unwrap()
+            .into_iter()
+            .map(|result| result.expect("every request to be downloaded"))
+            .collect()
+    }
+
+    /// downloads one request, retrying with backoff while its errors look transient
+    pub fn download(&self, request: &DownloadRequest) -> Result<Download, DownloadError> {
+        let host = host(&request.url)?;
+        if let Some(dir) = request.path.parent() {
+            fs::create_dir_all(dir)?;
+        }
+
+        let mut attempt = 0;
+        loop {
+            attempt += 1;
+            let result = {
+                let _permit = self.hosts.acquire(&host);
+                self.try_download(request)
+            };
+            match result {
+                Ok(mut download) => {
+                    download.attempts = attempt;
+                    return Ok(download);
+                }
+                Err(e) if e.is_transient() && attempt < self.options.max_attempts => {
+                    println!("retrying {} after attempt {attempt}: {e}", request.url);
+                    thread::sleep(backoff(self.options.retry_backoff, attempt - 1));
+                }
+                Err(e) => return Err(e),
+            }
+        }
+    }
+
+    fn try_download(&self, request: &DownloadRequest) -> Result<Download, DownloadError> {
+        let partial_path = with_suffix(&request.path, PARTIAL_SUFFIX);
+        let etag_path = with_suffix(&partial_path, ".etag");
+        let resumed_from = fs::metadata(&partial_path).map_or(0, |meta| meta.len());
+
+        // ranges count encoded bytes, so the body is asked for as is
+        let mut get = self
+            .agent
+            .get(&request.url)
+            .header("Accept-Encoding", "identity");
+        if resumed_from > 0 {
+            get = get.header("Range", format!("bytes={resumed_from}-"));
+            // without the etag the file was first downloaded with, a changed file can't be told apart
+            if let Ok(etag) = fs::read_to_string(&etag_path) {
+                get = get.header("If-Range", etag);
+            }
+        }
+        let response = match get.call() {
+            Err(ureq::Error::StatusCode(416)) => {
+                remove_partial(&partial_path, &etag_path)?;
+                return Err(ureq::Error::StatusCode(416).into());
+            }
+            response => response?,
+        };
+
+        let etag = response
+            .headers()
+            .get("etag")
+            .and_then(|etag| etag.to_str().ok())
+            .map(str::to_string);
+        let resuming = response.status().as_u16() == 206;
+        let total_size = if resuming {
+            header(&response, "content-range")
+                .and_then(|range| range.rsplit_once('/')?.1.parse().ok())
+        } else {
+            header(&response, "content-length").and_then(|len| len.parse().ok())
+        };
+
+        let mut hasher = Sha256::new();
+        let mut partial = if resuming {
+            // the bytes already on disk are hashed before the rest is appended
+            io::copy(
+                &mut File::open(&partial_path)?,
+                &mut HashWriter(&mut hasher),
+            )?;
+            OpenOptions::new().append(true).open(&partial_path)?
+        } else {
+            match &etag {
+                Some(etag) => fs::write(&etag_path, etag)?,
+                None => remove_if_exists(&etag_path)?,
+            }
+            File::create(&partial_path)?
+        };
+
+        let mut body = response.into_body().into_reader();
+        let mut writer = BufWriter::new(&mut partial);
+        let mut buf = vec![0; 64 * 1024];
+        loop {
+            let n = body.read(&mut buf)?;
+            if n == 0 {
+                break;
+            }
+            hasher.update(&buf[..n]);
+            writer.write_all(&buf[..n])?;
+        }
+        writer.flush()?;
+        drop(writer);
+
+        let bytes = partial.metadata()?.len();
+        let sha256 = hex(hasher.finalize().as_slice());
+        if let Err(e) = verify(request, total_size, bytes, &sha256) {
+            // the bytes on disk can't be trusted anymore, so the next attempt starts over
+            remove_partial(&partial_path, &etag_path)?;
+            return Err(e);
+        }
+
+        fs::rename(&partial_path, &request.path)?;
+        remove_if_exists(&etag_path)?;
+        Ok(Download {
+            url: request.url.clone(),
+            path: request.path.clone(),
+            bytes,
+            sha256,
+            etag,
+            attempts: 1,
+            resumed_from: if resuming { resumed_from } else { 0 },
+        })
+    }
+}
+
+fn verify(
+    request: &DownloadRequest,
+    total_size: Option<u64>,
+    bytes: u64,
+    sha256: &str,
+) -> Result<(), DownloadError> {
+    if let Some(expected) = request.expected_size.or(total_size) {
+        if expected != bytes {
+            return Err(DownloadError::SizeMismatch {
+                expected,
+                actual: bytes,
+            });
+        }
+    }
+    if let Some(expected) = &request.expected_sha256 {
+        if !expected.eq_ignore_ascii_case(sha256) {
+            return Err(DownloadError::ChecksumMismatch {
+                expected: expected.clone(),
+                actual: sha256.to_string(),
+            });
+        }
+    }
+    Ok(())
+}
+
+// a url's host and port, which downloads are limited by
+// the path with `suffix` appended to its whole file name, keeping any extension
+pub(crate) fn with_suffix(path: &Path, suffix: &str) -> PathBuf {
+    let mut path = path.as_os_str().to_owned();
+    path.push(suffix);
+    PathBuf::from(path)
+}
+
+// how long to wait before the next attempt, doubling with each retry so far
+pub(crate) fn backoff(retry_backoff: Duration, retries: u32) -> Duration {
+    retry_backoff.saturating_mul(2u32.saturating_pow(retries))
+}
+
+fn host(url: &str) -> Result<String, DownloadError> {
+    url.parse::<Uri>()
+        .ok()
+        .filter(|uri| matches!(uri.scheme_str(), Some("http" | "https")))
+        .and_then(|uri| uri.authority().map(|authority| authority.to_string()))
+        .ok_or_else(|| DownloadError::InvalidUrl(url.to_string()))
+}
+
+fn header<'a>(response: &'a ureq::http::Response<ureq::Body>, name: &str) -> Option<&'a str> {
+    response.headers().get(name)?.to_str().ok()
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{byte:02x}")).collect()
+}
+
+fn remove_if_exists(path: &Path) -> io::Result<()> {
+    match fs::remove_file(path) {
+        Err(e) if e.kind() != io::ErrorKind::NotFound => Err(e),
+        _ => Ok(()),
+    }
+}
+
+fn remove_partial(partial_path: &Path, etag_path: &Path) -> io::Result<()> {
+    remove_if_exists(partial_path)?;
+    remove_if_exists(etag_path)
+}
+
+struct HashWriter<'a>(&'a mut Sha256);
+
+impl Write for HashWriter<'_> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.0.update(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+// how many downloads each host has going, so no more than `max_per_host` start at once
+struct HostLimits {
+    max_per_host: usize,
+    active: Mutex<HashMap<String, usize>>,
+    freed: Condvar,
+}
+
+struct HostPermit<'a> {
+    limits: &'a HostLimits,
+    host: String,
+}
+
+impl HostLimits {
+    fn new(max_per_host: usize) -> Self {
+        HostLimits {
+            max_per_host: max_per_host.max(1),
+            active: Mutex::default(),
+            freed: Condvar::new(),
+        }
+    }
+
+    fn acquire(&self, host: &str) -> HostPermit<'_> {
+        let mut active = self.active.lock().unwrap();
+        while *active.get(host).unwrap_or(&0) >= self.max_per_host {
+            active = self.freed.wait(active).unwrap();
+        }
+        *active.entry(host.to_string()).or_insert(0) += 1;
+        HostPermit {
+            limits: self,
+            host: host.to_string(),
+        }
+    }
+}
+
+impl Drop for HostPermit<'_> {
+    fn drop(&mut self) {
+        let mut active = self.limits.active.lock().unwrap();
+        if let Some(count) = active.get_mut(&self.host) {
+            *count -= 1;
+        }
+        self.limits.freed.notify_all();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn it_appends_suffixes_to_whole_file_names() {
+        assert_eq!(
+            with_suffix(Path::new("out/rates.json"), PARTIAL_SUFFIX),
+            Path::new("out/rates.json.partial")
+        );
+        assert_eq!(
+            with_suffix(Path::new("out/rates"), PARTIAL_SUFFIX),
+            Path::new("out/rates.partial")
+        );
+    }
+
+    #[test]
+    fn it_doubles_the_backoff_without_overflowing() {
+        let second = Duration::from_secs(1);
+        assert_eq!(backoff(second, 0), second);
+        assert_eq!(backoff(second, 3), second * 8);
+        assert_eq!(backoff(second, 40), second * u32::MAX);
+        assert_eq!(backoff(Duration::MAX, 40), Duration::MAX);
+    }
+
+    #[test]
+    fn it_keys_hosts_by_host_and_port() {
+        assert_eq!(
+            host("https://example.com/in-network/a.json").unwrap(),
+            "example.com"
+        );
+        assert_eq!(
+            host("http://127.0.0.1:8080/a.json").unwrap(),
+            "127.0.0.1:8080"
+        );
+        assert!(host("./tests/fixtures/in-network-rates-sample.json").is_err());
+    }
+
+    fn file_named(filename: &str) -> FileRow {
+        FileRow {
+            id: 7,
+            url: format!("https://bucket.example.com/rates/{filename}"),
+            filename: filename.to_string(),
+            reporting_entity_name: "drew".to_string(),
+            reporting_entity_type: "type1".to_string(),
+            file_type: crate::index_file_parsing::meta_repository_trait::FileType::InNetwork,
+            description: String::new(),
+        }
+    }
+
+    #[test]
+    fn it_names_files_safely_from_presigned_urls() {
+        let presigned = file_named(
+            "2022-07-01_in%20network%3Arates.json?X-Amz-Signature=ab%2Fcd&X-Amz-Expires=3600#top",
+        );
+        assert_eq!(
+            output_filename(&presigned),
+            "7-2022-07-01_in network_rates.json"
+        );
+        assert_eq!(
+            DownloadRequest::for_file(&presigned, "out").path,
+            Path::new("out/7-2022-07-01_in network_rates.json")
+        );
+
+        assert_eq!(output_filename(&file_named("..%2F..%2Fetc")), "7-.._.._etc");
+        assert_eq!(output_filename(&file_named("?token=1")), "7.json");
+        assert_eq!(output_filename(&file_named("%2E%2E")), "7.json");
+
+        let long = output_filename(&file_named(&format!("{}.json", "é".repeat(200))));
+        assert_eq!(long.len(), MAX_FILENAME_LEN);
+        assert!(long.starts_with("7-é") && long.ends_with("é.json"));
+    }
+}
@@ -1,57 +1,887 @@
-use std::{fmt, marker::PhantomData};
+use std::{borrow::Cow, fmt, io::Read, marker::PhantomData};
 
 use serde::{
-    de::{SeqAccess, Visitor},
-    Deserializer,
+    de::{
+        self,
+        value::{MapAccessDeserializer, SeqAccessDeserializer},
+        DeserializeOwned, DeserializeSeed, IgnoredAny, IntoDeserializer, MapAccess, SeqAccess,
+        Visitor,
+    },
+    Deserialize, Deserializer,
 };
+use serde_json::{value::RawValue, Map, Value};
 
-use crate::{in_network_file_dto::InNetworkRateObject, node_filters::NodeFilters};
+use crate::{
+    allowed_amounts_file_dto::{AllowedAmountsFile, OutOfNetworkObject},
+    borrowed_in_network_file_dto::BorrowedInNetworkRateObject,
+    in_network_file_dto::{InNetworkFile, InNetworkRateObject},
+    lenient::{self, Coerced, CoercionWarning, PartiallyParsed, SkippedNode},
+    node_filters::{BillingCoded, NdcFilters, NodeFilter, NodeFilters},
+    prescription_drug_file_dto::{PrescriptionDrug, PrescriptionDrugFile},
+    schema_version::SchemaVersion,
+};
 
-pub fn filter_nodes<'de, D>(
+pub fn filter_nodes<'de, D, T>(deserializer: D, filter: &NodeFilters) -> Result<Vec<T>, D::Error>
+where
+    D: Deserializer<'de>,
+    T: Deserialize<'de> + BillingCoded,
+{
+    FilteredNodes::<_, _, Value>::new(filter, "", None, None, None).deserialize(deserializer)
+}
+
+// like `filter_nodes`, but skips the items that can't be read rather than failing.
+// each one skipped is logged, and returned with the items that were read.
+pub fn filter_nodes_skipping_malformed<'de, D, T>(
     deserializer: D,
-    filter: NodeFilters,
-) -> Result<Vec<InNetworkRateObject>, D::Error>
+    filter: &NodeFilters,
+) -> Result<PartiallyParsed<Vec<T>>, D::Error>
 where
     D: Deserializer<'de>,
+    T: Deserialize<'de> + BillingCoded,
+{
+    let mut skipped = vec![];
+    let file = FilteredNodes::<_, _, Value>::new(filter, "", None, None, Some(&mut skipped))
+        .deserialize(deserializer)?;
+    Ok(PartiallyParsed { file, skipped })
+}
+
+// deserializes an in network file, keeping only the rate objects matching `filters`,
+// which unlike `InNetworkFile`'s `Deserialize` impl can be chosen at runtime.
+// files of other schema versions than 1.x are normalized into the same dtos along the way.
+pub fn deserialize_filtered_in_network_file<R: Read>(
+    reader: R,
+    filters: &NodeFilters,
+) -> serde_json::Result<InNetworkFile> {
+    deserialize_filtered_file(reader, filters)
+}
+
+// deserializes an allowed amounts file, keeping only the out of network objects matching `filters`
+pub fn deserialize_filtered_allowed_amounts_file<R: Read>(
+    reader: R,
+    filters: &NodeFilters,
+) -> serde_json::Result<AllowedAmountsFile> {
+    deserialize_filtered_file(reader, filters)
+}
+
+// deserializes a prescription drug file, keeping only the drugs whose ndc matches `filters`
+pub fn deserialize_filtered_prescription_drug_file<R: Read>(
+    reader: R,
+    filters: &NdcFilters,
+) -> serde_json::Result<PrescriptionDrugFile> {
+    deserialize_filtered_file(reader, filters)
+}
+
+// like `deserialize_filtered_in_network_file`, but coerces the ways real payer files break the
+// schema rather than failing on them, see `lenient::coerce_quirks`.
+// only fails on a rate object that still can't be read, saying where it is.
+pub fn deserialize_filtered_in_network_file_leniently<R: Read>(
+    reader: R,
+    filters: &NodeFilters,
+) -> serde_json::Result<Coerced<InNetworkFile>> {
+    deserialize_filtered_file_leniently(reader, filters)
+}
+
+// like `deserialize_filtered_allowed_amounts_file`, in lenient mode
+pub fn deserialize_filtered_allowed_amounts_file_leniently<R: Read>(
+    reader: R,
+    filters: &NodeFilters,
+) -> serde_json::Result<Coerced<AllowedAmountsFile>> {
+    deserialize_filtered_file_leniently(reader, filters)
+}
+
+// like `deserialize_filtered_prescription_drug_file`, in lenient mode
+pub fn deserialize_filtered_prescription_drug_file_leniently<R: Read>(
+    reader: R,
+    filters: &NdcFilters,
+) -> serde_json::Result<Coerced<PrescriptionDrugFile>> {
+    deserialize_filtered_file_leniently(reader, filters)
+}
+
+// the rate objects of an in network file already in memory, e.g. memory mapped, keeping only
+// those matching `filters`. their strings borrow from `bytes` rather than being allocated,
+// so convert the ones to keep into `InNetworkRateObject`s. the file's other fields are skipped,
+// and it's read as 1.x whatever version it declares.
+pub fn deserialize_filtered_borrowed_rate_objects<'a, P>(
+    bytes: &'a [u8],
+    filters: &P,
+) -> serde_json::Result<Vec<BorrowedInNetworkRateObject<'a>>>
+where
+    P: for<'b> NodeFilter<BorrowedInNetworkRateObject<'b>>,
+{
+    let mut deserializer = serde_json::Deserializer::from_slice(bytes);
+    let rate_objects = BorrowedRateObjectsSeed(filters).deserialize(&mut deserializer)?;
+    deserializer.end()?;
+    Ok(rate_objects)
+}
+
+// like `deserialize_filtered_in_network_file`, but a rate object that can't be read is skipped
+// rather than failing the whole file. each one skipped is logged, and returned with the file.
+pub fn deserialize_filtered_in_network_file_skipping_malformed<R: Read>(
+    reader: R,
+    filters: &NodeFilters,
+) -> serde_json::Result<PartiallyParsed<InNetworkFile>> {
+    let mut skipped = vec![];
+    let mut deserializer = serde_json::Deserializer::from_reader(std::io::BufReader::new(reader));
+    let file = FilteredFileSeed(filters, None, Some(&mut skipped), PhantomData)
+        .deserialize(&mut deserializer)?;
+    deserializer.end()?;
+    Ok(PartiallyParsed { file, skipped })
+}
+
+fn deserialize_filtered_file<F, P, R>(reader: R, filters: &P) -> serde_json::Result<F>
+where
+    F: FilteredFile,
+    P: NodeFilter<F::Node>,
+    R: Read,
+{
+    let mut deserializer = serde_json::Deserializer::from_reader(std::io::BufReader::new(reader));
+    let file = FilteredFileSeed(filters, None, None, PhantomData).deserialize(&mut deserializer)?;
+    deserializer.end()?;
+    Ok(file)
+}
+
+fn deserialize_filtered_file_leniently<F, P, R>(
+    reader: R,
+    filters: &P,
+) -> serde_json::Result<Coerced<F>>
+where
+    F: FilteredFile,
+    P: NodeFilter<F::Node>,
+    R: Read,
+{
+    let mut warnings = vec![];
+    let mut deserializer = serde_json::Deserializer::from_reader(std::io::BufReader::new(reader));
+    let file = FilteredFileSeed(filters, Some(&mut warnings), None, PhantomData)
+        .deserialize(&mut deserializer)?;
+    deserializer.end()?;
+    Ok(Coerced { file, warnings })
+}
+
+// a file with one big array of items, which is filtered as it's read
+trait FilteredFile: DeserializeOwned {
+    type Node: DeserializeOwned;
+    const NODES_KEY: &'static str;
+
+    // whether nodes are rewritten for the schema version the file declares,
+    // so they can't be read until it's known, see `PendingNodes`
+    const VERSIONED: bool = false;
+
+    fn set_nodes(&mut self, nodes: Vec<Self::Node>);
+
+    // how to rewrite each node to fit `Self::Node`, for the schema version the file declared.
+    // `version` is `None` if the file doesn't declare one.
+    fn node_normalizer(_version: Option<&str>) -> Option<fn(&mut Value)> {
+        None
+    }
+
+    // rewrites the file's other fields, once they've all been read
+    fn normalize_fields(_fields: &mut Map<String, Value>) {}
+}
+
+impl FilteredFile for InNetworkFile {
+    type Node = InNetworkRateObject;
+    const NODES_KEY: &'static str = "in_network";
+    const VERSIONED: bool = true;
+
+    fn set_nodes(&mut self, nodes: Vec<InNetworkRateObject>) {
+        self.in_network = nodes;
+    }
+
+    fn node_normalizer(version: Option<&str>) -> Option<fn(&mut Value)> {
+        SchemaVersion::from_declared(version).rate_object_adapter()
+    }
+
+    fn normalize_fields(fields: &mut Map<String, Value>) {
+        let version = fields.get("version").and_then(Value::as_str);
+        if let Some(normalize) = SchemaVersion::from_declared(version).file_fields_adapter() {
+            normalize(fields);
+        }
+    }
+}
+
+impl FilteredFile for AllowedAmountsFile {
+    type Node = OutOfNetworkObject;
+    const NODES_KEY: &'static str = "out_of_network";
+
+    fn set_nodes(&mut self, nodes: Vec<OutOfNetworkObject>) {
+        self.out_of_network = nodes;
+    }
+}
+
+impl FilteredFile for PrescriptionDrugFile {
+    type Node = PrescriptionDrug;
+    const NODES_KEY: &'static str = "prescription_drugs";
+
+    fn set_nodes(&mut self, nodes: Vec<PrescriptionDrug>) {
+        self.prescription_drugs = nodes;
+    }
+}
+
+// the array of items, filtered as it's read, and normalized first when there's a normalizer.
+// `B` is how the fields before an item's key field are kept until it's known whether it matches.
+struct FilteredNodes<'a, 'w, P, T, B> {
+    filter: &'a P,
+    // the array's key, for the paths of warnings and errors
+    key: &'a str,
+    normalizer: Option<fn(&mut Value)>,
+    // only in lenient mode, where each item's quirks are coerced and recorded here
+    warnings: Option<&'w mut Vec<CoercionWarning>>,
+    // only when skipping malformed items, which are recorded here instead of failing
+    skipped: Option<&'w mut Vec<SkippedNode>>,
+    item: PhantomData<fn() -> (T, B)>,
+}
+
+impl<'a, 'w, P, T, B> FilteredNodes<'a, 'w, P, T, B> {
+    fn new(
+        filter: &'a P,
+        key: &'a str,
+        normalizer: Option<fn(&mut Value)>,
+        warnings: Option<&'w mut Vec<CoercionWarning>>,
+        skipped: Option<&'w mut Vec<SkippedNode>>,
+    ) -> Self {
+        FilteredNodes {
+            filter,
+            key,
+            normalizer,
+            warnings,
+            skipped,
+            item: PhantomData,
+        }
+    }
+}
+
+impl<'de, P, T, B> DeserializeSeed<'de> for FilteredNodes<'_, '_, P, T, B>
+where
+    P: NodeFilter<T>,
+    T: Deserialize<'de>,
+    B: BufferedField<'de>,
 {
-    struct FilteredRateObjectVisitor {
-        data: PhantomData<fn() -> InNetworkRateObject>,
-        filter: NodeFilters,
+    type Value = Vec<T>;
+
+    fn deserialize<D>(self, deserializer: D) -> Result<Self::Value, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        // the deserializer will call visitor.visit_seq() if a seq is present in the input data.
+        deserializer.deserialize_seq(self)
+    }
+}
+
+impl<'de, P, T, B> Visitor<'de> for FilteredNodes<'_, '_, P, T, B>
+where
+    P: NodeFilter<T>,
+    T: Deserialize<'de>,
+    B: BufferedField<'de>,
+{
+    // return value of visitor.  will return a vector of
+    // only the items matching the given NodeFilter.
+    type Value = Vec<T>;
+
+    fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+        formatter.write_str("an array of objects to filter")
+    }
+
+    fn visit_seq<S>(mut self, mut seq: S) -> Result<Vec<T>, S::Error>
+    where
+        S: SeqAccess<'de>,
+    {
+        // Start with an empty Vec
+        let mut filtered_nodes = vec![];
+
+        // only keep nodes that match our filter
+        let raw = self.normalizer.is_some() || self.warnings.is_some() || self.skipped.is_some();
+        let mut index = 0;
+        loop {
+            let value: Option<T> = match self.filter.key_field() {
+                // only nodes whose key field could match are read in full,
+                // and only go through raw json when they have to be rewritten first
+                Some(key_field) if raw => {
+                    match seq.next_element_seed(PrefilteredNode::<_, _, Value, Value>::new(
+                        self.filter,
+                        key_field,
+                    ))? {
+                        Some(Some(value)) => self.read_raw(value, index)?,
+                        Some(None) => None,
+                        None => break,
+                    }
+                }
+                Some(key_field) => {
+                    match seq.next_element_seed(PrefilteredNode::<_, _, T, B>::new(
+                        self.filter,
+                        key_field,
+                    ))? {
+                        Some(value) => value,
+                        None => break,
+                    }
+                }
+                None if raw => match seq.next_element::<Value>()? {
+                    Some(value) => self.read_raw(value, index)?,
+                    None => break,
+                },
+                None => match seq.next_element()? {
+                    Some(value) => Some(value),
+                    None => break,
+                },
+            };
+            index += 1;
+            if let Some(value) = value.filter(|value| self.filter.matches(value)) {
+                filtered_nodes.push(value);
+            }
+        }
+
+        Ok(filtered_nodes)
     }
+}
 
-    impl<'de> Visitor<'de> for FilteredRateObjectVisitor {
-        // return value of visitor.  will return a vector of
-        // only the RateObjects matching the given NodeFilter.
-        type Value = Vec<InNetworkRateObject>;
+impl<P, T, B> FilteredNodes<'_, '_, P, T, B> {
+    // deserializes a node read as raw json, after rewriting it for the file's version
+    // and any quirks in lenient mode. `None` if it's malformed and was skipped.
+    fn read_raw<'de, E>(&mut self, mut value: Value, index: usize) -> Result<Option<T>, E>
+    where
+        T: Deserialize<'de>,
+        E: de::Error,
+    {
+        let path = format!("$.{}[{index}]", self.key);
+        if let Some(normalize) = self.normalizer {
+            normalize(&mut value);
+        }
+        if let Some(warnings) = self.warnings.as_deref_mut() {
+            lenient::coerce_quirks(&mut value, &path, warnings);
+        }
+        let billing_code = match self.skipped {
+            Some(_) => value.get("billing_code").and_then(Value::as_str),
+            None => None,
+        }
+        .map(str::to_string);
+        match (T::deserialize(value), self.skipped.as_deref_mut()) {
+            (Ok(value), _) => Ok(Some(value)),
+            (Err(e), None) => Err(E::custom(format!("{path}: {e}"))),
+            (Err(e), Some(skipped)) => {
+                let node = SkippedNode {
+                    index,
+                    billing_code,
+                    error: e.to_string(),
+                };
+                println!("skipping malformed {node}");
+                skipped.push(node);
+                Ok(None)
+            }
+        }
+    }
 
-        fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
-            formatter.write_str("an in network rate object")
+    // reads the nodes `PendingNodes` kept, now that the file's version is known
+    fn read_pending<'de, E>(mut self, pending: Vec<(usize, Value)>) -> Result<Vec<T>, E>
+    where
+        P: NodeFilter<T>,
+        T: Deserialize<'de>,
+        E: de::Error,
+    {
+        let mut filtered_nodes = vec![];
+        for (index, value) in pending {
+            let value = self.read_raw(value, index)?;
+            if let Some(value) = value.filter(|value| self.filter.matches(value)) {
+                filtered_nodes.push(value);
+            }
         }
+        Ok(filtered_nodes)
+    }
+}
 
-        fn visit_seq<S>(self, mut seq: S) -> Result<Vec<InNetworkRateObject>, S::Error>
-        where
-            S: SeqAccess<'de>,
-        {
-            // Start with an empty Vec
-            let mut filtered_nodes = vec![];
+// the nodes that may match of a file that declares its version after them, e.g. a 2.x file
+// with `version` last. they're kept as raw json with their indexes until it's known how to
+// read them, which costs more memory than reading them straight away, but only for those
+// whose key field could match.
+struct PendingNodes<'a, P, T> {
+    filter: &'a P,
+    node: PhantomData<fn() -> T>,
+}
 
-            // only keep nodes that match our filter
-            while let Some(value) = seq.next_element()? {
-                if self.filter.matches(&value) {
-                    filtered_nodes.push(value);
+impl<'a, P, T> PendingNodes<'a, P, T> {
+    fn new(filter: &'a P) -> Self {
+        PendingNodes {
+            filter,
+            node: PhantomData,
+        }
+    }
+}
+
+impl<'de, P: NodeFilter<T>, T> DeserializeSeed<'de> for PendingNodes<'_, P, T> {
+    type Value = Vec<(usize, Value)>;
+
+    fn deserialize<D>(self, deserializer: D) -> Result<Self::Value, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        deserializer.deserialize_seq(self)
+    }
+}
+
+impl<'de, P: NodeFilter<T>, T> Visitor<'de> for PendingNodes<'_, P, T> {
+    type Value = Vec<(usize, Value)>;
+
+    fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+        formatter.write_str("an array of objects to filter")
+    }
+
+    fn visit_seq<S>(self, mut seq: S) -> Result<Self::Value, S::Error>
+    where
+        S: SeqAccess<'de>,
+    {
+        let mut pending = vec![];
+        let mut index = 0;
+        loop {
+            let value = match self.filter.key_field() {
+                Some(key_field) => {
+                    match seq.next_element_seed(PrefilteredNode::<_, T, Value, Value>::new(
+                        self.filter,
+                        key_field,
+                    ))? {
+                        Some(value) => value,
+                        None => break,
+                    }
                 }
+                None => match seq.next_element::<Value>()? {
+                    Some(value) => Some(value),
+                    None => break,
+                },
+            };
+            if let Some(value) = value {
+                pending.push((index, value));
+            }
+            index += 1;
+        }
+        Ok(pending)
+    }
+}
+
+// one node, read in full unless its key field shows the filter can't match it,
+// in which case the rest of it is skipped without being deserialized and it's `None`.
+// the fields before the key field have to be kept in case it matches, but the schemas
+// list the key fields before the big arrays, e.g. `billing_code` before `negotiated_rates`.
+// a node that may match is read as `N`: straight into the node, or as raw json to rewrite first.
+// one that isn't an object is read as `N` too, to fail like any other malformed node.
+// the fields before the key field are kept as `B`, see `BufferedField`.
+struct PrefilteredNode<'a, P, T, N, B> {
+    filter: &'a P,
+    key_field: &'static str,
+    node: PhantomData<fn() -> (T, N)>,
+    buffered: PhantomData<fn() -> B>,
+}
+
+impl<'a, P, T, N, B> PrefilteredNode<'a, P, T, N, B> {
+    fn new(filter: &'a P, key_field: &'static str) -> Self {
+        PrefilteredNode {
+            filter,
+            key_field,
+            node: PhantomData,
+            buffered: PhantomData,
+        }
+    }
+}
+
+impl<'de, P, T, N, B> DeserializeSeed<'de> for PrefilteredNode<'_, P, T, N, B>
+where
+    P: NodeFilter<T>,
+    N: Deserialize<'de>,
+    B: BufferedField<'de>,
+{
+    type Value = Option<N>;
+
+    fn deserialize<D>(self, deserializer: D) -> Result<Self::Value, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        deserializer.deserialize_any(self)
+    }
+}
+
+impl<'de, P, T, N, B> Visitor<'de> for PrefilteredNode<'_, P, T, N, B>
+where
+    P: NodeFilter<T>,
+    N: Deserialize<'de>,
+    B: BufferedField<'de>,
+{
+    type Value = Option<N>;
+
+    fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+        write!(formatter, "an object with a `{}`", self.key_field)
+    }
+
+    fn visit_map<M>(self, mut map: M) -> Result<Option<N>, M::Error>
+    where
+        M: MapAccess<'de>,
+    {
+        let mut buffered = vec![];
+        let mut rest = None;
+        while let Some(key) = map.next_key::<String>()? {
+            let value: B = map.next_value()?;
+            if key != self.key_field {
+                buffered.push((key, value));
+                continue;
+            }
+            if value
+                .as_str()
+                .is_some_and(|v| !self.filter.may_match_key(&v))
+            {
+                while map.next_entry::<IgnoredAny, IgnoredAny>()?.is_some() {}
+                return Ok(None);
+            }
+            buffered.push((key, value));
+            rest = Some(map);
+            break;
+        }
+        let fields = ReplayedMap {
+            buffered: buffered.into_iter(),
+            value: None,
+            rest,
+        };
+        N::deserialize(MapAccessDeserializer::new(fields)).map(Some)
+    }
+
+    fn visit_unit<E: de::Error>(self) -> Result<Option<N>, E> {
+        N::deserialize(().into_deserializer()).map(Some)
+    }
+
+    fn visit_bool<E: de::Error>(self, v: bool) -> Result<Option<N>, E> {
+        N::deserialize(v.into_deserializer()).map(Some)
+    }
+
+    fn visit_i64<E: de::Error>(self, v: i64) -> Result<Option<N>, E> {
+        N::deserialize(v.into_deserializer()).map(Some)
+    }
+
+    fn visit_u64<E: de::Error>(self, v: u64) -> Result<Option<N>, E> {
+        N::deserialize(v.into_deserializer()).map(Some)
+    }
+
+    fn visit_f64<E: de::Error>(self, v: f64) -> Result<Option<N>, E> {
+        N::deserialize(v.into_deserializer()).map(Some)
+    }
+
+    fn visit_str<E: de::Error>(self, v: &str) -> Result<Option<N>, E> {
+        N::deserialize(v.into_deserializer()).map(Some)
+    }
+
+    fn visit_seq<S>(self, seq: S) -> Result<Option<N>, S::Error>
+    where
+        S: SeqAccess<'de>,
+    {
+        N::deserialize(SeqAccessDeserializer::new(seq)).map(Some)
+    }
+}
+
+// how a node's fields are kept while its key field is looked for: as json values,
+// or when reading from a slice, as the raw json they are, so they can still borrow from it
+trait BufferedField<'de>: Deserialize<'de> {
+    // the field's value, if it's a string
+    fn as_str(&self) -> Option<Cow<'_, str>>;
+
+    fn replay<V: DeserializeSeed<'de>>(self, seed: V) -> serde_json::Result<V::Value>;
+}
+
+impl<'de> BufferedField<'de> for Value {
+    fn as_str(&self) -> Option<Cow<'_, str>> {
+        Value::as_str(self).map(Cow::Borrowed)
+    }
+
+    fn replay<V: DeserializeSeed<'de>>(self, seed: V) -> serde_json::Result<V::Value> {
+        seed.deserialize(self)
+    }
+}
+
+impl<'de> BufferedField<'de> for &'de RawValue {
+    fn as_str(&self) -> Option<Cow<'_, str>> {
+        // only strings with escapes have to be allocated
+        match serde_json::from_str::<&str>(self.get()) {
+            Ok(s) => Some(Cow::Borrowed(s)),
+            Err(_) => serde_json::from_str::<String>(self.get())
+                .ok()
+                .map(Cow::Owned),
+        }
+    }
+
+    fn replay<V: DeserializeSeed<'de>>(self, seed: V) -> serde_json::Result<V::Value> {
+        seed.deserialize(&mut serde_json::Deserializer::from_str(self.get()))
+    }
+}
+
+// a node's fields read while looking for its key field, then the ones after it still to be read
+// (`None` when there's none left)
+struct ReplayedMap<B, M> {
+    buffered: std::vec::IntoIter<(String, B)>,
+    value: Option<B>,
+    rest: Option<M>,
+}
+
+impl<'de, B: BufferedField<'de>, M: MapAccess<'de>> MapAccess<'de> for ReplayedMap<B, M> {
+    type Error = M::Error;
+
+    fn next_key_seed<K>(&mut self, seed: K) -> Result<Option<K::Value>, M::Error>
+    where
+        K: DeserializeSeed<'de>,
+    {
+        if let Some((key, value)) = self.buffered.next() {
+            self.value = Some(value);
+            return seed.deserialize(key.into_deserializer()).map(Some);
+        }
+        match &mut self.rest {
+            Some(map) => map.next_key_seed(seed),
+            None => Ok(None),
+        }
+    }
+
+    fn next_value_seed<V>(&mut self, seed: V) -> Result<V::Value, M::Error>
+    where
+        V: DeserializeSeed<'de>,
+    {
+        match (self.value.take(), &mut self.rest) {
+            (Some(value), _) => value.replay(seed).map_err(de::Error::custom),
+            (None, Some(map)) => map.next_value_seed(seed),
+            (None, None) => Err(de::Error::custom("a value without a key")),
+        }
+    }
+}
+
+// the file's filter, where to record coerced quirks in lenient mode,
+// and where to record malformed items when they're skipped
+struct FilteredFileSeed<'a, 'w, P, F>(
+    &'a P,
+    Option<&'w mut Vec<CoercionWarning>>,
+    Option<&'w mut Vec<SkippedNode>>,
+    PhantomData<fn() -> F>,
+);
+
+impl<'a, P, F: FilteredFile> FilteredFileSeed<'a, '_, P, F> {
+    // the file's nodes, read for the schema version it declares
+    fn nodes(&mut self, version: Option<&str>) -> FilteredNodes<'a, '_, P, F::Node, Value> {
+        FilteredNodes::new(
+            self.0,
+            F::NODES_KEY,
+            F::node_normalizer(version),
+            self.1.as_deref_mut(),
+            self.2.as_deref_mut(),
+        )
+    }
+}
+
+impl<'de, P: NodeFilter<F::Node>, F: FilteredFile> DeserializeSeed<'de>
+    for FilteredFileSeed<'_, '_, P, F>
+{
+    type Value = F;
+
+    fn deserialize<D>(self, deserializer: D) -> Result<Self::Value, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        deserializer.deserialize_map(self)
+    }
+}
+
+impl<'de, P: NodeFilter<F::Node>, F: FilteredFile> Visitor<'de> for FilteredFileSeed<'_, '_, P, F> {
+    type Value = F;
+
+    fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+        write!(formatter, "a file with an `{}` array", F::NODES_KEY)
+    }
+
+    // every field but the filtered array is small enough to collect as json first,
+    // then deserialize into the file with the array swapped in afterwards.
+    fn visit_map<M>(mut self, mut map: M) -> Result<F, M::Error>
+    where
+        M: MapAccess<'de>,
+    {
+        let mut fields = Map::new();
+        let mut nodes = None;
+        // the nodes that may match, when they come before the version to read them for
+        let mut pending = None;
+        while let Some(key) = map.next_key::<String>()? {
+            if key == F::NODES_KEY && F::VERSIONED && !fields.contains_key("version") {
+                pending = Some(map.next_value_seed(PendingNodes::<_, F::Node>::new(self.0))?);
+            } else if key == F::NODES_KEY {
+                let version = fields.get("version").and_then(Value::as_str);
+                nodes = Some(map.next_value_seed(self.nodes(version))?);
+            } else {
+                fields.insert(key, map.next_value()?);
             }
+        }
+        if let Some(pending) = pending {
+            let version = fields.get("version").and_then(Value::as_str);
+            nodes = Some(self.nodes(version).read_pending(pending)?);
+        }
+
+        let nodes = nodes.ok_or_else(|| de::Error::missing_field(F::NODES_KEY))?;
+        F::normalize_fields(&mut fields);
+        fields.insert(F::NODES_KEY.to_string(), Value::Array(vec![]));
+        let mut fields = Value::Object(fields);
+        if let Some(warnings) = self.1 {
+            lenient::coerce_quirks(&mut fields, "$", warnings);
+        }
+        let mut file: F = serde_json::from_value(fields).map_err(de::Error::custom)?;
+        file.set_nodes(nodes);
+        Ok(file)
+    }
+}
+
+// just the `in_network` array of a file read from a slice, see `deserialize_filtered_borrowed_rate_objects`
+struct BorrowedRateObjectsSeed<'a, P>(&'a P);
 
-            Ok(filtered_nodes)
+impl<'de, P> DeserializeSeed<'de> for BorrowedRateObjectsSeed<'_, P>
+where
+    P: for<'b> NodeFilter<BorrowedInNetworkRateObject<'b>>,
+{
+    type Value = Vec<BorrowedInNetworkRateObject<'de>>;
+
+    fn deserialize<D>(self, deserializer: D) -> Result<Self::Value, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        deserializer.deserialize_map(self)
+    }
+}
+
+impl<'de, P> Visitor<'de> for BorrowedRateObjectsSeed<'_, P>
+where
+    P: for<'b> NodeFilter<BorrowedInNetworkRateObject<'b>>,
+{
+    type Value = Vec<BorrowedInNetworkRateObject<'de>>;
+
+    fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+        formatter.write_str("a file with an `in_network` array")
+    }
+
+    fn visit_map<M>(self, mut map: M) -> Result<Self::Value, M::Error>
+    where
+        M: MapAccess<'de>,
+    {
+        let mut rate_objects = None;
+        while let Some(key) = map.next_key::<String>()? {
+            if key == InNetworkFile::NODES_KEY {
+                // the fields before each rate object's billing code are kept as raw json,
+                // so they borrow from the slice like the rest of it
+                let seed = FilteredNodes::<_, _, &'de RawValue>::new(
+                    self.0,
+                    InNetworkFile::NODES_KEY,
+                    None,
+                    None,
+                    None,
+                );
+                rate_objects = Some(map.next_value_seed(seed)?);
+            } else {
+                map.next_value::<IgnoredAny>()?;
+            }
         }
+        rate_objects.ok_or_else(|| de::Error::missing_field(InNetworkFile::NODES_KEY))
     }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
 
-    // Create the visitor and ask the deserializer to drive it. The
-    // deserializer will call visitor.visit_seq() if a seq is present in
-    // the input data.
-    let visitor = FilteredRateObjectVisitor {
-        data: PhantomData,
-        filter,
-    };
-    deserializer.deserialize_seq(visitor)
+    use super::*;
+
+    #[test]
+    fn it_reads_rate_objects_for_a_version_declared_after_them() {
+        let filters = NodeFilters::new(vec!["27447".to_string(), "99213".to_string()]);
+        // `version` last rather than first
+        let mut v1 = fs::read_to_string("./tests/fixtures/in-network-rates-sample.json")
+            .unwrap()
+            .replacen("\"version\": \"1.0.0\",", "", 1);
+        v1.insert_str(v1.rfind('}').unwrap(), ", \"version\": \"1.0.0\"");
+
+        let file = deserialize_filtered_in_network_file(v1.as_bytes(), &filters).unwrap();
+        assert_eq!(file.schema_version(), SchemaVersion::V1);
+        let billing_codes: Vec<&str> = file
+            .in_network
+            .iter()
+            .map(|rate_object| rate_object.billing_code.as_str())
+            .collect();
+        assert_eq!(billing_codes, vec!["27447", "99213"]);
+
+        // a 1.x file isn't rewritten as an older version's would be, wherever it declares its version
+        let legacy_rate = v1.replacen(
+            "\"negotiated_rate\": 1500.5",
+            "\"negotiated_price\": 1500.5",
+            1,
+        );
+        let error =
+            deserialize_filtered_in_network_file(legacy_rate.as_bytes(), &filters).unwrap_err();
+        assert!(error.to_string().contains("negotiated_rate"), "{error}");
+        let unversioned = legacy_rate.replacen(", \"version\": \"1.0.0\"", "", 1);
+        assert!(deserialize_filtered_in_network_file(unversioned.as_bytes(), &filters).is_ok());
+    }
+
+    #[test]
+    fn it_skips_and_reports_malformed_rate_objects() {
+        let all = NodeFilters::new(vec![]);
+        // the second rate object's price isn't a number
+        let json = fs::read_to_string("./tests/fixtures/in-network-rates-sample.json")
+            .unwrap()
+            .replace("\"negotiated_rate\": 90", "\"negotiated_rate\": \"ninety\"");
+
+        assert!(deserialize_filtered_in_network_file(json.as_bytes(), &all).is_err());
+
+        let parsed =
+            deserialize_filtered_in_network_file_skipping_malformed(json.as_bytes(), &all).unwrap();
+        let billing_codes: Vec<&str> = parsed
+            .file
+            .in_network
+            .iter()
+            .map(|rate_object| rate_object.billing_code.as_str())
+            .collect();
+        assert_eq!(billing_codes, vec!["27447", "42820"]);
+        assert_eq!(parsed.skipped.len(), 1);
+        let SkippedNode {
+            index,
+            billing_code,
+            error,
+        } = &parsed.skipped[0];
+        assert_eq!(*index, 1);
+        assert_eq!(billing_code.as_deref(), Some("99213"));
+        assert!(error.contains("ninety"), "{error}");
+
+        // the same, for a bare array
+        let in_network = &json[json.find("\"in_network\"").unwrap() + "\"in_network\":".len()..];
+        let in_network = &in_network[..in_network.rfind('}').unwrap()];
+        let rate_objects: PartiallyParsed<Vec<InNetworkRateObject>> =
+            filter_nodes_skipping_malformed(
+                &mut serde_json::Deserializer::from_str(in_network),
+                &all,
+            )
+            .unwrap();
+        assert_eq!(rate_objects.file.len(), 2);
+        assert_eq!(rate_objects.skipped, parsed.skipped);
+    }
+
+    #[test]
+    fn it_skips_items_that_arent_objects() {
+        let filters = NodeFilters::new(vec!["27447".to_string(), "99213".to_string()]);
+        let json = fs::read_to_string("./tests/fixtures/in-network-rates-sample.json")
+            .unwrap()
+            .replacen("\"in_network\": [", "\"in_network\": [null, 1, [],", 1);
+
+        // they fail the file unless they're skipped, even when the filter only reads billing codes
+        let error = deserialize_filtered_in_network_file(json.as_bytes(), &filters).unwrap_err();
+        assert!(
+            error.to_string().starts_with("invalid type: null"),
+            "{error}"
+        );
+
+        let parsed =
+            deserialize_filtered_in_network_file_skipping_malformed(json.as_bytes(), &filters)
+                .unwrap();
+        let billing_codes: Vec<&str> = parsed
+            .file
+            .in_network
+            .iter()
+            .map(|rate_object| rate_object.billing_code.as_str())
+            .collect();
+        assert_eq!(billing_codes, vec!["27447", "99213"]);
+        let skipped: Vec<(usize, Option<&str>)> = parsed
+            .skipped
+            .iter()
+            .map(|node| (node.index, node.billing_code.as_deref()))
+            .collect();
+        assert_eq!(skipped, vec![(0, None), (1, None), (2, None)]);
+    }
 }
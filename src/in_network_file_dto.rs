@@ -1,8 +1,11 @@
+use std::fmt;
+
 use serde::{Deserialize, Deserializer, Serialize};
 use serde_json::Number;
 
 use crate::filtered_in_network_file::filter_nodes;
 use crate::node_filters::NodeFilters;
+use crate::schema_version::SchemaVersion;
 
 fn filter_nodes_by_billing_codes<'de, D>(
     deserializer: D,
@@ -12,7 +15,7 @@ where
 {
     let billing_codes = vec!["945".to_string()];
     let filters = NodeFilters::new(billing_codes);
-    filter_nodes(deserializer, filters)
+    filter_nodes(deserializer, &filters)
 }
 
 #[derive(Deserialize, Debug, Serialize)]
@@ -32,11 +35,17 @@ pub struct InNetworkFile {
     pub provider_references: Option<Vec<ProviderReference>>,
 }
 
+impl InNetworkFile {
+    pub fn schema_version(&self) -> SchemaVersion {
+        SchemaVersion::from_declared(Some(&self.version))
+    }
+}
+
 #[derive(Deserialize, Debug, Serialize)]
 pub struct InNetworkRateObject {
-    pub negotiation_arrangement: String,
+    pub negotiation_arrangement: NegotiationArrangement,
     pub name: String,
-    pub billing_code_type: String,
+    pub billing_code_type: BillingCodeType,
     pub billing_code_type_version: String,
     pub billing_code: String,
     pub negotiated_rates: Vec<NegotiatedRate>,
@@ -55,7 +64,7 @@ pub struct NegotiatedRate {
 #[derive(Deserialize, Debug, Serialize)]
 pub struct NegotiatedPrice {
     pub negotiated_rate: Number,
-    pub negotiated_type: String,
+    pub negotiated_type: NegotiatedType,
     pub expiration_date: String,
     pub service_code: Option<Vec<String>>,
     pub billing_class: BillingClass,
@@ -71,6 +80,190 @@ pub enum BillingClass {
     Institutional,
 }
 
+// the schema enumerates the values of the next few fields, but payers don't always stick to them.
+// values are matched ignoring case, and anything else is kept as `Other`.
+
+#[derive(Deserialize, Debug, Serialize, Clone, PartialEq, Eq, Hash)]
+#[serde(from = "String", into = "String")]
+pub enum BillingCodeType {
+    Cpt,
+    Hcpcs,
+    Icd,
+    MsDrg,
+    RDrg,
+    SDrg,
+    ApsDrg,
+    ApDrg,
+    AprDrg,
+    Apc,
+    Ndc,
+    Hipps,
+    Local,
+    Eapg,
+    Cdt,
+    Rc,
+    CstmAll,
+    Other(String),
+}
+
+impl BillingCodeType {
+    const KNOWN: [BillingCodeType; 17] = [
+        BillingCodeType::Cpt,
+        BillingCodeType::Hcpcs,
+        BillingCodeType::Icd,
+        BillingCodeType::MsDrg,
+        BillingCodeType::RDrg,
+        BillingCodeType::SDrg,
+        BillingCodeType::ApsDrg,
+        BillingCodeType::ApDrg,
+        BillingCodeType::AprDrg,
+        BillingCodeType::Apc,
+        BillingCodeType::Ndc,
+        BillingCodeType::Hipps,
+        BillingCodeType::Local,
+        BillingCodeType::Eapg,
+        BillingCodeType::Cdt,
+        BillingCodeType::Rc,
+        BillingCodeType::CstmAll,
+    ];
+
+    pub fn as_str(&self) -> &str {
+        match self {
+            BillingCodeType::Cpt => "CPT",
+            BillingCodeType::Hcpcs => "HCPCS",
+            BillingCodeType::Icd => "ICD",
+            BillingCodeType::MsDrg => "MS-DRG",
+            BillingCodeType::RDrg => "R-DRG",
+            BillingCodeType::SDrg => "S-DRG",
+            BillingCodeType::ApsDrg => "APS-DRG",
+            BillingCodeType::ApDrg => "AP-DRG",
+            BillingCodeType::AprDrg => "APR-DRG",
+            BillingCodeType::Apc => "APC",
+            BillingCodeType::Ndc => "NDC",
+            BillingCodeType::Hipps => "HIPPS",
+            BillingCodeType::Local => "LOCAL",
+            BillingCodeType::Eapg => "EAPG",
+            BillingCodeType::Cdt => "CDT",
+            BillingCodeType::Rc => "RC",
+            BillingCodeType::CstmAll => "CSTM-ALL",
+            BillingCodeType::Other(other) => other,
+        }
+    }
+}
+
+impl From<String> for BillingCodeType {
+    fn from(s: String) -> Self {
+        BillingCodeType::KNOWN
+            .into_iter()
+            .find(|known| known.as_str().eq_ignore_ascii_case(s.trim()))
+            .unwrap_or(BillingCodeType::Other(s))
+    }
+}
+
+impl From<BillingCodeType> for String {
+    fn from(t: BillingCodeType) -> Self {
+        t.as_str().to_string()
+    }
+}
+
+impl fmt::Display for BillingCodeType {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+#[derive(Deserialize, Debug, Serialize, Clone, PartialEq, Eq, Hash)]
+#[serde(from = "String", into = "String")]
+pub enum NegotiationArrangement {
+    Ffs,
+    Bundle,
+    Capitation,
+    Other(String),
+}
+
+impl NegotiationArrangement {
+    pub fn as_str(&self) -> &str {
+        match self {
+            NegotiationArrangement::Ffs => "ffs",
+            NegotiationArrangement::Bundle => "bundle",
+            NegotiationArrangement::Capitation => "capitation",
+            NegotiationArrangement::Other(other) => other,
+        }
+    }
+}
+
+impl From<String> for NegotiationArrangement {
+    fn from(s: String) -> Self {
+        match s.trim().to_ascii_lowercase().as_str() {
+            "ffs" => NegotiationArrangement::Ffs,
+            "bundle" => NegotiationArrangement::Bundle,
+            "capitation" => NegotiationArrangement::Capitation,
+            _ => NegotiationArrangement::Other(s),
+        }
+    }
+}
+
+impl From<NegotiationArrangement> for String {
+    fn from(a: NegotiationArrangement) -> Self {
+        a.as_str().to_string()
+    }
+}
+
+impl fmt::Display for NegotiationArrangement {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+#[derive(Deserialize, Debug, Serialize, Clone, PartialEq, Eq, Hash)]
+#[serde(from = "String", into = "String")]
+pub enum NegotiatedType {
+    Negotiated,
+    Derived,
+    FeeSchedule,
+    Percentage,
+    PerDiem,
+    Other(String),
+}
+
+impl NegotiatedType {
+    pub fn as_str(&self) -> &str {
+        match self {
+            NegotiatedType::Negotiated => "negotiated",
+            NegotiatedType::Derived => "derived",
+            NegotiatedType::FeeSchedule => "fee schedule",
+            NegotiatedType::Percentage => "percentage",
+            NegotiatedType::PerDiem => "per diem",
+            NegotiatedType::Other(other) => other,
+        }
+    }
+}
+
+impl From<String> for NegotiatedType {
+    fn from(s: String) -> Self {
+        match s.trim().to_ascii_lowercase().as_str() {
+            "negotiated" => NegotiatedType::Negotiated,
+            "derived" => NegotiatedType::Derived,
+            "fee schedule" => NegotiatedType::FeeSchedule,
+            "percentage" => NegotiatedType::Percentage,
+            "per diem" => NegotiatedType::PerDiem,
+            _ => NegotiatedType::Other(s),
+        }
+    }
+}
+
+impl From<NegotiatedType> for String {
+    fn from(t: NegotiatedType) -> Self {
+        t.as_str().to_string()
+    }
+}
+
+impl fmt::Display for NegotiatedType {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
 #[derive(Deserialize, Debug, Serialize)]
 pub struct ProviderGroup {
     pub npi: Vec<Number>,
@@ -96,7 +289,7 @@ pub enum TinType {
 #[derive(Deserialize, Debug, Serialize)]
 pub struct CoveredService {
     pub billing_code: String,
-    pub billing_code_type: String,
+    pub billing_code_type: BillingCodeType,
     pub billing_code_type_version: String,
     pub description: String,
 }
@@ -110,4 +303,45 @@ pub struct ProviderReference {
 
 #[cfg(test)]
 mod tests {
+    use super::*;
+
+    #[test]
+    fn it_matches_enumerated_values_ignoring_case() {
+        let types: Vec<BillingCodeType> =
+            serde_json::from_str(r#"["CPT", "ms-drg", "CSTM-ALL", "CPT4"]"#).unwrap();
+        assert_eq!(
+            types,
+            vec![
+                BillingCodeType::Cpt,
+                BillingCodeType::MsDrg,
+                BillingCodeType::CstmAll,
+                BillingCodeType::Other("CPT4".to_string())
+            ]
+        );
+
+        let negotiated_type: NegotiatedType = serde_json::from_str(r#""Fee Schedule""#).unwrap();
+        assert_eq!(negotiated_type, NegotiatedType::FeeSchedule);
+        assert_eq!(
+            serde_json::to_string(&negotiated_type).unwrap(),
+            r#""fee schedule""#
+        );
+        let arrangement: NegotiationArrangement = serde_json::from_str(r#""pbm""#).unwrap();
+        assert_eq!(serde_json::to_string(&arrangement).unwrap(), r#""pbm""#);
+    }
+
+    #[test]
+    fn it_requires_an_expiration_date() {
+        let price = r#"{"negotiated_rate": 12.5, "negotiated_type": "negotiated", "billing_class": "professional""#;
+        let error = serde_json::from_str::<NegotiatedPrice>(&format!("{price}}}")).unwrap_err();
+        assert!(
+            error
+                .to_string()
+                .starts_with("missing field `expiration_date`"),
+            "{error}"
+        );
+        assert!(serde_json::from_str::<NegotiatedPrice>(&format!(
+            r#"{price}, "expiration_date": null}}"#
+        ))
+        .is_err());
+    }
 }
@@ -1,7 +1,14 @@
-use std::fs::File;
+use std::{
+    collections::HashSet,
+    time::{Instant, SystemTime, UNIX_EPOCH},
+};
+
+use crate::location::{open_location, LocationError};
 
 use crate::index_file_parsing::{
-    index_file::IndexFile, meta_repository_trait::DbLinkInput, results_dto::IndexFileParsingResults,
+    index_file::IndexFile,
+    meta_repository_trait::{DbLinkInput, EntityKind, FileType, LinkKind},
+    results_dto::{IndexFileParsingResults, ReportingStructureResults, SkippedReportingStructure},
 };
 
 use self::meta_repository_trait::{FileRowInput, MetaRepository, PlanInput};
@@ -9,99 +16,176 @@ use self::meta_repository_trait::{FileRowInput, MetaRepository, PlanInput};
 pub mod csv_meta_repository;
 pub mod index_file;
 pub mod meta_repository_trait;
-mod results_dto;
+#[cfg(feature = "postgres")]
+pub mod postgres_meta_repository;
+pub mod results_dto;
+#[cfg(feature = "sqlite")]
+pub mod sqlite_meta_repository;
 
 // given a path to a local index file,
 // deserialize it and its reporting structures,
-// and send files and plan info to DB.
-pub fn parse_index_file_from_path(path: &'static str) -> IndexFileParsingResults {
-    // get reporting_entity_name & type, publish file & get id
-    println!("reading from {path}");
-    let file = deserialize_index_file(path).unwrap();
-    start_index_file_consumer(path, file)
+// and send files and plan info to the given repository.
+pub fn parse_index_file_from_path(
+    path: impl AsRef<str>,
+    repo: &mut dyn MetaRepository,
+) -> IndexFileParsingResults {
+    parse_index_file(path, repo).expect("index file to be parsed")
 }
 
-fn start_index_file_consumer(path: &'static str, index_file: IndexFile) -> IndexFileParsingResults {
-    let mut num_reporting_structures: i32 = 0;
-    let mut num_plans: i32 = 0;
-    let mut num_rate_files: i32 = 0;
+// like `parse_index_file_from_path`, but `location` may also be an http(s) url,
+// whose body is deserialized as it's downloaded.
+// the index file is recorded under `location` as given.
+// json that isn't well formed fails it, though the structures read before are still recorded.
+pub fn parse_index_file(
+    location: impl AsRef<str>,
+    repo: &mut dyn MetaRepository,
+) -> Result<IndexFileParsingResults, LocationError> {
+    let location = location.as_ref();
+    let started = Instant::now();
+    let started_at_ms = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |since| since.as_millis());
+    // get reporting_entity_name & type, publish file & get id
+    println!("reading from {location}");
+    let (reader, _) = open_location(location)?;
+    let file = IndexFile::from_reader(reader)?;
+    let mut results = start_index_file_consumer(location, file, repo)?;
+    results.started_at_ms = started_at_ms;
+    results.duration_ms = started.elapsed().as_millis();
+    Ok(results)
+}
 
-    let repo = csv_meta_repository::CsvMetaRepository {
-        files_csv_path: "./db/files.csv",
-        links_csv_path: "./db/links.csv",
-        plans_csv_path: "./db/plans.csv",
+fn start_index_file_consumer(
+    location: &str,
+    mut index_file: IndexFile,
+    repo: &mut dyn MetaRepository,
+) -> serde_json::Result<IndexFileParsingResults> {
+    let mut results = IndexFileParsingResults {
+        index_file_id: 0,
+        location: location.to_string(),
+        reporting_entity_name: index_file.reporting_entity_name.clone(),
+        reporting_entity_type: index_file.reporting_entity_type.clone(),
+        version: index_file.version.clone(),
+        num_reporting_structures: 0,
+        num_plans: 0,
+        num_rate_files: 0,
+        num_unique_rate_files: 0,
+        num_duplicate_rate_files: 0,
+        reporting_structures: vec![],
+        skipped_reporting_structures: vec![],
+        started_at_ms: 0,
+        duration_ms: 0,
     };
-
-    let index_file_id = repo.add_file(&mut FileRowInput {
-        url: path.clone(),
-        filename: "index",
-        reporting_entity_name: &index_file.reporting_entity_name,
-        reporting_entity_type: &index_file.reporting_entity_type,
+    let mut rate_file_urls: HashSet<String> = HashSet::new();
+
+    let index_file_id = repo.add_file(&FileRowInput {
+        url: location.to_string(),
+        filename: "index".to_string(),
+        reporting_entity_name: index_file.reporting_entity_name.clone(),
+        reporting_entity_type: index_file.reporting_entity_type.clone(),
+        file_type: FileType::Index,
+        description: String::new(),
     });
-
-    for node in index_file.reporting_structure {
-        println!("handling reporting structure {num_reporting_structures}");
-        let mut plan_ids: Vec<usize> = vec![];
-        let mut file_ids: Vec<usize> = vec![];
+    results.index_file_id = index_file_id;
+
+    for (index, node) in index_file.reporting_structure.by_ref().enumerate() {
+        results.num_reporting_structures += 1;
+        let node = match node.0 {
+            Ok(node) => node,
+            Err(error) => {
+                println!("skipping reporting structure {index}: {error}");
+                results
+                    .skipped_reporting_structures
+                    .push(SkippedReportingStructure { index, error });
+                continue;
+            }
+        };
+        println!("handling reporting structure {index}");
+        let mut structure = ReportingStructureResults {
+            index,
+            plan_ids: vec![],
+            in_network_file_ids: vec![],
+            allowed_amount_file_id: None,
+        };
 
         for plan in node.reporting_plans {
-            plan_ids.push(repo.add_plan(&mut PlanInput::from_reporting_plan(&plan)));
-            num_plans += 1;
+            structure
+                .plan_ids
+                .push(repo.add_plan(&PlanInput::from_reporting_plan(&plan)));
+            results.num_plans += 1;
         }
 
-        for rate_file in node.in_network_files {
-            file_ids.push(repo.add_file(&mut FileRowInput {
-                url: &rate_file.location,
-                filename: _get_filename_from_url(&rate_file.location).as_str(),
-                reporting_entity_name: &index_file.reporting_entity_name,
-                reporting_entity_type: &index_file.reporting_entity_type,
-            }));
-            num_rate_files += 1;
-        }
+        let linked_files = node
+            .in_network_files
+            .into_iter()
+            .map(|file| (FileType::InNetwork, file))
+            .chain(
+                node.allowed_amount_file
+                    .map(|file| (FileType::AllowedAmount, file)),
+            );
+        for (file_type, rate_file) in linked_files {
+            results.num_rate_files += 1;
+            if rate_file_urls.insert(rate_file.location.clone()) {
+                results.num_unique_rate_files += 1;
+            } else {
+                results.num_duplicate_rate_files += 1;
+            }
 
-        file_ids.push(repo.add_file(&mut FileRowInput {
-            url: &node.allowed_amount_file.location,
-            filename: &node.allowed_amount_file.description,
-            reporting_entity_name: &index_file.reporting_entity_name,
-            reporting_entity_type: &index_file.reporting_entity_type,
-        }));
-        num_rate_files += 1;
-
-        for file_id in &file_ids {
-            repo.add_link(&mut DbLinkInput {
-                from_id: index_file_id.clone(),
-                from_type: "index_file",
-                to_id: file_id.clone(),
-                to_type: "rate_file",
+            let file_id = repo.add_file(&FileRowInput {
+                filename: _get_filename_from_url(&rate_file.location),
+                url: rate_file.location,
+                reporting_entity_name: index_file.reporting_entity_name.clone(),
+                reporting_entity_type: index_file.reporting_entity_type.clone(),
+                file_type,
+                description: rate_file.description,
             });
+            match file_type {
+                FileType::AllowedAmount => structure.allowed_amount_file_id = Some(file_id),
+                _ => structure.in_network_file_ids.push(file_id),
+            }
+        }
 
-            for plan_id in &plan_ids {
-                repo.add_link(&mut DbLinkInput {
-                    from_id: plan_id.clone(),
-                    from_type: "plan",
-                    to_id: file_id.clone(),
-                    to_type: "rate_file",
-                });
+        let file_ids = structure
+            .in_network_file_ids
+            .iter()
+            .chain(&structure.allowed_amount_file_id);
+        for file_id in file_ids {
+            repo.add_link(&DbLinkInput::new(
+                LinkKind::IndexFileToRateFile,
+                index_file_id,
+                *file_id,
+            ));
+
+            for plan_id in &structure.plan_ids {
+                repo.add_link(&DbLinkInput::new(
+                    LinkKind::PlanToRateFile,
+                    *plan_id,
+                    *file_id,
+                ));
             }
         }
-        num_reporting_structures += 1;
+        results.reporting_structures.push(structure);
     }
-
-    IndexFileParsingResults {
-        index_file_id,
-        reporting_entity_name: index_file.reporting_entity_name,
-        reporting_entity_type: index_file.reporting_entity_type,
-        num_reporting_structures,
-        num_plans,
-        num_rate_files,
+    let version = index_file.finish();
+    repo.commit();
+    results.version = version?;
+
+    results.index_file_id = repo.committed_id(EntityKind::IndexFile, results.index_file_id);
+    for structure in &mut results.reporting_structures {
+        for id in &mut structure.plan_ids {
+            *id = repo.committed_id(EntityKind::Plan, *id);
+        }
+        for id in structure
+            .in_network_file_ids
+            .iter_mut()
+            .chain(&mut structure.allowed_amount_file_id)
+        {
+            *id = repo.committed_id(EntityKind::RateFile, *id);
+        }
     }
+    Ok(results)
 }
 
 fn _get_filename_from_url(url: &str) -> String {
-    return url.split("/").last().unwrap().to_string();
-}
-
-fn deserialize_index_file(path: &'static str) -> Result<IndexFile, serde_json::Error> {
-    let file = File::open(path).unwrap();
-    serde_json::from_reader::<File, IndexFile>(file)
+    url.split('/').next_back().unwrap().to_string()
 }
@@ -1,83 +1,739 @@
-use std::fs::OpenOptions;
+use std::{
+    collections::{BTreeMap, BTreeSet, HashMap, HashSet},
+    error::Error,
+    fmt,
+    fs::{self, File, OpenOptions},
+    io::{self, Read, Seek, SeekFrom, Write},
+    path::{Path, PathBuf},
+};
 
 use csv;
 
 use super::meta_repository_trait::{
-    DbLink, DbLinkInput, FileRow, FileRowInput, FromInput, MetaRepository, Plan, PlanInput,
+    DbLink, DbLinkInput, EntityKind, FileRow, FileRowInput, FileState, FileType, FromInput,
+    MetaRepository, Plan, PlanInput,
 };
 
-pub struct CsvMetaRepository<'a> {
-    pub files_csv_path: &'a str,
-    pub links_csv_path: &'a str,
-    pub plans_csv_path: &'a str,
+/// keeps each csv file's writer open, and the next id and every row's
+/// unique key in memory, so adding a row doesn't reread the file.
+/// rows are buffered, and only guaranteed to be on disk after `commit`,
+/// `flush`, or once the repository is dropped.
+pub struct CsvMetaRepository {
+    files: CsvTable,
+    links: CsvTable,
+    plans: CsvTable,
+    processed_files: CsvTable,
+    file_states: CsvTable,
+}
+
+impl CsvMetaRepository {
+    /// missing csv files are created with their header.
+    /// existing ones must have the expected header and only full-width rows,
+    /// see `repair_dir` for fixing ones that don't.
+    pub fn new<P: AsRef<Path>>(
+        files_csv_path: P,
+        links_csv_path: P,
+        plans_csv_path: P,
+        processed_files_csv_path: P,
+        file_states_csv_path: P,
+    ) -> Result<Self, CsvDbError> {
+        Ok(CsvMetaRepository {
+            files: CsvTable::open(files_csv_path.as_ref(), &FILES)?,
+            links: CsvTable::open(links_csv_path.as_ref(), &LINKS)?,
+            plans: CsvTable::open(plans_csv_path.as_ref(), &PLANS)?,
+            processed_files: CsvTable::open(processed_files_csv_path.as_ref(), &PROCESSED_FILES)?,
+            file_states: CsvTable::open(file_states_csv_path.as_ref(), &FILE_STATES)?,
+        })
+    }
+
+    /// a repository over the `files.csv`, `links.csv`, `plans.csv`,
+    /// `processed_files.csv` and `file_states.csv` in `dir`, like `./db`
+    pub fn in_dir<P: AsRef<Path>>(dir: P) -> Result<Self, CsvDbError> {
+        let dir = dir.as_ref();
+        Self::new(
+            dir.join("files.csv"),
+            dir.join("links.csv"),
+            dir.join("plans.csv"),
+            dir.join("processed_files.csv"),
+            dir.join("file_states.csv"),
+        )
+    }
+
+    pub fn flush(&mut self) -> csv::Result<()> {
+        self.files.writer.flush()?;
+        self.links.writer.flush()?;
+        self.plans.writer.flush()?;
+        self.processed_files.writer.flush()?;
+        self.file_states.writer.flush()?;
+        Ok(())
+    }
+
+    // ids at the other end of links to or from `id`.
+    // `id` is a plan's when `is_plan`, and a file's otherwise, and the other end is the opposite.
+    fn _linked_ids(&self, id: usize, is_plan: bool) -> BTreeSet<usize> {
+        let id = id.to_string();
+        let mut linked = BTreeSet::new();
+        for row in self.links.rows.values() {
+            let (from, to) = ((&row[1], &row[2]), (&row[3], &row[4]));
+            for ((end_id, end_type), (other_id, other_type)) in [(from, to), (to, from)] {
+                if end_id == id
+                    && (end_type == EntityKind::Plan.as_str()) == is_plan
+                    && (other_type == EntityKind::Plan.as_str()) != is_plan
+                {
+                    linked.extend(other_id.parse::<usize>().ok());
+                }
+            }
+        }
+        linked
+    }
+
+    fn _file_records(&self, ids: impl IntoIterator<Item = usize>) -> Vec<FileRow> {
+        ids.into_iter()
+            .filter_map(|id| self.files.rows.get(&id))
+            .map(|row| FileRow {
+                id: row[0].parse().expect("validated on open"),
+                url: row[1].to_string(),
+                filename: row[2].to_string(),
+                reporting_entity_name: row[3].to_string(),
+                reporting_entity_type: row[4].to_string(),
+                file_type: row[5].parse().expect("file_type to be a known file type"),
+                description: row[6].to_string(),
+            })
+            .collect()
+    }
+
+    /// rewrites the csv files in `dir` so they can be opened again:
+    /// headers are restored, rows with the wrong number of fields or without an id are dropped,
+    /// and duplicate rows are collapsed onto the first one, with links pointed at the rows kept.
+    pub fn repair_dir<P: AsRef<Path>>(dir: P) -> Result<Vec<CsvRepairReport>, CsvDbError> {
+        let dir = dir.as_ref();
+        let (files_report, file_ids) = repair_table(&dir.join("files.csv"), &FILES, |_| {})?;
+        let (plans_report, plan_ids) = repair_table(&dir.join("plans.csv"), &PLANS, |_| {})?;
+        let (processed_files_report, _) =
+            repair_table(&dir.join("processed_files.csv"), &PROCESSED_FILES, |row| {
+                if let Some(kept_id) = row[1].parse().ok().and_then(|id| file_ids.get(&id)) {
+                    row[1] = kept_id.to_string();
+                }
+            })?;
+        let (file_states_report, _) =
+            repair_table(&dir.join("file_states.csv"), &FILE_STATES, |row| {
+                if let Some(kept_id) = row[1].parse().ok().and_then(|id| file_ids.get(&id)) {
+                    row[1] = kept_id.to_string();
+                }
+            })?;
+        let (links_report, _) = repair_table(&dir.join("links.csv"), &LINKS, |row| {
+            // from_id & from_type, then to_id & to_type
+            for (id_column, type_column) in [(1, 2), (3, 4)] {
+                let ids = match row[type_column].parse() {
+                    Ok(EntityKind::Plan) => &plan_ids,
+                    _ => &file_ids,
+                };
+                if let Some(kept_id) = row[id_column].parse().ok().and_then(|id| ids.get(&id)) {
+                    row[id_column] = kept_id.to_string();
+                }
+            }
+        })?;
+        Ok(vec![
+            files_report,
+            plans_report,
+            links_report,
+            processed_files_report,
+            file_states_report,
+        ])
+    }
+}
+
+impl Drop for CsvMetaRepository {
+    fn drop(&mut self) {
+        // nothing to report a failure to here, call `flush` first to handle errors
+        let _ = self.flush();
+    }
 }
 
-impl<'a> CsvMetaRepository<'a> {
-    fn _get_length_of_file_db(&self, db_path: &str) -> usize {
-        // get id of last row in csv file
-        // or just length of csv file?
-        let reader = csv::Reader::from_path(db_path).expect("failed to open {db_path}");
-        // add one to get new id
-        return reader.into_records().count() as usize;
+impl MetaRepository for CsvMetaRepository {
+    fn add_file(&mut self, file: &FileRowInput) -> usize {
+        self.files.upsert(vec![file.url.to_string()], |id| {
+            FileRow::from_input(id, file)
+        })
+    }
+
+    fn add_link(&mut self, link: &DbLinkInput) -> usize {
+        let key = vec![
+            link.from_id.to_string(),
+            link.from_type.as_str().to_string(),
+            link.to_id.to_string(),
+            link.to_type.as_str().to_string(),
+        ];
+        self.links.upsert(key, |id| DbLink::from_input(id, link))
+    }
+
+    fn add_plan(&mut self, plan: &PlanInput) -> usize {
+        let key = vec![plan.plan_id_type.to_string(), plan.plan_id.to_string()];
+        self.plans.upsert(key, |id| Plan::from_input(id, plan))
+    }
+
+    fn commit(&mut self) {
+        self.flush().expect("csv dbs to be flushed");
+    }
+
+    fn get_file(&mut self, url: &str) -> Option<FileRow> {
+        let id = self.files.ids_by_key.get(&vec![url.to_string()])?;
+        self._file_records([*id]).pop()
+    }
+
+    fn find_files_for_plan(&mut self, plan_id: usize) -> Vec<FileRow> {
+        self._file_records(self._linked_ids(plan_id, true))
+    }
+
+    fn find_plans_for_file(&mut self, file_id: usize) -> Vec<Plan> {
+        self._linked_ids(file_id, false)
+            .into_iter()
+            .filter_map(|id| self.plans.rows.get(&id))
+            .map(|row| Plan {
+                id: row[0].parse().expect("validated on open"),
+                plan_name: row[1].to_string(),
+                plan_id_type: row[2].to_string(),
+                plan_id: row[3].to_string(),
+                plan_market_type: row[4].to_string(),
+            })
+            .collect()
+    }
+
+    fn find_files_for_index_file(&mut self, index_file_id: usize) -> Vec<FileRow> {
+        let index_file_id = index_file_id.to_string();
+        let file_ids: BTreeSet<usize> = self
+            .links
+            .rows
+            .values()
+            .filter(|row| {
+                row[1] == index_file_id
+                    && &row[2] == EntityKind::IndexFile.as_str()
+                    && &row[4] != EntityKind::Plan.as_str()
+            })
+            .filter_map(|row| row[3].parse().ok())
+            .collect();
+        self._file_records(file_ids)
+    }
+
+    fn mark_file_processed(&mut self, file_id: usize) {
+        let file_id = file_id.to_string();
+        self.processed_files
+            .upsert(vec![file_id.clone()], |id| [id.to_string(), file_id]);
+    }
+
+    fn get_file_state(&mut self, file_id: usize) -> Option<FileState> {
+        let id = self
+            .file_states
+            .ids_by_key
+            .get(&vec![file_id.to_string()])?;
+        let row = &self.file_states.rows[id];
+        let optional = |field: &str| (!field.is_empty()).then(|| field.to_string());
+        Some(FileState {
+            file_id,
+            status: row[2].parse().expect("status to be a known file status"),
+            error: optional(&row[3]),
+            bytes_processed: row[4].parse().expect("bytes_processed to be a number"),
+            content_hash: optional(&row[5]),
+            etag: optional(&row[6]),
+            attempts: row[7].parse().expect("attempts to be a number"),
+            last_attempt_at_ms: optional(&row[8])
+                .map(|ms| ms.parse().expect("last_attempt_at_ms to be a number")),
+        })
+    }
+
+    fn set_file_state(&mut self, state: &FileState) {
+        let file_id = state.file_id.to_string();
+        self.file_states.append(vec![file_id.clone()], |id| {
+            [
+                id.to_string(),
+                file_id,
+                state.status.as_str().to_string(),
+                state.error.clone().unwrap_or_default(),
+                state.bytes_processed.to_string(),
+                state.content_hash.clone().unwrap_or_default(),
+                state.etag.clone().unwrap_or_default(),
+                state.attempts.to_string(),
+                state
+                    .last_attempt_at_ms
+                    .map(|ms| ms.to_string())
+                    .unwrap_or_default(),
+            ]
+        });
+    }
+
+    fn list_unprocessed_files(&mut self) -> Vec<FileRow> {
+        let rate_file_ids: BTreeSet<usize> = self
+            .links
+            .rows
+            .values()
+            .flat_map(|row| [(&row[1], &row[2]), (&row[3], &row[4])])
+            .filter(|(_, end_type)| *end_type == EntityKind::RateFile.as_str())
+            .filter(|(end_id, _)| {
+                !self
+                    .processed_files
+                    .ids_by_key
+                    .contains_key(&vec![end_id.to_string()])
+            })
+            .filter_map(|(end_id, _)| end_id.parse().ok())
+            .collect();
+        self._file_records(rate_file_ids)
     }
+}
+
+// the columns of one csv file db
+struct CsvSchema {
+    header: &'static [&'static str],
+    // the columns that make a row unique, in the order upsert keys are given
+    key_columns: &'static [usize],
+    // whether rows are a log of changes, where the last row with a key replaces earlier ones,
+    // rather than the first row with a key being the only one kept
+    last_row_wins: bool,
+    // an older header this file db is upgraded from when it's opened or repaired
+    upgrade: Option<CsvUpgrade>,
+}
+
+// files with `header` get the columns their header is missing filled in by `new_fields`
+struct CsvUpgrade {
+    header: &'static [&'static str],
+    new_fields: fn(&csv::StringRecord) -> Vec<String>,
+}
+
+const FILES: CsvSchema = CsvSchema {
+    header: &[
+        "id",
+        "url",
+        "filename",
+        "reporting_entity_name",
+        "reporting_entity_type",
+        "file_type",
+        "description",
+    ],
+    key_columns: &[1],
+    last_row_wins: false,
+    upgrade: Some(CsvUpgrade {
+        header: &[
+            "id",
+            "url",
+            "filename",
+            "reporting_entity_name",
+            "reporting_entity_type",
+        ],
+        new_fields: _legacy_file_type_and_description,
+    }),
+};
+
+// before files had a type, the index file was the one named "index",
+// allowed amount files were named by their description rather than their url's last segment,
+// and every other file was an in-network file
+fn _legacy_file_type_and_description(row: &csv::StringRecord) -> Vec<String> {
+    let url = row.get(1).unwrap_or_default();
+    let filename = row.get(2).unwrap_or_default();
+    let (file_type, description) = if filename == "index" {
+        (FileType::Index, "")
+    } else if url.rsplit('/').next() != Some(filename) {
+        (FileType::AllowedAmount, filename)
+    } else {
+        (FileType::InNetwork, "")
+    };
+    vec![file_type.as_str().to_string(), description.to_string()]
+}
+
+const LINKS: CsvSchema = CsvSchema {
+    header: &["id", "from_id", "from_type", "to_id", "to_type"],
+    key_columns: &[1, 2, 3, 4],
+    last_row_wins: false,
+    upgrade: None,
+};
+
+const PROCESSED_FILES: CsvSchema = CsvSchema {
+    header: &["id", "file_id"],
+    key_columns: &[1],
+    last_row_wins: false,
+    upgrade: None,
+};
 
-    fn _write_row_to_file_db<InputType, RowType: FromInput<'a, InputType, RowType> + IntoIterator>(
-        &self,
-        db_path: &str,
-        row: &'a mut InputType,
-    ) -> usize
+// a row is appended each time a file's state is set
+const FILE_STATES: CsvSchema = CsvSchema {
+    header: &[
+        "id",
+        "file_id",
+        "status",
+        "error",
+        "bytes_processed",
+        "content_hash",
+        "etag",
+        "attempts",
+        "last_attempt_at_ms",
+    ],
+    key_columns: &[1],
+    last_row_wins: true,
+    upgrade: Some(CsvUpgrade {
+        header: &[
+            "id",
+            "file_id",
+            "status",
+            "error",
+            "bytes_processed",
+            "content_hash",
+            "etag",
+            "attempts",
+        ],
+        new_fields: _unknown_last_attempt,
+    }),
+};
+
+// states recorded before attempts were timed
+fn _unknown_last_attempt(_row: &csv::StringRecord) -> Vec<String> {
+    vec![String::new()]
+}
+
+const PLANS: CsvSchema = CsvSchema {
+    header: &[
+        "id",
+        "plan_name",
+        "plan_id_type",
+        "plan_id",
+        "plan_market_type",
+    ],
+    key_columns: &[2, 3],
+    last_row_wins: false,
+    upgrade: None,
+};
+
+impl CsvSchema {
+    fn key_of(&self, row: &csv::StringRecord) -> Vec<String> {
+        self.key_columns
+            .iter()
+            .map(|i| row[*i].to_string())
+            .collect()
+    }
+}
+
+#[derive(Debug)]
+pub enum CsvDbError {
+    Csv(csv::Error),
+    Io(io::Error),
+    UnexpectedHeader {
+        path: PathBuf,
+        expected: Vec<String>,
+        found: Vec<String>,
+    },
+    MalformedRow {
+        path: PathBuf,
+        line: u64,
+        expected_fields: usize,
+        found_fields: usize,
+    },
+}
+
+impl fmt::Display for CsvDbError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            CsvDbError::Csv(e) => write!(f, "{e}"),
+            CsvDbError::Io(e) => write!(f, "{e}"),
+            CsvDbError::UnexpectedHeader {
+                path,
+                expected,
+                found,
+            } => write!(
+                f,
+                "{} has header {found:?}, expected {expected:?}",
+                path.display()
+            ),
+            CsvDbError::MalformedRow {
+                path,
+                line,
+                expected_fields,
+                found_fields,
+            } => write!(
+                f,
+                "{} line {line} has {found_fields} fields, expected {expected_fields}",
+                path.display()
+            ),
+        }
+    }
+}
+
+impl Error for CsvDbError {}
+
+impl From<csv::Error> for CsvDbError {
+    fn from(e: csv::Error) -> Self {
+        CsvDbError::Csv(e)
+    }
+}
+
+impl From<io::Error> for CsvDbError {
+    fn from(e: io::Error) -> Self {
+        CsvDbError::Io(e)
+    }
+}
+
+#[derive(Debug)]
+pub struct CsvRepairReport {
+    pub path: PathBuf,
+    pub rows_kept: usize,
+    pub malformed_rows_dropped: usize,
+    pub duplicate_rows_dropped: usize,
+}
+
+// a csv file db, read into memory once when opened and only appended to after that
+struct CsvTable {
+    next_id: usize,
+    width: usize,
+    ids_by_key: HashMap<Vec<String>, usize>,
+    rows: BTreeMap<usize, csv::StringRecord>,
+    writer: csv::Writer<File>,
+}
+
+impl CsvTable {
+    fn open(db_path: &Path, schema: &CsvSchema) -> Result<Self, CsvDbError> {
+        if !db_path.exists() || fs::metadata(db_path)?.len() == 0 {
+            let mut writer = csv::Writer::from_path(db_path)?;
+            writer.write_record(schema.header)?;
+            writer.flush()?;
+        }
+        _ensure_trailing_newline(db_path)?;
+        _upgrade_table(db_path, schema)?;
+
+        let mut reader = csv::ReaderBuilder::new()
+            .flexible(true)
+            .from_path(db_path)?;
+        let found = reader.headers()?;
+        if found != schema.header {
+            return Err(CsvDbError::UnexpectedHeader {
+                path: db_path.to_path_buf(),
+                expected: schema.header.iter().map(|h| h.to_string()).collect(),
+                found: found.iter().map(str::to_string).collect(),
+            });
+        }
+
+        // ids come from the largest existing id rather than the row count,
+        // so rows that were removed or skipped don't lead to reused ids
+        let mut max_id = 0;
+        let mut ids_by_key = HashMap::new();
+        let mut rows = BTreeMap::new();
+        for row in reader.records() {
+            let row = row?;
+            let id = _parse_full_width_row(db_path, &row, schema.header.len())?;
+            max_id = max_id.max(id);
+            if schema.last_row_wins {
+                if let Some(replaced_id) = ids_by_key.insert(schema.key_of(&row), id) {
+                    rows.remove(&replaced_id);
+                }
+                rows.insert(id, row);
+            } else {
+                ids_by_key.entry(schema.key_of(&row)).or_insert(id);
+                rows.entry(id).or_insert(row);
+            }
+        }
+
+        let file_db = OpenOptions::new().append(true).open(db_path)?;
+        Ok(CsvTable {
+            next_id: max_id + 1,
+            width: schema.header.len(),
+            ids_by_key,
+            rows,
+            writer: csv::Writer::from_writer(file_db),
+        })
+    }
+
+    fn upsert<RowType>(&mut self, key: Vec<String>, to_row: impl FnOnce(usize) -> RowType) -> usize
     where
+        RowType: IntoIterator,
         <RowType as IntoIterator>::Item: AsRef<[u8]>,
     {
-        let id = self._get_length_of_file_db(db_path) + 1;
+        if let Some(id) = self.ids_by_key.get(&key) {
+            return *id;
+        }
+        self.append(key, to_row)
+    }
 
-        let file_db = OpenOptions::new()
-            .write(true)
-            .append(true)
-            .open(db_path)
-            .unwrap();
+    // writes a new row even if one with `key` exists, which it replaces
+    fn append<RowType>(&mut self, key: Vec<String>, to_row: impl FnOnce(usize) -> RowType) -> usize
+    where
+        RowType: IntoIterator,
+        <RowType as IntoIterator>::Item: AsRef<[u8]>,
+    {
+        let id = self.next_id;
+        let row: csv::ByteRecord = to_row(id).into_iter().collect();
+        assert_eq!(
+            row.len(),
+            self.width,
+            "csv db rows need one field per column"
+        );
+        self.writer
+            .write_byte_record(&row)
+            .expect("row to be written to csv db");
+        self.next_id += 1;
+        if let Some(replaced_id) = self.ids_by_key.insert(key, id) {
+            self.rows.remove(&replaced_id);
+        }
+        self.rows.insert(
+            id,
+            csv::StringRecord::from_byte_record(row).expect("utf-8 csv db row"),
+        );
+        id
+    }
+}
 
-        // todo ensure headers and newline are there
-        let mut csv_writer = csv::Writer::from_writer(file_db);
-        csv_writer
-            .write_record(RowType::from_input(id, row))
-            .unwrap();
-        csv_writer.flush().unwrap();
-        // return id
-        return id;
+// the row's id, if it has exactly `width` fields and starts with one
+fn _parse_full_width_row(
+    db_path: &Path,
+    row: &csv::StringRecord,
+    width: usize,
+) -> Result<usize, CsvDbError> {
+    let malformed = || CsvDbError::MalformedRow {
+        path: db_path.to_path_buf(),
+        line: row.position().map_or(0, |p| p.line()),
+        expected_fields: width,
+        found_fields: row.len(),
+    };
+    if row.len() != width {
+        return Err(malformed());
     }
+    row[0].parse().map_err(|_| malformed())
 }
 
-impl<'a> MetaRepository<'a> for CsvMetaRepository<'a> {
-    fn add_file(&self, file: &'a mut FileRowInput<'a>) -> usize {
-        return self._write_row_to_file_db::<FileRowInput<'a>, FileRow>(self.files_csv_path, file);
+// rewrites a file db that still has its schema's older header with the current one,
+// filling in the new columns of each row. rows of the wrong width are left as they are.
+fn _upgrade_table(db_path: &Path, schema: &CsvSchema) -> Result<(), CsvDbError> {
+    let Some(upgrade) = &schema.upgrade else {
+        return Ok(());
+    };
+    let mut reader = csv::ReaderBuilder::new()
+        .flexible(true)
+        .from_path(db_path)?;
+    if reader.headers()? != upgrade.header {
+        return Ok(());
     }
 
-    fn add_link(&self, link: &'a mut DbLinkInput<'a>) -> usize {
-        return self
-            ._write_row_to_file_db::<DbLinkInput<'a>, DbLink<'a>>(self.links_csv_path, link);
+    let upgraded_path = db_path.with_extension("csv.upgrading");
+    let mut writer = csv::WriterBuilder::new()
+        .flexible(true)
+        .from_path(&upgraded_path)?;
+    writer.write_record(schema.header)?;
+    for row in reader.records() {
+        let row = row?;
+        let mut fields: Vec<String> = row.iter().map(str::to_string).collect();
+        if row.len() == upgrade.header.len() {
+            fields.extend((upgrade.new_fields)(&row));
+        }
+        writer.write_record(&fields)?;
     }
+    writer.flush()?;
+    drop(writer);
+    fs::rename(&upgraded_path, db_path)?;
+    Ok(())
+}
 
-    fn add_plan(&self, plan: &'a mut PlanInput<'a>) -> usize {
-        return self._write_row_to_file_db::<PlanInput<'a>, Plan<'a>>(self.plans_csv_path, plan);
+// a last row without a newline would have the next appended row glued onto it
+fn _ensure_trailing_newline(db_path: &Path) -> io::Result<()> {
+    let mut file = OpenOptions::new().read(true).append(true).open(db_path)?;
+    if file.metadata()?.len() == 0 {
+        return Ok(());
+    }
+    let mut last_byte = [0u8];
+    file.seek(SeekFrom::End(-1))?;
+    file.read_exact(&mut last_byte)?;
+    if last_byte[0] != b'\n' {
+        file.write_all(b"\n")?;
     }
+    Ok(())
 }
 
-pub struct FileRowIterator<'a> {
-    file_row: FileRow<'a>,
+// rewrites `db_path` with only its well-formed, unique rows, after passing each through `remap`.
+// returns the ids of dropped duplicates, mapped to the id of the row that was kept instead,
+// or for tables where the last row wins, the ids of replaced rows mapped to their replacement.
+fn repair_table(
+    db_path: &Path,
+    schema: &CsvSchema,
+    remap: impl Fn(&mut Vec<String>),
+) -> Result<(CsvRepairReport, HashMap<usize, usize>), CsvDbError> {
+    let mut report = CsvRepairReport {
+        path: db_path.to_path_buf(),
+        rows_kept: 0,
+        malformed_rows_dropped: 0,
+        duplicate_rows_dropped: 0,
+    };
+    let mut kept_ids: HashMap<usize, usize> = HashMap::new();
+    if !db_path.exists() {
+        return Ok((report, kept_ids));
+    }
+    _upgrade_table(db_path, schema)?;
+
+    // the header may be missing or wrong too, so read every row as data
+    let mut reader = csv::ReaderBuilder::new()
+        .has_headers(false)
+        .flexible(true)
+        .from_path(db_path)?;
+    let repaired_path = db_path.with_extension("csv.repairing");
+    let mut writer = csv::Writer::from_path(&repaired_path)?;
+    writer.write_record(schema.header)?;
+
+    // rows are held until every duplicate has been seen, in case a later one replaces them
+    let mut kept_rows: Vec<(usize, csv::StringRecord)> = vec![];
+    let mut positions_by_key: HashMap<Vec<String>, usize> = HashMap::new();
+    let mut used_ids: HashSet<usize> = HashSet::new();
+    for (i, row) in reader.records().enumerate() {
+        let row = row?;
+        let Ok(id) = _parse_full_width_row(db_path, &row, schema.header.len()) else {
+            // a header row isn't a malformed one
+            if i > 0 || row.get(0) != Some("id") {
+                report.malformed_rows_dropped += 1;
+            }
+            continue;
+        };
+        if !used_ids.insert(id) {
+            report.malformed_rows_dropped += 1;
+            continue;
+        }
+
+        let mut fields: Vec<String> = row.iter().map(str::to_string).collect();
+        remap(&mut fields);
+        let row = csv::StringRecord::from(fields);
+        let key = schema.key_of(&row);
+        if let Some(position) = positions_by_key.get(&key) {
+            let kept = &mut kept_rows[*position];
+            if schema.last_row_wins {
+                kept_ids.insert(kept.0, id);
+                *kept = (id, row);
+            } else {
+                kept_ids.insert(id, kept.0);
+            }
+            report.duplicate_rows_dropped += 1;
+            continue;
+        }
+        positions_by_key.insert(key, kept_rows.len());
+        kept_rows.push((id, row));
+    }
+
+    for (_, row) in &kept_rows {
+        writer.write_record(row)?;
+    }
+    report.rows_kept = kept_rows.len();
+    writer.flush()?;
+    drop(writer);
+    fs::rename(&repaired_path, db_path)?;
+    Ok((report, kept_ids))
+}
+
+pub struct FileRowIterator {
+    file_row: FileRow,
     index: usize,
 }
 
-impl<'a> Iterator for FileRowIterator<'a> {
+impl Iterator for FileRowIterator {
     type Item = Vec<u8>;
 
     fn next(&mut self) -> Option<Self::Item> {
         let result: Option<Self::Item> = match self.index {
             0 => Some(self.file_row.id.to_string().into_bytes()),
-            1 => Some(self.file_row.url.into()),
-            2 => Some(self.file_row.filename.into()),
-            3 => Some(self.file_row.reporting_entity_name.into()),
-            4 => Some(self.file_row.reporting_entity_type.into()),
+            1 => Some(std::mem::take(&mut self.file_row.url).into_bytes()),
+            2 => Some(std::mem::take(&mut self.file_row.filename).into_bytes()),
+            3 => Some(std::mem::take(&mut self.file_row.reporting_entity_name).into_bytes()),
+            4 => Some(std::mem::take(&mut self.file_row.reporting_entity_type).into_bytes()),
+            5 => Some(self.file_row.file_type.as_str().into()),
+            6 => Some(std::mem::take(&mut self.file_row.description).into_bytes()),
             _ => None,
         };
         self.index += 1;
@@ -85,34 +741,34 @@ impl<'a> Iterator for FileRowIterator<'a> {
     }
 }
 
-impl<'a> IntoIterator for FileRow<'a> {
+impl IntoIterator for FileRow {
     type Item = Vec<u8>;
 
-    type IntoIter = FileRowIterator<'a>;
+    type IntoIter = FileRowIterator;
 
     fn into_iter(self) -> Self::IntoIter {
-        return FileRowIterator {
+        FileRowIterator {
             file_row: self,
             index: 0,
-        };
+        }
     }
 }
 
-pub struct DbLinkIterator<'a> {
-    db_link: DbLink<'a>,
+pub struct DbLinkIterator {
+    db_link: DbLink,
     index: usize,
 }
 
-impl<'a> Iterator for DbLinkIterator<'a> {
+impl Iterator for DbLinkIterator {
     type Item = Vec<u8>;
 
     fn next(&mut self) -> Option<Self::Item> {
         let result: Option<Self::Item> = match self.index {
             0 => Some(self.db_link.id.to_string().into_bytes()),
             1 => Some(self.db_link.from_id.to_string().into_bytes()),
-            2 => Some(self.db_link.from_type.to_string().into_bytes()),
+            2 => Some(self.db_link.from_type.as_str().into()),
             3 => Some(self.db_link.to_id.to_string().into_bytes()),
-            4 => Some(self.db_link.to_type.to_string().into_bytes()),
+            4 => Some(self.db_link.to_type.as_str().into()),
             _ => None,
         };
         self.index += 1;
@@ -120,34 +776,34 @@ impl<'a> Iterator for DbLinkIterator<'a> {
     }
 }
 
-impl<'a> IntoIterator for DbLink<'a> {
+impl IntoIterator for DbLink {
     type Item = Vec<u8>;
 
-    type IntoIter = DbLinkIterator<'a>;
+    type IntoIter = DbLinkIterator;
 
     fn into_iter(self) -> Self::IntoIter {
-        return DbLinkIterator {
-            db_link: self.clone(),
+        DbLinkIterator {
+            db_link: self,
             index: 0,
-        };
+        }
     }
 }
 
-pub struct PlanIterator<'a> {
-    plan: Plan<'a>,
+pub struct PlanIterator {
+    plan: Plan,
     index: usize,
 }
 
-impl<'a> Iterator for PlanIterator<'a> {
+impl Iterator for PlanIterator {
     type Item = Vec<u8>;
 
     fn next(&mut self) -> Option<Self::Item> {
         let result: Option<Self::Item> = match self.index {
             0 => Some(self.plan.id.to_string().into_bytes()),
-            1 => Some(self.plan.plan_name.to_string().into_bytes()),
-            2 => Some(self.plan.plan_id_type.into()),
-            3 => Some(self.plan.plan_id.into()),
-            4 => Some(self.plan.plan_market_type.into()),
+            1 => Some(std::mem::take(&mut self.plan.plan_name).into_bytes()),
+            2 => Some(std::mem::take(&mut self.plan.plan_id_type).into_bytes()),
+            3 => Some(std::mem::take(&mut self.plan.plan_id).into_bytes()),
+            4 => Some(std::mem::take(&mut self.plan.plan_market_type).into_bytes()),
             _ => None,
         };
         self.index += 1;
@@ -155,15 +811,15 @@ impl<'a> Iterator for PlanIterator<'a> {
     }
 }
 
-impl<'a> IntoIterator for Plan<'a> {
+impl IntoIterator for Plan {
     type Item = Vec<u8>;
 
-    type IntoIter = PlanIterator<'a>;
+    type IntoIter = PlanIterator;
 
     fn into_iter(self) -> Self::IntoIter {
-        return PlanIterator {
-            plan: self.clone(),
+        PlanIterator {
+            plan: self,
             index: 0,
-        };
+        }
     }
 }
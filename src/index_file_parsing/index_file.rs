@@ -1,29 +1,249 @@
-use serde::Deserialize;
+use std::{
+    fmt,
+    io::{BufReader, Read},
+    panic,
+    sync::mpsc::{sync_channel, SyncSender},
+    thread::{self, JoinHandle},
+};
 
-use crate::sync_array_serde::channel_deserializer::deserialize_to_channel;
+use serde::{
+    de::{self, DeserializeSeed, IgnoredAny, MapAccess, Visitor},
+    Deserialize, Deserializer,
+};
+
+use crate::sync_array_serde::channel_deserializer::ChannelVisitor;
 use crate::sync_array_serde::channel_generator::ChannelGenerator;
 
-#[derive(Deserialize)]
+// follows the cms table-of-contents schema:
+// https://github.com/CMSgov/price-transparency-guide/tree/master/schemas/table-of-contents
+
+// how many reporting structures are read ahead of the ones being handled
+const STRUCTURES_READ_AHEAD: usize = 16;
+
+// an index file whose reporting structures are read on a background thread as they're iterated,
+// see `IndexFile::from_reader`
 pub struct IndexFile {
     pub reporting_entity_name: String,
     pub reporting_entity_type: String,
-    #[serde(deserialize_with = "deserialize_to_channel")]
-    pub reporting_structure: ChannelGenerator<ReportingStructure>,
+    pub reporting_structure: ChannelGenerator<MaybeReportingStructure>,
+    // the schema version, which older files leave out.
+    // one declared after `reporting_structure` is only returned by `finish`
+    pub version: Option<String>,
+    reading: JoinHandle<serde_json::Result<IndexFileFields>>,
+}
+
+impl IndexFile {
+    // starts reading an index file, returning once its reporting entity is read.
+    // its reporting structures are read as they're iterated, only a few ahead, so a big index
+    // file isn't held in memory. those listed before the reporting entity are the exception,
+    // as they're all read before it.
+    pub fn from_reader<R: Read + Send + 'static>(reader: R) -> serde_json::Result<IndexFile> {
+        let (fields_sender, fields_receiver) = sync_channel(1);
+        let (sender, receiver) = sync_channel(STRUCTURES_READ_AHEAD);
+        let reading = thread::spawn(move || {
+            let mut deserializer = serde_json::Deserializer::from_reader(BufReader::new(reader));
+            let seed = IndexFileSeed {
+                fields_sender: Some(fields_sender),
+                sender,
+            };
+            let fields = seed.deserialize(&mut deserializer)?;
+            deserializer.end()?;
+            Ok(fields)
+        });
+        let Ok(fields) = fields_receiver.recv() else {
+            // the file failed before its reporting entity was read
+            return Err(join(reading).expect_err("a file without its fields to fail"));
+        };
+        Ok(IndexFile {
+            reporting_entity_name: fields.reporting_entity_name,
+            reporting_entity_type: fields.reporting_entity_type,
+            reporting_structure: ChannelGenerator { receiver },
+            version: fields.version,
+            reading,
+        })
+    }
+
+    // waits for the rest of the file to be read, failing if it isn't well formed,
+    // and returns its version. call it once the reporting structures have been iterated.
+    pub fn finish(self) -> serde_json::Result<Option<String>> {
+        // the structures aren't needed anymore, so don't make the reader wait to send them
+        drop(self.reporting_structure);
+        join(self.reading).map(|fields| fields.version)
+    }
+}
+
+fn join<T>(reading: JoinHandle<T>) -> T {
+    reading
+        .join()
+        .unwrap_or_else(|panicked| panic::resume_unwind(panicked))
+}
+
+#[derive(Clone, Debug)]
+struct IndexFileFields {
+    reporting_entity_name: String,
+    reporting_entity_type: String,
+    version: Option<String>,
+}
+
+// reads an index file, sending its fields once the reporting entity is known,
+// and then each reporting structure. returns the fields once it's read it all.
+struct IndexFileSeed {
+    fields_sender: Option<SyncSender<IndexFileFields>>,
+    sender: SyncSender<MaybeReportingStructure>,
+}
+
+impl IndexFileSeed {
+    fn send_fields(&mut self, fields: &IndexFileFields) {
+        if let Some(fields_sender) = self.fields_sender.take() {
+            // it's only not received when the file's been given up on
+            let _ = fields_sender.send(fields.clone());
+        }
+    }
+}
+
+impl<'de> DeserializeSeed<'de> for IndexFileSeed {
+    type Value = IndexFileFields;
+
+    fn deserialize<D>(self, deserializer: D) -> Result<Self::Value, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        deserializer.deserialize_map(self)
+    }
+}
+
+impl<'de> Visitor<'de> for IndexFileSeed {
+    type Value = IndexFileFields;
+
+    fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+        formatter.write_str("an index file")
+    }
+
+    fn visit_map<M>(mut self, mut map: M) -> Result<Self::Value, M::Error>
+    where
+        M: MapAccess<'de>,
+    {
+        let mut name: Option<String> = None;
+        let mut type_: Option<String> = None;
+        let mut version: Option<String> = None;
+        // the structures listed before the reporting entity, which can only be sent after it
+        let mut early_structures: Option<Vec<MaybeReportingStructure>> = None;
+        let mut has_structures = false;
+        while let Some(key) = map.next_key::<String>()? {
+            match key.as_str() {
+                "reporting_entity_name" => name = Some(map.next_value()?),
+                "reporting_entity_type" => type_ = Some(map.next_value()?),
+                "version" => version = map.next_value()?,
+                "reporting_structure" => {
+                    has_structures = true;
+                    match (&name, &type_) {
+                        (Some(name), Some(type_)) => {
+                            self.send_fields(&IndexFileFields {
+                                reporting_entity_name: name.clone(),
+                                reporting_entity_type: type_.clone(),
+                                version: version.clone(),
+                            });
+                            map.next_value_seed(ChannelVisitor::new(self.sender.clone()))?;
+                        }
+                        _ => early_structures = Some(map.next_value()?),
+                    }
+                }
+                _ => {
+                    map.next_value::<IgnoredAny>()?;
+                }
+            }
+        }
+
+        let fields = IndexFileFields {
+            reporting_entity_name: name
+                .ok_or_else(|| de::Error::missing_field("reporting_entity_name"))?,
+            reporting_entity_type: type_
+                .ok_or_else(|| de::Error::missing_field("reporting_entity_type"))?,
+            version,
+        };
+        if !has_structures {
+            return Err(de::Error::missing_field("reporting_structure"));
+        }
+        self.send_fields(&fields);
+        for structure in early_structures.into_iter().flatten() {
+            if self.sender.send(structure).is_err() {
+                break;
+            }
+        }
+        Ok(fields)
+    }
+}
+
+// a reporting structure, or why it doesn't match the schema,
+// so one malformed structure doesn't stop the rest of the index file from being recorded
+#[derive(Debug)]
+pub struct MaybeReportingStructure(pub Result<ReportingStructure, String>);
+
+impl<'de> Deserialize<'de> for MaybeReportingStructure {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        // json that isn't well formed still fails the whole file
+        let value = serde_json::Value::deserialize(deserializer)?;
+        let structure = serde_json::from_value(value).map_err(|e| e.to_string());
+        Ok(MaybeReportingStructure(structure))
+    }
 }
 
+// a structure may list only in-network files, only an allowed amount file, or both
 #[derive(Deserialize, Debug)]
 pub struct ReportingStructure {
     pub reporting_plans: Vec<ReportingPlan>,
+    #[serde(default)]
     pub in_network_files: Vec<LinkedFile>,
-    pub allowed_amount_file: LinkedFile,
+    pub allowed_amount_file: Option<LinkedFile>,
 }
 
 #[derive(Deserialize, Debug)]
 pub struct ReportingPlan {
     pub plan_name: String,
-    pub plan_id_type: String, // TODO: enum
+    pub plan_id_type: PlanIdType,
     pub plan_id: String,
-    pub plan_market_type: String, // TODO: enum
+    pub plan_market_type: PlanMarketType,
+    pub plan_sponsor_name: Option<String>,
+    pub issuer_name: Option<String>,
+}
+
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PlanIdType {
+    #[serde(rename = "EIN", alias = "ein")]
+    Ein,
+    #[serde(rename = "HIOS", alias = "hios")]
+    Hios,
+}
+
+impl PlanIdType {
+    /// lowercase, the spelling plans were stored with before this was an enum,
+    /// so plans already in a repository are still matched
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            PlanIdType::Ein => "ein",
+            PlanIdType::Hios => "hios",
+        }
+    }
+}
+
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PlanMarketType {
+    #[serde(rename = "group", alias = "Group")]
+    Group,
+    #[serde(rename = "individual", alias = "Individual")]
+    Individual,
+}
+
+impl PlanMarketType {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            PlanMarketType::Group => "group",
+            PlanMarketType::Individual => "individual",
+        }
+    }
 }
 
 #[derive(Deserialize, Debug)]
@@ -31,3 +251,109 @@ pub struct LinkedFile {
     pub description: String,
     pub location: String, // URL
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn it_defaults_missing_files() {
+        let structure: ReportingStructure = serde_json::from_str(
+            r#"{"reporting_plans": [{
+                "plan_name": "plan1",
+                "plan_id_type": "hios",
+                "plan_id": "1111111111",
+                "plan_market_type": "individual"
+            }]}"#,
+        )
+        .unwrap();
+
+        assert!(structure.in_network_files.is_empty());
+        assert!(structure.allowed_amount_file.is_none());
+        assert_eq!(structure.reporting_plans[0].plan_id_type, PlanIdType::Hios);
+        assert_eq!(structure.reporting_plans[0].plan_sponsor_name, None);
+    }
+
+    #[test]
+    fn it_rejects_unknown_plan_id_types() {
+        let plan = serde_json::from_str::<ReportingPlan>(
+            r#"{
+                "plan_name": "plan1",
+                "plan_id_type": "ssn",
+                "plan_id": "1111111111",
+                "plan_market_type": "group"
+            }"#,
+        );
+
+        assert!(plan.is_err());
+    }
+
+    #[test]
+    fn it_keeps_the_error_of_a_malformed_structure() {
+        let structures: Vec<MaybeReportingStructure> =
+            serde_json::from_str(r#"[{"reporting_plans": []}, {"in_network_files": []}]"#).unwrap();
+
+        assert!(structures[0].0.is_ok());
+        let error = structures[1].0.as_ref().unwrap_err();
+        assert!(error.contains("reporting_plans"), "{error}");
+    }
+
+    fn index_file_with(fields_before: &str, structures: usize, fields_after: &str) -> Vec<u8> {
+        let structures = vec![r#"{"reporting_plans": []}"#; structures].join(", ");
+        format!(r#"{{{fields_before} "reporting_structure": [{structures}]{fields_after}}}"#)
+            .into_bytes()
+    }
+
+    #[test]
+    fn it_reads_more_structures_than_it_reads_ahead() {
+        let entity = r#""reporting_entity_name": "medicare", "reporting_entity_type": "medicare""#;
+        let structures = STRUCTURES_READ_AHEAD * 4;
+        for json in [
+            index_file_with(&format!("{entity},"), structures, r#", "version": "1.0.0""#),
+            // the reporting entity after the structures
+            index_file_with("", structures, &format!(", {entity}")),
+        ] {
+            let mut index_file = IndexFile::from_reader(std::io::Cursor::new(json)).unwrap();
+            assert_eq!(index_file.reporting_entity_name, "medicare");
+            assert_eq!(index_file.reporting_structure.by_ref().count(), structures);
+            index_file.finish().unwrap();
+        }
+    }
+
+    #[test]
+    fn it_returns_a_version_declared_after_the_structures() {
+        let json = index_file_with(
+            r#""reporting_entity_name": "medicare", "reporting_entity_type": "medicare","#,
+            1,
+            r#", "version": "1.0.0""#,
+        );
+        let index_file = IndexFile::from_reader(std::io::Cursor::new(json)).unwrap();
+
+        assert_eq!(index_file.version, None);
+        assert_eq!(index_file.finish().unwrap().as_deref(), Some("1.0.0"));
+    }
+
+    #[test]
+    fn it_fails_on_json_that_isnt_well_formed() {
+        let entity = r#""reporting_entity_name": "medicare", "reporting_entity_type": "medicare","#;
+        let mut json = index_file_with(entity, STRUCTURES_READ_AHEAD * 2, "");
+        // the last structure is cut short
+        json.truncate(json.len() - 4);
+
+        let mut index_file = IndexFile::from_reader(std::io::Cursor::new(json)).unwrap();
+        assert_eq!(
+            index_file.reporting_structure.by_ref().count(),
+            STRUCTURES_READ_AHEAD * 2 - 1
+        );
+        assert!(index_file.finish().is_err());
+
+        let missing_entity = index_file_with("", 1, "");
+        let error = IndexFile::from_reader(std::io::Cursor::new(missing_entity))
+            .err()
+            .unwrap();
+        assert!(
+            error.to_string().contains("reporting_entity_name"),
+            "{error}"
+        );
+    }
+}
@@ -1,107 +1,392 @@
-pub trait MetaRepository<'a> {
-    fn add_file(&self, file: &'a mut FileRowInput<'a>) -> usize;
-    fn add_link(&self, link: &'a mut DbLinkInput<'a>) -> usize;
-    fn add_plan(&self, plan: &'a mut PlanInput<'a>) -> usize;
+use std::{fmt, str::FromStr};
+
+/// every `add_*` is an upsert: adding a row that's already stored
+/// returns the existing row's id instead of writing a duplicate.
+pub trait MetaRepository {
+    /// files are unique by `url`
+    fn add_file(&mut self, file: &FileRowInput) -> usize;
+    /// links are unique by both of their ends
+    fn add_link(&mut self, link: &DbLinkInput) -> usize;
+    /// plans are unique by `(plan_id_type, plan_id)`
+    fn add_plan(&mut self, plan: &PlanInput) -> usize;
+
+    /// called once everything from an index file has been added.
+    /// repositories that buffer or batch their writes persist them here.
+    fn commit(&mut self) {}
+    /// the id a row added before the last `commit` was stored with.
+    /// repositories that only find already stored rows on commit
+    /// can hand out ids from `add_*` that end up replaced by the stored row's.
+    fn committed_id(&self, _kind: EntityKind, id: usize) -> usize {
+        id
+    }
+
+    /// the file stored with this url
+    fn get_file(&mut self, url: &str) -> Option<FileRow>;
+    /// files linked to or from the plan, ordered by id
+    fn find_files_for_plan(&mut self, plan_id: usize) -> Vec<FileRow>;
+    /// plans linked to or from the file, ordered by id
+    fn find_plans_for_file(&mut self, file_id: usize) -> Vec<Plan>;
+    /// files the index file links to, ordered by id
+    fn find_files_for_index_file(&mut self, index_file_id: usize) -> Vec<FileRow>;
+
+    fn mark_file_processed(&mut self, file_id: usize);
+    /// files linked to as a `RateFile` that haven't been marked processed, ordered by id
+    fn list_unprocessed_files(&mut self) -> Vec<FileRow>;
+    /// the crawl state last recorded for the file, if any
+    fn get_file_state(&mut self, file_id: usize) -> Option<FileState>;
+    /// records the file's crawl state, replacing any earlier one
+    fn set_file_state(&mut self, state: &FileState);
+
+    /// the unprocessed files of one type, e.g. only in-network files, ordered by id
+    fn list_unprocessed_files_of_type(&mut self, file_type: FileType) -> Vec<FileRow> {
+        self.list_unprocessed_files()
+            .into_iter()
+            .filter(|file| file.file_type == file_type)
+            .collect()
+    }
+}
+
+/// what the id at either end of a link refers to.
+/// stored as its `as_str` name, e.g. `index_file`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum EntityKind {
+    IndexFile,
+    Plan,
+    RateFile,
+}
+
+impl EntityKind {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            EntityKind::IndexFile => "index_file",
+            EntityKind::Plan => "plan",
+            EntityKind::RateFile => "rate_file",
+        }
+    }
+
+    /// whether ids of this kind are in the files table, rather than plans
+    pub fn is_file(&self) -> bool {
+        *self != EntityKind::Plan
+    }
+}
+
+impl fmt::Display for EntityKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub struct UnknownEntityKind(pub String);
+
+impl fmt::Display for UnknownEntityKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "unknown entity kind {:?}", self.0)
+    }
+}
+
+impl std::error::Error for UnknownEntityKind {}
+
+impl FromStr for EntityKind {
+    type Err = UnknownEntityKind;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "index_file" => Ok(EntityKind::IndexFile),
+            "plan" => Ok(EntityKind::Plan),
+            "rate_file" => Ok(EntityKind::RateFile),
+            _ => Err(UnknownEntityKind(s.to_string())),
+        }
+    }
+}
+
+/// what's in a file, stored in its row's `file_type` column as its `as_str` name
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum FileType {
+    /// a table of contents, listing the other files
+    Index,
+    InNetwork,
+    AllowedAmount,
+    ProviderReference,
+}
+
+impl FileType {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            FileType::Index => "index",
+            FileType::InNetwork => "in_network",
+            FileType::AllowedAmount => "allowed_amount",
+            FileType::ProviderReference => "provider_reference",
+        }
+    }
+}
+
+impl fmt::Display for FileType {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub struct UnknownFileType(pub String);
+
+impl fmt::Display for UnknownFileType {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "unknown file type {:?}", self.0)
+    }
+}
+
+impl std::error::Error for UnknownFileType {}
+
+impl FromStr for FileType {
+    type Err = UnknownFileType;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "index" => Ok(FileType::Index),
+            "in_network" => Ok(FileType::InNetwork),
+            "allowed_amount" => Ok(FileType::AllowedAmount),
+            "provider_reference" => Ok(FileType::ProviderReference),
+            _ => Err(UnknownFileType(s.to_string())),
+        }
+    }
+}
+
+/// where a file is in being crawled, stored as its `as_str` name
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, serde::Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum FileStatus {
+    Pending,
+    Downloading,
+    Parsed,
+    Failed,
+}
+
+impl FileStatus {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            FileStatus::Pending => "pending",
+            FileStatus::Downloading => "downloading",
+            FileStatus::Parsed => "parsed",
+            FileStatus::Failed => "failed",
+        }
+    }
 }
 
-pub trait FromInput<'a, I, O> {
-    fn from_input(id: usize, item: &'a I) -> O;
+impl fmt::Display for FileStatus {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
 }
 
-#[derive(Clone)]
-pub struct FileRowInput<'a> {
-    pub url: &'a str,
-    pub filename: &'a str,
-    pub reporting_entity_name: &'a str,
-    pub reporting_entity_type: &'a str,
+#[derive(Debug, PartialEq, Eq)]
+pub struct UnknownFileStatus(pub String);
+
+impl fmt::Display for UnknownFileStatus {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "unknown file status {:?}", self.0)
+    }
+}
+
+impl std::error::Error for UnknownFileStatus {}
+
+impl FromStr for FileStatus {
+    type Err = UnknownFileStatus;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "pending" => Ok(FileStatus::Pending),
+            "downloading" => Ok(FileStatus::Downloading),
+            "parsed" => Ok(FileStatus::Parsed),
+            "failed" => Ok(FileStatus::Failed),
+            _ => Err(UnknownFileStatus(s.to_string())),
+        }
+    }
+}
+
+/// how far crawling a file got, so an interrupted crawl can pick up where it left off
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct FileState {
+    pub file_id: usize,
+    pub status: FileStatus,
+    /// why the last attempt failed
+    pub error: Option<String>,
+    /// how much of the file the last attempt read, so far while it's still downloading
+    pub bytes_processed: u64,
+    /// hex sha-256 of the file's contents, once it's been read in full
+    pub content_hash: Option<String>,
+    pub etag: Option<String>,
+    /// how many times crawling the file has been started
+    pub attempts: u32,
+    /// when the last attempt started, in milliseconds since the unix epoch
+    pub last_attempt_at_ms: Option<u64>,
+}
+
+impl FileState {
+    pub fn pending(file_id: usize) -> Self {
+        FileState {
+            file_id,
+            status: FileStatus::Pending,
+            error: None,
+            bytes_processed: 0,
+            content_hash: None,
+            etag: None,
+            attempts: 0,
+            last_attempt_at_ms: None,
+        }
+    }
 }
 
-#[derive(Clone, Copy)]
-pub struct FileRow<'a> {
+/// what a link between two rows means, from the kinds at its ends
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum LinkKind {
+    /// an index file listing a rate file
+    IndexFileToRateFile,
+    /// a plan whose rates are in a rate file
+    PlanToRateFile,
+    Other(EntityKind, EntityKind),
+}
+
+impl LinkKind {
+    pub fn new(from_type: EntityKind, to_type: EntityKind) -> Self {
+        match (from_type, to_type) {
+            (EntityKind::IndexFile, EntityKind::RateFile) => LinkKind::IndexFileToRateFile,
+            (EntityKind::Plan, EntityKind::RateFile) => LinkKind::PlanToRateFile,
+            (from_type, to_type) => LinkKind::Other(from_type, to_type),
+        }
+    }
+
+    /// the kinds at the (from, to) ends of this kind of link
+    pub fn ends(&self) -> (EntityKind, EntityKind) {
+        match self {
+            LinkKind::IndexFileToRateFile => (EntityKind::IndexFile, EntityKind::RateFile),
+            LinkKind::PlanToRateFile => (EntityKind::Plan, EntityKind::RateFile),
+            LinkKind::Other(from_type, to_type) => (*from_type, *to_type),
+        }
+    }
+}
+
+pub trait FromInput<I> {
+    fn from_input(id: usize, item: &I) -> Self;
+}
+
+#[derive(Clone, Debug)]
+pub struct FileRowInput {
+    pub url: String,
+    pub filename: String,
+    pub reporting_entity_name: String,
+    pub reporting_entity_type: String,
+    pub file_type: FileType,
+    /// the description the index file gave the file, empty for the index file itself
+    pub description: String,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct FileRow {
     pub id: usize,
-    pub url: &'a str,
-    pub filename: &'a str,
-    pub reporting_entity_name: &'a str,
-    pub reporting_entity_type: &'a str,
+    pub url: String,
+    pub filename: String,
+    pub reporting_entity_name: String,
+    pub reporting_entity_type: String,
+    pub file_type: FileType,
+    pub description: String,
 }
 
-impl<'a> FromInput<'a, FileRowInput<'a>, FileRow<'a>> for FileRow<'a> {
-    fn from_input(id: usize, file: &'a FileRowInput<'a>) -> FileRow {
-        return FileRow {
+impl FromInput<FileRowInput> for FileRow {
+    fn from_input(id: usize, file: &FileRowInput) -> FileRow {
+        FileRow {
             id,
-            url: file.url,
-            filename: file.filename,
-            reporting_entity_name: file.reporting_entity_name,
-            reporting_entity_type: file.reporting_entity_type,
-        };
+            url: file.url.clone(),
+            filename: file.filename.clone(),
+            reporting_entity_name: file.reporting_entity_name.clone(),
+            reporting_entity_type: file.reporting_entity_type.clone(),
+            file_type: file.file_type,
+            description: file.description.clone(),
+        }
     }
 }
 
-#[derive(Clone)]
-pub struct DbLinkInput<'a> {
+#[derive(Clone, Copy, Debug)]
+pub struct DbLinkInput {
     pub from_id: usize,
-    pub from_type: &'a str,
+    pub from_type: EntityKind,
     pub to_id: usize,
-    pub to_type: &'a str,
+    pub to_type: EntityKind,
 }
 
-#[derive(Clone, Copy)]
-pub struct DbLink<'a> {
+impl DbLinkInput {
+    pub fn new(kind: LinkKind, from_id: usize, to_id: usize) -> Self {
+        let (from_type, to_type) = kind.ends();
+        DbLinkInput {
+            from_id,
+            from_type,
+            to_id,
+            to_type,
+        }
+    }
+
+    pub fn kind(&self) -> LinkKind {
+        LinkKind::new(self.from_type, self.to_type)
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct DbLink {
     pub id: usize,
     pub from_id: usize,
-    pub from_type: &'a str,
+    pub from_type: EntityKind,
     pub to_id: usize,
-    pub to_type: &'a str,
+    pub to_type: EntityKind,
 }
 
-impl<'a> FromInput<'a, DbLinkInput<'a>, DbLink<'a>> for DbLink<'a> {
-    fn from_input(id: usize, link: &'a DbLinkInput) -> DbLink<'a> {
-        return DbLink {
+impl FromInput<DbLinkInput> for DbLink {
+    fn from_input(id: usize, link: &DbLinkInput) -> DbLink {
+        DbLink {
             id,
             from_id: link.from_id,
             from_type: link.from_type,
             to_id: link.to_id,
             to_type: link.to_type,
-        };
+        }
     }
 }
 
-#[derive(Clone)]
-pub struct PlanInput<'a> {
-    pub plan_name: &'a str,
-    pub plan_id_type: &'a str,
-    pub plan_market_type: &'a str,
-    pub plan_id: &'a str,
+#[derive(Clone, Debug)]
+pub struct PlanInput {
+    pub plan_name: String,
+    pub plan_id_type: String,
+    pub plan_market_type: String,
+    pub plan_id: String,
 }
 
-impl<'a> PlanInput<'a> {
-    pub(crate) fn from_reporting_plan(plan: &'a super::index_file::ReportingPlan) -> PlanInput<'a> {
+impl PlanInput {
+    pub(crate) fn from_reporting_plan(plan: &super::index_file::ReportingPlan) -> PlanInput {
         PlanInput {
-            plan_name: &plan.plan_name,
-            plan_id_type: &plan.plan_id_type,
-            plan_market_type: &plan.plan_market_type,
-            plan_id: &plan.plan_id,
+            plan_name: plan.plan_name.clone(),
+            plan_id_type: plan.plan_id_type.as_str().to_string(),
+            plan_market_type: plan.plan_market_type.as_str().to_string(),
+            plan_id: plan.plan_id.clone(),
         }
     }
 }
 
-#[derive(Clone, Copy)]
-pub struct Plan<'a> {
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Plan {
     pub id: usize,
-    pub plan_name: &'a str,
-    pub plan_id_type: &'a str,
-    pub plan_market_type: &'a str,
-    pub plan_id: &'a str,
+    pub plan_name: String,
+    pub plan_id_type: String,
+    pub plan_market_type: String,
+    pub plan_id: String,
 }
 
-impl<'a> FromInput<'a, PlanInput<'a>, Plan<'a>> for Plan<'a> {
-    fn from_input(id: usize, plan: &'a PlanInput) -> Plan<'a> {
-        return Plan {
+impl FromInput<PlanInput> for Plan {
+    fn from_input(id: usize, plan: &PlanInput) -> Plan {
+        Plan {
             id,
-            plan_name: plan.plan_name,
-            plan_id_type: plan.plan_id_type,
-            plan_market_type: plan.plan_market_type,
-            plan_id: plan.plan_id,
-        };
+            plan_name: plan.plan_name.clone(),
+            plan_id_type: plan.plan_id_type.clone(),
+            plan_market_type: plan.plan_market_type.clone(),
+            plan_id: plan.plan_id.clone(),
+        }
     }
 }
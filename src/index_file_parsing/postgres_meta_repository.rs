@@ -0,0 +1,620 @@
+use std::{collections::HashMap, io::Write};
+
+use postgres::{types::ToSql, Client, Row};
+
+use super::meta_repository_trait::{
+    DbLink, DbLinkInput, EntityKind, FileRow, FileRowInput, FileState, FromInput, MetaRepository,
+    Plan, PlanInput,
+};
+
+// applied once each, in order, and recorded in `schema_migrations`.
+// only ever append to this list, so existing databases can be migrated forward.
+const MIGRATIONS: &[&str] = &[
+    CREATE_FILES_PLANS_AND_LINKS,
+    UNIQUE_FILES_PLANS_AND_LINKS,
+    CREATE_PROCESSED_FILES,
+    ADD_FILE_TYPE_AND_DESCRIPTION,
+    CREATE_FILE_STATES,
+    ADD_LAST_ATTEMPT_AT,
+    CLASSIFY_LEGACY_ALLOWED_AMOUNT_FILES,
+];
+
+const CREATE_FILES_PLANS_AND_LINKS: &str = "CREATE TABLE files (
+    id BIGINT GENERATED BY DEFAULT AS IDENTITY PRIMARY KEY,
+    url TEXT NOT NULL,
+    filename TEXT NOT NULL,
+    reporting_entity_name TEXT NOT NULL,
+    reporting_entity_type TEXT NOT NULL
+);
+CREATE INDEX files_url_idx ON files (url);
+
+CREATE TABLE plans (
+    id BIGINT GENERATED BY DEFAULT AS IDENTITY PRIMARY KEY,
+    plan_name TEXT NOT NULL,
+    plan_id_type TEXT NOT NULL,
+    plan_id TEXT NOT NULL,
+    plan_market_type TEXT NOT NULL
+);
+CREATE INDEX plans_plan_id_idx ON plans (plan_id_type, plan_id);
+
+CREATE TABLE links (
+    id BIGINT GENERATED BY DEFAULT AS IDENTITY PRIMARY KEY,
+    from_id BIGINT NOT NULL,
+    from_type TEXT NOT NULL,
+    to_id BIGINT NOT NULL,
+    to_type TEXT NOT NULL
+);
+CREATE INDEX links_from_idx ON links (from_type, from_id);
+CREATE INDEX links_to_idx ON links (to_type, to_id);
+
+-- a link's ends point at either a plan or a file depending on its type,
+-- which a plain REFERENCES clause can't express, so check them here instead.
+CREATE FUNCTION links_check_references() RETURNS trigger AS $$
+BEGIN
+    IF NOT EXISTS (
+            SELECT 1 FROM plans WHERE NEW.from_type = 'plan' AND id = NEW.from_id
+            UNION ALL
+            SELECT 1 FROM files WHERE NEW.from_type <> 'plan' AND id = NEW.from_id
+        )
+        OR NOT EXISTS (
+            SELECT 1 FROM plans WHERE NEW.to_type = 'plan' AND id = NEW.to_id
+            UNION ALL
+            SELECT 1 FROM files WHERE NEW.to_type <> 'plan' AND id = NEW.to_id
+        )
+    THEN
+        RAISE foreign_key_violation USING MESSAGE = 'link references a missing row';
+    END IF;
+    RETURN NEW;
+END;
+$$ LANGUAGE plpgsql;
+
+CREATE TRIGGER links_references_existing_rows
+BEFORE INSERT ON links
+FOR EACH ROW EXECUTE FUNCTION links_check_references();";
+
+// rows are upserted on these keys, see `MetaRepository`
+const UNIQUE_FILES_PLANS_AND_LINKS: &str = "DROP INDEX files_url_idx;
+CREATE UNIQUE INDEX files_url_key ON files (url);
+
+DROP INDEX plans_plan_id_idx;
+CREATE UNIQUE INDEX plans_plan_id_key ON plans (plan_id_type, plan_id);
+
+DROP INDEX links_from_idx;
+CREATE UNIQUE INDEX links_from_to_key ON links (from_type, from_id, to_type, to_id);";
+
+const CREATE_PROCESSED_FILES: &str = "CREATE TABLE processed_files (
+    file_id BIGINT PRIMARY KEY REFERENCES files (id)
+);";
+
+// before files had a type, the index file was the one named "index".
+// allowed amount files were named by their description, see CLASSIFY_LEGACY_ALLOWED_AMOUNT_FILES
+const ADD_FILE_TYPE_AND_DESCRIPTION: &str =
+    "ALTER TABLE files ADD COLUMN file_type TEXT NOT NULL DEFAULT 'in_network';
+ALTER TABLE files ADD COLUMN description TEXT NOT NULL DEFAULT '';
+UPDATE files SET file_type = 'index' WHERE filename = 'index';";
+
+const CREATE_FILE_STATES: &str = "CREATE TABLE file_states (
+    file_id BIGINT PRIMARY KEY REFERENCES files (id),
+    status TEXT NOT NULL,
+    error TEXT,
+    bytes_processed BIGINT NOT NULL,
+    content_hash TEXT,
+    etag TEXT,
+    attempts BIGINT NOT NULL
+);";
+
+const ADD_LAST_ATTEMPT_AT: &str = "ALTER TABLE file_states ADD COLUMN last_attempt_at_ms BIGINT;";
+
+// legacy files named by their description rather than their url's last segment were allowed amount files.
+// also repairs databases that went through ADD_FILE_TYPE_AND_DESCRIPTION when it took them as in-network files
+const CLASSIFY_LEGACY_ALLOWED_AMOUNT_FILES: &str =
+    "UPDATE files SET file_type = 'allowed_amount', description = filename
+WHERE file_type = 'in_network' AND description = ''
+    AND url <> filename AND right(url, length(filename) + 1) <> '/' || filename;";
+
+const FILE_COLUMNS: &str =
+    "id, url, filename, reporting_entity_name, reporting_entity_type, file_type, description";
+const PLAN_COLUMNS: &str = "id, plan_name, plan_id_type, plan_id, plan_market_type";
+const LINK_COLUMNS: &str = "id, from_id, from_type, to_id, to_type";
+
+// how many ids to reserve from a table's sequence per round trip
+const ID_BLOCK_SIZE: i64 = 1000;
+
+// ids handed out by a table's identity sequence, ahead of the rows being inserted.
+// ids handed out since the last commit are handed out again after a rollback,
+// reserved ids that never get committed otherwise are left as gaps, like any other sequence.
+struct IdBlock {
+    table: &'static str,
+    ids: std::vec::IntoIter<i64>,
+    handed_out: Vec<i64>,
+}
+
+impl IdBlock {
+    fn new(table: &'static str) -> Self {
+        IdBlock {
+            table,
+            ids: vec![].into_iter(),
+            handed_out: vec![],
+        }
+    }
+
+    fn next_id(&mut self, client: &mut Client) -> usize {
+        if self.ids.as_slice().is_empty() {
+            let ids: Vec<i64> = client
+                .query(
+                    "SELECT nextval(pg_get_serial_sequence($1, 'id')) FROM generate_series(1, $2::bigint)",
+                    &[&self.table, &ID_BLOCK_SIZE],
+                )
+                .expect("ids to be reserved")
+                .iter()
+                .map(|row| row.get(0))
+                .collect();
+            self.ids = ids.into_iter();
+        }
+        let id = self.ids.next().expect("a reserved id");
+        self.handed_out.push(id);
+        id as usize
+    }
+
+    fn commit(&mut self) {
+        self.handed_out.clear();
+    }
+
+    fn rollback(&mut self) {
+        let ids: Vec<i64> = self.handed_out.drain(..).chain(&mut self.ids).collect();
+        self.ids = ids.into_iter();
+    }
+}
+
+// rows encoded as csv, to be copied into postgres
+fn to_csv<R>(rows: impl IntoIterator<Item = R>) -> Vec<u8>
+where
+    R: IntoIterator,
+    R::Item: AsRef<[u8]>,
+{
+    let mut writer = csv::WriterBuilder::new()
+        .has_headers(false)
+        .from_writer(vec![]);
+    for row in rows {
+        writer.write_record(row).expect("row to be encoded");
+    }
+    writer.into_inner().expect("encoded rows")
+}
+
+// copies rows into a temporary copy of `table`, then inserts those whose unique `key`
+// isn't taken yet. returns the ids of the rows that were already stored,
+// by the ids they were copied with, so everything is resolved in a few statements per table.
+fn copy_and_resolve(
+    tx: &mut postgres::Transaction,
+    table: &str,
+    columns: &str,
+    key: &[&str],
+    rows: &[u8],
+) -> Result<HashMap<usize, usize>, postgres::Error> {
+    if rows.is_empty() {
+        return Ok(HashMap::new());
+    }
+    let pending = format!("pending_{table}");
+    tx.batch_execute(&format!(
+        "CREATE TEMPORARY TABLE {pending} ON COMMIT DROP AS
+        SELECT {columns} FROM {table} WITH NO DATA"
+    ))?;
+
+    // csv copies read empty fields as NULL, but none of these columns are nullable,
+    // so empty fields like a file's missing description are empty strings
+    let mut copy = tx.copy_in(&format!(
+        "COPY {pending} ({columns}) FROM STDIN (FORMAT csv, FORCE_NOT_NULL ({columns}))"
+    ))?;
+    copy.write_all(rows).expect("rows to be sent to postgres");
+    copy.finish()?;
+
+    tx.execute(
+        &format!(
+            "INSERT INTO {table} ({columns}) SELECT {columns} FROM {pending}
+            ON CONFLICT ({}) DO NOTHING",
+            key.join(", ")
+        ),
+        &[],
+    )?;
+    let same_key: Vec<String> = key.iter().map(|c| format!("t.{c} = p.{c}")).collect();
+    Ok(tx
+        .query(
+            &format!(
+                "SELECT p.id, t.id FROM {pending} p JOIN {table} t ON {} WHERE t.id <> p.id",
+                same_key.join(" AND ")
+            ),
+            &[],
+        )?
+        .iter()
+        .map(|row| (row.get::<_, i64>(0) as usize, row.get::<_, i64>(1) as usize))
+        .collect())
+}
+
+/// buffers every row added for an index file and writes them
+/// all with `COPY` inside a single transaction on `commit`.
+/// ids are reserved from each table's sequence up front,
+/// so they can be returned before the rows are written.
+/// rows already in the database are only found on commit, in one query per table,
+/// and keep their existing id: see `committed_id` for the ids they were added with.
+/// rows already buffered, or committed by this repository, are found straight away.
+/// queries, marking files processed, and file states only see committed rows,
+/// and file states are written straight away.
+pub struct PostgresMetaRepository {
+    client: Client,
+    file_ids: IdBlock,
+    link_ids: IdBlock,
+    plan_ids: IdBlock,
+    files: Vec<FileRow>,
+    links: Vec<DbLink>,
+    plans: Vec<Plan>,
+    known_files: HashMap<String, usize>,
+    known_links: HashMap<LinkKey, usize>,
+    known_plans: HashMap<(String, String), usize>,
+    // the ids rows added before the last commit were stored with, where they differ
+    committed_file_ids: HashMap<usize, usize>,
+    committed_plan_ids: HashMap<usize, usize>,
+}
+
+type LinkKey = (usize, EntityKind, usize, EntityKind);
+
+impl PostgresMetaRepository {
+    pub fn new(mut client: Client) -> Result<Self, postgres::Error> {
+        Self::migrate(&mut client)?;
+        Ok(PostgresMetaRepository {
+            client,
+            file_ids: IdBlock::new("files"),
+            link_ids: IdBlock::new("links"),
+            plan_ids: IdBlock::new("plans"),
+            files: vec![],
+            links: vec![],
+            plans: vec![],
+            known_files: HashMap::new(),
+            known_links: HashMap::new(),
+            known_plans: HashMap::new(),
+            committed_file_ids: HashMap::new(),
+            committed_plan_ids: HashMap::new(),
+        })
+    }
+
+    fn migrate(client: &mut Client) -> Result<(), postgres::Error> {
+        client.batch_execute(
+            "CREATE TABLE IF NOT EXISTS schema_migrations (version INTEGER PRIMARY KEY)",
+        )?;
+        for (i, migration) in MIGRATIONS.iter().enumerate() {
+            let version = i as i32 + 1;
+            let mut tx = client.transaction()?;
+            // serialize concurrent migrators, so each migration only runs once
+            tx.batch_execute("LOCK TABLE schema_migrations IN EXCLUSIVE MODE")?;
+            let applied = tx
+                .query_opt(
+                    "SELECT version FROM schema_migrations WHERE version = $1",
+                    &[&version],
+                )?
+                .is_some();
+            if !applied {
+                tx.batch_execute(migration)?;
+                tx.execute(
+                    "INSERT INTO schema_migrations (version) VALUES ($1)",
+                    &[&version],
+                )?;
+            }
+            tx.commit()?;
+        }
+        Ok(())
+    }
+
+    fn _query_files(&mut self, query: &str, params: &[&(dyn ToSql + Sync)]) -> Vec<FileRow> {
+        self.client
+            .query(query, params)
+            .expect("files to be queried")
+            .iter()
+            .map(_file_record)
+            .collect()
+    }
+
+    /// writes every buffered row in one transaction.
+    /// if it fails nothing is written, and the rows stay buffered to be retried.
+    pub fn try_commit(&mut self) -> Result<(), postgres::Error> {
+        let mut tx = self.client.transaction()?;
+        // files and plans first, so links can be pointed at the rows they were stored as
+        let file_ids = copy_and_resolve(
+            &mut tx,
+            "files",
+            FILE_COLUMNS,
+            &["url"],
+            &to_csv(self.files.iter().cloned()),
+        )?;
+        let plan_ids = copy_and_resolve(
+            &mut tx,
+            "plans",
+            PLAN_COLUMNS,
+            &["plan_id_type", "plan_id"],
+            &to_csv(self.plans.iter().cloned()),
+        )?;
+        let stored_id = |kind: EntityKind, id: usize| {
+            let ids = if kind.is_file() { &file_ids } else { &plan_ids };
+            *ids.get(&id).unwrap_or(&id)
+        };
+        let links: Vec<DbLink> = self
+            .links
+            .iter()
+            .map(|link| DbLink {
+                from_id: stored_id(link.from_type, link.from_id),
+                to_id: stored_id(link.to_type, link.to_id),
+                ..*link
+            })
+            .collect();
+        let link_ids = copy_and_resolve(
+            &mut tx,
+            "links",
+            LINK_COLUMNS,
+            &["from_type", "from_id", "to_type", "to_id"],
+            &to_csv(links.iter().copied()),
+        )?;
+        tx.commit()?;
+
+        // the buffered rows are stored now, so remember them by the ids they were stored with
+        for file in self.files.drain(..) {
+            self.known_files
+                .insert(file.url, stored_id(EntityKind::RateFile, file.id));
+        }
+        for plan in self.plans.drain(..) {
+            self.known_plans.insert(
+                (plan.plan_id_type, plan.plan_id),
+                stored_id(EntityKind::Plan, plan.id),
+            );
+        }
+        for link in links {
+            self.known_links.insert(
+                (link.from_id, link.from_type, link.to_id, link.to_type),
+                *link_ids.get(&link.id).unwrap_or(&link.id),
+            );
+        }
+        self.links.clear();
+        for ids in [&mut self.file_ids, &mut self.link_ids, &mut self.plan_ids] {
+            ids.commit();
+        }
+        self.committed_file_ids = file_ids;
+        self.committed_plan_ids = plan_ids;
+        Ok(())
+    }
+
+    /// drops every row buffered since the last commit, so they can be added again.
+    /// their ids are handed out again too.
+    pub fn rollback(&mut self) {
+        for file in self.files.drain(..) {
+            self.known_files.remove(&file.url);
+        }
+        for plan in self.plans.drain(..) {
+            self.known_plans.remove(&(plan.plan_id_type, plan.plan_id));
+        }
+        for link in self.links.drain(..) {
+            self.known_links
+                .remove(&(link.from_id, link.from_type, link.to_id, link.to_type));
+        }
+        for ids in [&mut self.file_ids, &mut self.link_ids, &mut self.plan_ids] {
+            ids.rollback();
+        }
+    }
+}
+
+impl MetaRepository for PostgresMetaRepository {
+    fn add_file(&mut self, file: &FileRowInput) -> usize {
+        if let Some(id) = self.known_files.get(&file.url) {
+            return *id;
+        }
+        let id = self.file_ids.next_id(&mut self.client);
+        self.files.push(FileRow::from_input(id, file));
+        self.known_files.insert(file.url.clone(), id);
+        id
+    }
+
+    fn add_link(&mut self, link: &DbLinkInput) -> usize {
+        let key = (link.from_id, link.from_type, link.to_id, link.to_type);
+        if let Some(id) = self.known_links.get(&key) {
+            return *id;
+        }
+        let id = self.link_ids.next_id(&mut self.client);
+        self.links.push(DbLink::from_input(id, link));
+        self.known_links.insert(key, id);
+        id
+    }
+
+    fn add_plan(&mut self, plan: &PlanInput) -> usize {
+        let key = (plan.plan_id_type.clone(), plan.plan_id.clone());
+        if let Some(id) = self.known_plans.get(&key) {
+            return *id;
+        }
+        let id = self.plan_ids.next_id(&mut self.client);
+        self.plans.push(Plan::from_input(id, plan));
+        self.known_plans.insert(key, id);
+        id
+    }
+
+    fn commit(&mut self) {
+        if let Err(err) = self.try_commit() {
+            // nothing was written, so don't remember any of it as stored
+            self.rollback();
+            panic!("index file rows to be committed: {err}");
+        }
+    }
+
+    fn committed_id(&self, kind: EntityKind, id: usize) -> usize {
+        let ids = if kind.is_file() {
+            &self.committed_file_ids
+        } else {
+            &self.committed_plan_ids
+        };
+        *ids.get(&id).unwrap_or(&id)
+    }
+
+    fn get_file(&mut self, url: &str) -> Option<FileRow> {
+        self.client
+            .query_opt(
+                &format!("SELECT {FILE_COLUMNS} FROM files WHERE url = $1"),
+                &[&url],
+            )
+            .expect("file to be queried")
+            .as_ref()
+            .map(_file_record)
+    }
+
+    fn find_files_for_plan(&mut self, plan_id: usize) -> Vec<FileRow> {
+        self._query_files(
+            &format!(
+                "SELECT {FILE_COLUMNS} FROM files WHERE id IN (
+                    SELECT to_id FROM links
+                    WHERE from_type = 'plan' AND from_id = $1 AND to_type <> 'plan'
+                    UNION
+                    SELECT from_id FROM links
+                    WHERE to_type = 'plan' AND to_id = $1 AND from_type <> 'plan'
+                )
+                ORDER BY id"
+            ),
+            &[&(plan_id as i64)],
+        )
+    }
+
+    fn find_plans_for_file(&mut self, file_id: usize) -> Vec<Plan> {
+        self.client
+            .query(
+                &format!(
+                    "SELECT {PLAN_COLUMNS} FROM plans WHERE id IN (
+                        SELECT to_id FROM links
+                        WHERE from_type <> 'plan' AND from_id = $1 AND to_type = 'plan'
+                        UNION
+                        SELECT from_id FROM links
+                        WHERE to_type <> 'plan' AND to_id = $1 AND from_type = 'plan'
+                    )
+                    ORDER BY id"
+                ),
+                &[&(file_id as i64)],
+            )
+            .expect("plans to be queried")
+            .iter()
+            .map(_plan_record)
+            .collect()
+    }
+
+    fn find_files_for_index_file(&mut self, index_file_id: usize) -> Vec<FileRow> {
+        self._query_files(
+            &format!(
+                "SELECT {FILE_COLUMNS} FROM files WHERE id IN (
+                    SELECT to_id FROM links
+                    WHERE from_type = 'index_file' AND from_id = $1 AND to_type <> 'plan'
+                )
+                ORDER BY id"
+            ),
+            &[&(index_file_id as i64)],
+        )
+    }
+
+    fn mark_file_processed(&mut self, file_id: usize) {
+        self.client
+            .execute(
+                "INSERT INTO processed_files (file_id) VALUES ($1) ON CONFLICT DO NOTHING",
+                &[&(file_id as i64)],
+            )
+            .expect("file to be marked processed");
+    }
+
+    fn get_file_state(&mut self, file_id: usize) -> Option<FileState> {
+        self.client
+            .query_opt(
+                "SELECT file_id, status, error, bytes_processed, content_hash, etag, attempts,
+                    last_attempt_at_ms
+                FROM file_states WHERE file_id = $1",
+                &[&(file_id as i64)],
+            )
+            .expect("file state to be queried")
+            .as_ref()
+            .map(_file_state)
+    }
+
+    fn set_file_state(&mut self, state: &FileState) {
+        self.client
+            .execute(
+                "INSERT INTO file_states (
+                    file_id, status, error, bytes_processed, content_hash, etag, attempts,
+                    last_attempt_at_ms
+                )
+                VALUES ($1, $2, $3, $4, $5, $6, $7, $8)
+                ON CONFLICT (file_id) DO UPDATE SET
+                    status = EXCLUDED.status,
+                    error = EXCLUDED.error,
+                    bytes_processed = EXCLUDED.bytes_processed,
+                    content_hash = EXCLUDED.content_hash,
+                    etag = EXCLUDED.etag,
+                    attempts = EXCLUDED.attempts,
+                    last_attempt_at_ms = EXCLUDED.last_attempt_at_ms",
+                &[
+                    &(state.file_id as i64),
+                    &state.status.as_str(),
+                    &state.error,
+                    &(state.bytes_processed as i64),
+                    &state.content_hash,
+                    &state.etag,
+                    &(state.attempts as i64),
+                    &state.last_attempt_at_ms.map(|ms| ms as i64),
+                ],
+            )
+            .expect("file state to be recorded");
+    }
+
+    fn list_unprocessed_files(&mut self) -> Vec<FileRow> {
+        self._query_files(
+            &format!(
+                "SELECT {FILE_COLUMNS} FROM files
+                WHERE id IN (
+                    SELECT to_id FROM links WHERE to_type = 'rate_file'
+                    UNION
+                    SELECT from_id FROM links WHERE from_type = 'rate_file'
+                )
+                AND id NOT IN (SELECT file_id FROM processed_files)
+                ORDER BY id"
+            ),
+            &[],
+        )
+    }
+}
+
+fn _file_record(row: &Row) -> FileRow {
+    FileRow {
+        id: row.get::<_, i64>(0) as usize,
+        url: row.get(1),
+        filename: row.get(2),
+        reporting_entity_name: row.get(3),
+        reporting_entity_type: row.get(4),
+        file_type: row
+            .get::<_, &str>(5)
+            .parse()
+            .expect("file_type to be a known file type"),
+        description: row.get(6),
+    }
+}
+
+fn _file_state(row: &Row) -> FileState {
+    FileState {
+        file_id: row.get::<_, i64>(0) as usize,
+        status: row
+            .get::<_, &str>(1)
+            .parse()
+            .expect("status to be a known file status"),
+        error: row.get(2),
+        bytes_processed: row.get::<_, i64>(3) as u64,
+        content_hash: row.get(4),
+        etag: row.get(5),
+        attempts: row.get::<_, i64>(6) as u32,
+        last_attempt_at_ms: row.get::<_, Option<i64>>(7).map(|ms| ms as u64),
+    }
+}
+
+fn _plan_record(row: &Row) -> Plan {
+    Plan {
+        id: row.get::<_, i64>(0) as usize,
+        plan_name: row.get(1),
+        plan_id_type: row.get(2),
+        plan_id: row.get(3),
+        plan_market_type: row.get(4),
+    }
+}
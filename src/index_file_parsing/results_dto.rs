@@ -1,9 +1,42 @@
-#[derive(Debug)]
+use serde::Serialize;
+
+// a report of what was recorded from an index file, serializable as json so it can be stored
+#[derive(Debug, Serialize)]
 pub struct IndexFileParsingResults {
     pub index_file_id: usize,
+    pub location: String,
     pub reporting_entity_name: String,
     pub reporting_entity_type: String,
-    pub num_reporting_structures: i32,
-    pub num_plans: i32,
-    pub num_rate_files: i32,
+    pub version: Option<String>,
+    /// every structure in the file, including those that were skipped
+    pub num_reporting_structures: usize,
+    pub num_plans: usize,
+    /// every in-network and allowed amount file listed, counting a file once per structure
+    pub num_rate_files: usize,
+    /// distinct urls among those files
+    pub num_unique_rate_files: usize,
+    /// listings of a file already listed by an earlier structure
+    pub num_duplicate_rate_files: usize,
+    pub reporting_structures: Vec<ReportingStructureResults>,
+    pub skipped_reporting_structures: Vec<SkippedReportingStructure>,
+    /// when parsing started, in milliseconds since the unix epoch
+    pub started_at_ms: u128,
+    /// how long reading and recording the index file took
+    pub duration_ms: u128,
+}
+
+#[derive(Debug, Serialize)]
+pub struct ReportingStructureResults {
+    /// the structure's position in the index file
+    pub index: usize,
+    pub plan_ids: Vec<usize>,
+    pub in_network_file_ids: Vec<usize>,
+    pub allowed_amount_file_id: Option<usize>,
+}
+
+// a structure that didn't match the schema, and so wasn't recorded
+#[derive(Debug, Serialize)]
+pub struct SkippedReportingStructure {
+    pub index: usize,
+    pub error: String,
 }
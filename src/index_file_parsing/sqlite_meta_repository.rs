@@ -0,0 +1,370 @@
+use std::path::Path;
+
+use rusqlite::{params, Connection, OptionalExtension, Params, Row};
+
+use super::meta_repository_trait::{
+    DbLinkInput, FileRow, FileRowInput, FileState, MetaRepository, Plan, PlanInput,
+};
+
+// each entry is applied once, in order, and tracked with sqlite's `user_version` pragma.
+// only ever append to this list, so existing databases can be migrated forward.
+const MIGRATIONS: &[&str] = &[
+    CREATE_FILES_PLANS_AND_LINKS,
+    UNIQUE_FILES_PLANS_AND_LINKS,
+    CREATE_PROCESSED_FILES,
+    ADD_FILE_TYPE_AND_DESCRIPTION,
+    CREATE_FILE_STATES,
+    ADD_LAST_ATTEMPT_AT,
+    CLASSIFY_LEGACY_ALLOWED_AMOUNT_FILES,
+];
+
+const CREATE_FILES_PLANS_AND_LINKS: &str = "CREATE TABLE files (
+    id INTEGER PRIMARY KEY,
+    url TEXT NOT NULL,
+    filename TEXT NOT NULL,
+    reporting_entity_name TEXT NOT NULL,
+    reporting_entity_type TEXT NOT NULL
+);
+CREATE INDEX files_url_idx ON files (url);
+
+CREATE TABLE plans (
+    id INTEGER PRIMARY KEY,
+    plan_name TEXT NOT NULL,
+    plan_id_type TEXT NOT NULL,
+    plan_id TEXT NOT NULL,
+    plan_market_type TEXT NOT NULL
+);
+CREATE INDEX plans_plan_id_idx ON plans (plan_id_type, plan_id);
+
+CREATE TABLE links (
+    id INTEGER PRIMARY KEY,
+    from_id INTEGER NOT NULL,
+    from_type TEXT NOT NULL,
+    to_id INTEGER NOT NULL,
+    to_type TEXT NOT NULL
+);
+CREATE INDEX links_from_idx ON links (from_type, from_id);
+CREATE INDEX links_to_idx ON links (to_type, to_id);
+
+-- a link's ends point at either a plan or a file depending on its type,
+-- which a plain REFERENCES clause can't express, so check them here instead.
+CREATE TRIGGER links_references_existing_rows
+BEFORE INSERT ON links
+WHEN NOT EXISTS (
+        SELECT 1 FROM plans WHERE NEW.from_type = 'plan' AND id = NEW.from_id
+        UNION ALL
+        SELECT 1 FROM files WHERE NEW.from_type != 'plan' AND id = NEW.from_id
+    )
+    OR NOT EXISTS (
+        SELECT 1 FROM plans WHERE NEW.to_type = 'plan' AND id = NEW.to_id
+        UNION ALL
+        SELECT 1 FROM files WHERE NEW.to_type != 'plan' AND id = NEW.to_id
+    )
+BEGIN
+    SELECT RAISE(ABORT, 'FOREIGN KEY constraint failed');
+END;";
+
+// rows are upserted on these keys, see `MetaRepository`
+const UNIQUE_FILES_PLANS_AND_LINKS: &str = "DROP INDEX files_url_idx;
+CREATE UNIQUE INDEX files_url_key ON files (url);
+
+DROP INDEX plans_plan_id_idx;
+CREATE UNIQUE INDEX plans_plan_id_key ON plans (plan_id_type, plan_id);
+
+DROP INDEX links_from_idx;
+CREATE UNIQUE INDEX links_from_to_key ON links (from_type, from_id, to_type, to_id);";
+
+const CREATE_PROCESSED_FILES: &str = "CREATE TABLE processed_files (
+    file_id INTEGER PRIMARY KEY REFERENCES files (id)
+);";
+
+// before files had a type, the index file was the one named "index".
+// allowed amount files were named by their description, see CLASSIFY_LEGACY_ALLOWED_AMOUNT_FILES
+const ADD_FILE_TYPE_AND_DESCRIPTION: &str =
+    "ALTER TABLE files ADD COLUMN file_type TEXT NOT NULL DEFAULT 'in_network';
+ALTER TABLE files ADD COLUMN description TEXT NOT NULL DEFAULT '';
+UPDATE files SET file_type = 'index' WHERE filename = 'index';";
+
+const CREATE_FILE_STATES: &str = "CREATE TABLE file_states (
+    file_id INTEGER PRIMARY KEY REFERENCES files (id),
+    status TEXT NOT NULL,
+    error TEXT,
+    bytes_processed INTEGER NOT NULL,
+    content_hash TEXT,
+    etag TEXT,
+    attempts INTEGER NOT NULL
+);";
+
+const ADD_LAST_ATTEMPT_AT: &str = "ALTER TABLE file_states ADD COLUMN last_attempt_at_ms INTEGER;";
+
+// legacy files named by their description rather than their url's last segment were allowed amount files.
+// also repairs databases that went through ADD_FILE_TYPE_AND_DESCRIPTION when it took them as in-network files
+const CLASSIFY_LEGACY_ALLOWED_AMOUNT_FILES: &str =
+    "UPDATE files SET file_type = 'allowed_amount', description = filename
+WHERE file_type = 'in_network' AND description = ''
+    AND url <> filename AND substr(url, -length(filename) - 1) <> '/' || filename;";
+
+const FILE_COLUMNS: &str =
+    "id, url, filename, reporting_entity_name, reporting_entity_type, file_type, description";
+const PLAN_COLUMNS: &str = "id, plan_name, plan_id_type, plan_id, plan_market_type";
+
+pub struct SqliteMetaRepository {
+    conn: Connection,
+}
+
+impl SqliteMetaRepository {
+    pub fn open<P: AsRef<Path>>(path: P) -> rusqlite::Result<Self> {
+        Self::from_connection(Connection::open(path)?)
+    }
+
+    pub fn open_in_memory() -> rusqlite::Result<Self> {
+        Self::from_connection(Connection::open_in_memory()?)
+    }
+
+    fn from_connection(mut conn: Connection) -> rusqlite::Result<Self> {
+        conn.pragma_update(None, "foreign_keys", true)?;
+        Self::migrate(&mut conn)?;
+        Ok(SqliteMetaRepository { conn })
+    }
+
+    fn migrate(conn: &mut Connection) -> rusqlite::Result<()> {
+        let version: i64 = conn.pragma_query_value(None, "user_version", |row| row.get(0))?;
+        for (i, migration) in MIGRATIONS.iter().enumerate().skip(version as usize) {
+            let tx = conn.transaction()?;
+            tx.execute_batch(migration)?;
+            tx.pragma_update(None, "user_version", (i + 1) as i64)?;
+            tx.commit()?;
+        }
+        Ok(())
+    }
+
+    fn _query_files<P: Params>(&self, query: &str, params: P) -> Vec<FileRow> {
+        self.conn
+            .prepare_cached(query)
+            .and_then(|mut statement| {
+                statement
+                    .query_map(params, _file_record)?
+                    .collect::<rusqlite::Result<Vec<_>>>()
+            })
+            .expect("files to be queried")
+    }
+}
+
+fn _file_record(row: &Row) -> rusqlite::Result<FileRow> {
+    Ok(FileRow {
+        id: row.get::<_, i64>(0)? as usize,
+        url: row.get(1)?,
+        filename: row.get(2)?,
+        reporting_entity_name: row.get(3)?,
+        reporting_entity_type: row.get(4)?,
+        file_type: row.get::<_, String>(5)?.parse().map_err(|e| {
+            rusqlite::Error::FromSqlConversionFailure(5, rusqlite::types::Type::Text, Box::new(e))
+        })?,
+        description: row.get(6)?,
+    })
+}
+
+fn _file_state(row: &Row) -> rusqlite::Result<FileState> {
+    Ok(FileState {
+        file_id: row.get::<_, i64>(0)? as usize,
+        status: row.get::<_, String>(1)?.parse().map_err(|e| {
+            rusqlite::Error::FromSqlConversionFailure(1, rusqlite::types::Type::Text, Box::new(e))
+        })?,
+        error: row.get(2)?,
+        bytes_processed: row.get::<_, i64>(3)? as u64,
+        content_hash: row.get(4)?,
+        etag: row.get(5)?,
+        attempts: row.get(6)?,
+        last_attempt_at_ms: row.get::<_, Option<i64>>(7)?.map(|ms| ms as u64),
+    })
+}
+
+fn _plan_record(row: &Row) -> rusqlite::Result<Plan> {
+    Ok(Plan {
+        id: row.get::<_, i64>(0)? as usize,
+        plan_name: row.get(1)?,
+        plan_id_type: row.get(2)?,
+        plan_id: row.get(3)?,
+        plan_market_type: row.get(4)?,
+    })
+}
+
+impl MetaRepository for SqliteMetaRepository {
+    // the no-op `DO UPDATE` makes `RETURNING` give back the existing row's id on a conflict
+    fn add_file(&mut self, file: &FileRowInput) -> usize {
+        self.conn
+            .query_row(
+                "INSERT INTO files (
+                    url, filename, reporting_entity_name, reporting_entity_type, file_type, description
+                )
+                VALUES (?1, ?2, ?3, ?4, ?5, ?6)
+                ON CONFLICT (url) DO UPDATE SET id = id
+                RETURNING id",
+                params![
+                    file.url,
+                    file.filename,
+                    file.reporting_entity_name,
+                    file.reporting_entity_type,
+                    file.file_type.as_str(),
+                    file.description
+                ],
+                |row| row.get::<_, i64>(0),
+            )
+            .expect("file row to be upserted") as usize
+    }
+
+    fn add_link(&mut self, link: &DbLinkInput) -> usize {
+        self.conn
+            .query_row(
+                "INSERT INTO links (from_id, from_type, to_id, to_type) VALUES (?1, ?2, ?3, ?4)
+                ON CONFLICT (from_type, from_id, to_type, to_id) DO UPDATE SET id = id
+                RETURNING id",
+                params![
+                    link.from_id as i64,
+                    link.from_type.as_str(),
+                    link.to_id as i64,
+                    link.to_type.as_str()
+                ],
+                |row| row.get::<_, i64>(0),
+            )
+            .expect("link row to be upserted") as usize
+    }
+
+    fn add_plan(&mut self, plan: &PlanInput) -> usize {
+        self.conn
+            .query_row(
+                "INSERT INTO plans (plan_name, plan_id_type, plan_id, plan_market_type)
+                VALUES (?1, ?2, ?3, ?4)
+                ON CONFLICT (plan_id_type, plan_id) DO UPDATE SET id = id
+                RETURNING id",
+                params![
+                    plan.plan_name,
+                    plan.plan_id_type,
+                    plan.plan_id,
+                    plan.plan_market_type
+                ],
+                |row| row.get::<_, i64>(0),
+            )
+            .expect("plan row to be upserted") as usize
+    }
+
+    fn get_file(&mut self, url: &str) -> Option<FileRow> {
+        self.conn
+            .query_row(
+                &format!("SELECT {FILE_COLUMNS} FROM files WHERE url = ?1"),
+                [url],
+                _file_record,
+            )
+            .optional()
+            .expect("file to be queried")
+    }
+
+    fn find_files_for_plan(&mut self, plan_id: usize) -> Vec<FileRow> {
+        self._query_files(
+            &format!(
+                "SELECT {FILE_COLUMNS} FROM files WHERE id IN (
+                    SELECT to_id FROM links
+                    WHERE from_type = 'plan' AND from_id = ?1 AND to_type != 'plan'
+                    UNION
+                    SELECT from_id FROM links
+                    WHERE to_type = 'plan' AND to_id = ?1 AND from_type != 'plan'
+                )
+                ORDER BY id"
+            ),
+            [plan_id as i64],
+        )
+    }
+
+    fn find_plans_for_file(&mut self, file_id: usize) -> Vec<Plan> {
+        self.conn
+            .prepare_cached(&format!(
+                "SELECT {PLAN_COLUMNS} FROM plans WHERE id IN (
+                    SELECT to_id FROM links
+                    WHERE from_type != 'plan' AND from_id = ?1 AND to_type = 'plan'
+                    UNION
+                    SELECT from_id FROM links
+                    WHERE to_type != 'plan' AND to_id = ?1 AND from_type = 'plan'
+                )
+                ORDER BY id"
+            ))
+            .and_then(|mut statement| {
+                statement
+                    .query_map([file_id as i64], _plan_record)?
+                    .collect::<rusqlite::Result<Vec<_>>>()
+            })
+            .expect("plans to be queried")
+    }
+
+    fn find_files_for_index_file(&mut self, index_file_id: usize) -> Vec<FileRow> {
+        self._query_files(
+            &format!(
+                "SELECT {FILE_COLUMNS} FROM files WHERE id IN (
+                    SELECT to_id FROM links
+                    WHERE from_type = 'index_file' AND from_id = ?1 AND to_type != 'plan'
+                )
+                ORDER BY id"
+            ),
+            [index_file_id as i64],
+        )
+    }
+
+    fn mark_file_processed(&mut self, file_id: usize) {
+        self.conn
+            .execute(
+                "INSERT INTO processed_files (file_id) VALUES (?1) ON CONFLICT DO NOTHING",
+                [file_id as i64],
+            )
+            .expect("file to be marked processed");
+    }
+
+    fn get_file_state(&mut self, file_id: usize) -> Option<FileState> {
+        self.conn
+            .query_row(
+                "SELECT file_id, status, error, bytes_processed, content_hash, etag, attempts,
+                    last_attempt_at_ms
+                FROM file_states WHERE file_id = ?1",
+                [file_id as i64],
+                _file_state,
+            )
+            .optional()
+            .expect("file state to be queried")
+    }
+
+    fn set_file_state(&mut self, state: &FileState) {
+        self.conn
+            .execute(
+                "INSERT OR REPLACE INTO file_states (
+                    file_id, status, error, bytes_processed, content_hash, etag, attempts,
+                    last_attempt_at_ms
+                )
+                VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8)",
+                params![
+                    state.file_id as i64,
+                    state.status.as_str(),
+                    state.error,
+                    state.bytes_processed as i64,
+                    state.content_hash,
+                    state.etag,
+                    state.attempts,
+                    state.last_attempt_at_ms.map(|ms| ms as i64)
+                ],
+            )
+            .expect("file state to be recorded");
+    }
+
+    fn list_unprocessed_files(&mut self) -> Vec<FileRow> {
+        self._query_files(
+            &format!(
+                "SELECT {FILE_COLUMNS} FROM files
+                WHERE id IN (
+                    SELECT to_id FROM links WHERE to_type = 'rate_file'
+                    UNION
+                    SELECT from_id FROM links WHERE from_type = 'rate_file'
+                )
+                AND id NOT IN (SELECT file_id FROM processed_files)
+                ORDER BY id"
+            ),
+            [],
+        )
+    }
+}
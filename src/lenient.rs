@@ -0,0 +1,291 @@
+use std::fmt;
+
+use serde::Serialize;
+use serde_json::{Number, Value};
+
+/// the `expiration_date` lenient mode gives prices whose date was empty or null, as it isn't known.
+/// strict mode requires a date, and keeps whatever string the file gave.
+pub const UNKNOWN_EXPIRATION_DATE: &str = "";
+
+/// a file read in lenient mode, with every quirk that was coerced to read it
+#[derive(Debug, Serialize)]
+pub struct Coerced<F> {
+    pub file: F,
+    pub warnings: Vec<CoercionWarning>,
+}
+
+#[derive(Debug, Serialize, PartialEq, Eq)]
+pub struct CoercionWarning {
+    /// where in the file, e.g. `$.in_network[3].negotiated_rates[0].provider_groups[1].npi[0]`
+    pub path: String,
+    pub message: String,
+}
+
+/// a file whose malformed items were skipped rather than failing it
+#[derive(Debug, Serialize)]
+pub struct PartiallyParsed<F> {
+    pub file: F,
+    pub skipped: Vec<SkippedNode>,
+}
+
+#[derive(Debug, Serialize, PartialEq, Eq)]
+pub struct SkippedNode {
+    /// its index in the file's array, e.g. `in_network`
+    pub index: usize,
+    pub billing_code: Option<String>,
+    pub error: String,
+}
+
+impl fmt::Display for SkippedNode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "item {}", self.index)?;
+        if let Some(billing_code) = &self.billing_code {
+            write!(f, " (billing code {billing_code})")?;
+        }
+        write!(f, ": {}", self.error)
+    }
+}
+
+impl fmt::Display for CoercionWarning {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}: {}", self.path, self.message)
+    }
+}
+
+/// rewrites the small ways real payer files break the schema into what the dtos expect,
+/// recording a warning for each. `path` is where `value` is in the file.
+/// - npis, provider references, ids and amounts given as strings become numbers
+/// - a single npi becomes a list of one
+/// - `billing_class` and `tin.type` are lowercased
+/// - an empty or null `expiration_date` becomes [`UNKNOWN_EXPIRATION_DATE`]
+pub fn coerce_quirks(value: &mut Value, path: &str, warnings: &mut Vec<CoercionWarning>) {
+    match value {
+        Value::Object(fields) => {
+            for (key, field) in fields.iter_mut() {
+                let path = format!("{path}.{key}");
+                coerce_field(key, field, &path, warnings);
+                coerce_quirks(field, &path, warnings);
+            }
+        }
+        Value::Array(items) => {
+            for (i, item) in items.iter_mut().enumerate() {
+                coerce_quirks(item, &format!("{path}[{i}]"), warnings);
+            }
+        }
+        _ => {}
+    }
+}
+
+fn coerce_field(key: &str, field: &mut Value, path: &str, warnings: &mut Vec<CoercionWarning>) {
+    match key {
+        "npi" => coerce_npis(field, path, Some(warnings)),
+        "provider_references" => coerce_numbers(field, path, Some(warnings)),
+        "provider_group_id" | "negotiated_rate" | "allowed_amount" | "billed_charge"
+        | "days_supply" | "dispensing_fee" => coerce_number(field, path, Some(warnings)),
+        "billing_class" => coerce_lowercase(field, path, warnings),
+        "tin" => {
+            if let Some(type_) = field.get_mut("type") {
+                coerce_lowercase(type_, &format!("{path}.type"), warnings);
+            }
+        }
+        "expiration_date"
+            if field.is_null() || field.as_str().is_some_and(|date| date.trim().is_empty()) =>
+        {
+            warn(
+                warnings,
+                path,
+                format!("{field} was taken as an unknown date"),
+            );
+            *field = Value::String(UNKNOWN_EXPIRATION_DATE.to_string());
+        }
+        _ => {}
+    }
+}
+
+// the coercions `schema_version`'s adapters share, which record no warnings.
+// `path` is where `field` is in the file, only needed for warnings.
+
+/// a single npi becomes a list of one, and npis given as strings become numbers
+pub(crate) fn coerce_npis(
+    field: &mut Value,
+    path: &str,
+    warnings: Option<&mut Vec<CoercionWarning>>,
+) {
+    let mut warnings = warnings;
+    if !field.is_array() && !field.is_null() {
+        *field = Value::Array(vec![field.take()]);
+        if let Some(warnings) = warnings.as_deref_mut() {
+            warn(warnings, path, "a single npi was made a list".to_string());
+        }
+    }
+    coerce_numbers(field, path, warnings);
+}
+
+/// the items of an array given as strings become numbers
+pub(crate) fn coerce_numbers(
+    field: &mut Value,
+    path: &str,
+    mut warnings: Option<&mut Vec<CoercionWarning>>,
+) {
+    let Some(items) = field.as_array_mut() else {
+        return;
+    };
+    for (i, item) in items.iter_mut().enumerate() {
+        match warnings.as_deref_mut() {
+            Some(warnings) => coerce_number(item, &format!("{path}[{i}]"), Some(warnings)),
+            None => coerce_number(item, path, None),
+        }
+    }
+}
+
+/// a number given as a string becomes a number
+pub(crate) fn coerce_number(
+    field: &mut Value,
+    path: &str,
+    warnings: Option<&mut Vec<CoercionWarning>>,
+) {
+    let Some(number) = field.as_str().and_then(parse_number) else {
+        return;
+    };
+    if let Some(warnings) = warnings {
+        warn(
+            warnings,
+            path,
+            format!("{field} was a string, not a number"),
+        );
+    }
+    *field = Value::Number(number);
+}
+
+fn parse_number(s: &str) -> Option<Number> {
+    let s = s.trim();
+    s.parse::<u64>()
+        .map(Number::from)
+        .ok()
+        .or_else(|| s.parse::<f64>().ok().and_then(Number::from_f64))
+}
+
+fn coerce_lowercase(field: &mut Value, path: &str, warnings: &mut Vec<CoercionWarning>) {
+    let Some(s) = field.as_str() else {
+        return;
+    };
+    let lowercase = s.trim().to_lowercase();
+    if lowercase != s {
+        warn(warnings, path, format!("{field} was lowercased"));
+        *field = Value::String(lowercase);
+    }
+}
+
+fn warn(warnings: &mut Vec<CoercionWarning>, path: &str, message: String) {
+    warnings.push(CoercionWarning {
+        path: path.to_string(),
+        message,
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs::{self, File};
+
+    use serde_json::json;
+
+    use super::*;
+    use crate::{
+        deserialize_filtered_in_network_file, deserialize_filtered_in_network_file_leniently,
+        in_network_file_dto::{BillingClass, TinType},
+        node_filters::NodeFilters,
+    };
+
+    #[test]
+    fn it_coerces_the_same_with_or_without_warnings() {
+        let mut warned = json!("1111111111");
+        let mut unwarned = warned.clone();
+        let mut warnings = vec![];
+        coerce_npis(&mut warned, "$.npi", Some(&mut warnings));
+        coerce_npis(&mut unwarned, "", None);
+
+        assert_eq!(warned, json!([1111111111]));
+        assert_eq!(unwarned, warned);
+        let paths: Vec<&str> = warnings.iter().map(|w| w.path.as_str()).collect();
+        assert_eq!(paths, vec!["$.npi", "$.npi[0]"]);
+    }
+
+    #[test]
+    fn it_coerces_quirks_with_their_paths() {
+        let mut rate = json!({
+            "provider_groups": [{ "npi": "1111111111", "tin": { "type": "EIN", "value": "1" } }],
+            "negotiated_prices": [{
+                "negotiated_rate": "12.50",
+                "billing_class": "Professional",
+                "expiration_date": ""
+            }]
+        });
+        let mut warnings = vec![];
+        coerce_quirks(&mut rate, "$.in_network[0]", &mut warnings);
+
+        assert_eq!(
+            rate,
+            json!({
+                "provider_groups": [{ "npi": [1111111111], "tin": { "type": "ein", "value": "1" } }],
+                "negotiated_prices": [{
+                    "negotiated_rate": 12.5,
+                    "billing_class": "professional",
+                    "expiration_date": ""
+                }]
+            })
+        );
+        let paths: Vec<&str> = warnings.iter().map(|w| w.path.as_str()).collect();
+        assert_eq!(
+            paths,
+            vec![
+                "$.in_network[0].negotiated_prices[0].billing_class",
+                "$.in_network[0].negotiated_prices[0].expiration_date",
+                "$.in_network[0].negotiated_prices[0].negotiated_rate",
+                "$.in_network[0].provider_groups[0].npi",
+                "$.in_network[0].provider_groups[0].npi[0]",
+                "$.in_network[0].provider_groups[0].tin.type",
+            ]
+        );
+    }
+
+    #[test]
+    fn it_coerces_payer_quirks_in_lenient_mode() {
+        let all = NodeFilters::new(vec![]);
+        let path = "./tests/fixtures/in-network-rates-quirks.json";
+
+        // a capitalized billing class is rejected unless we're lenient
+        assert!(deserialize_filtered_in_network_file(File::open(path).unwrap(), &all).is_err());
+
+        let coerced =
+            deserialize_filtered_in_network_file_leniently(File::open(path).unwrap(), &all)
+                .unwrap();
+        let rate = &coerced.file.in_network[0].negotiated_rates[0];
+        let group = &rate.provider_groups.as_ref().unwrap()[0];
+        assert_eq!(group.npi[0].as_u64(), Some(3333333333));
+        assert!(matches!(group.tin.type_, TinType::Npi));
+        let price = &rate.negotiated_prices[0];
+        assert_eq!(price.negotiated_rate.as_f64(), Some(1500.5));
+        assert_eq!(price.expiration_date, UNKNOWN_EXPIRATION_DATE);
+        assert!(matches!(price.billing_class, BillingClass::Institutional));
+        let references = coerced.file.provider_references.as_ref().unwrap();
+        assert_eq!(references[0].provider_group_id.as_u64(), Some(1));
+
+        let paths: Vec<&str> = coerced.warnings.iter().map(|w| w.path.as_str()).collect();
+        assert!(paths
+            .contains(&"$.in_network[0].negotiated_rates[0].negotiated_prices[0].billing_class"));
+        assert!(paths.contains(&"$.in_network[0].negotiated_rates[0].provider_groups[0].npi"));
+        assert!(paths
+            .contains(&"$.in_network[0].negotiated_rates[0].negotiated_prices[0].expiration_date"));
+        assert!(paths.contains(&"$.provider_references[0].provider_groups[0].tin.type"));
+        assert!(paths.contains(&"$.provider_references[0].provider_group_id"));
+        assert_eq!(coerced.warnings.len(), 10);
+
+        // still fails on what can't be coerced, saying where
+        let unusable = fs::read_to_string(path)
+            .unwrap()
+            .replace("\"Institutional\"", "\"hospital\"");
+        let error =
+            deserialize_filtered_in_network_file_leniently(unusable.as_bytes(), &all).unwrap_err();
+        assert!(error.to_string().starts_with("$.in_network[0]: "));
+    }
+}
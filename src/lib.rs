@@ -1,18 +1,49 @@
+pub mod allowed_amounts_file_dto;
+pub mod borrowed_in_network_file_dto;
+pub mod crawl;
+pub mod download;
 mod filtered_in_network_file;
 pub mod in_network_file_dto;
 pub mod index_file_parsing;
-mod node_filters;
+pub mod lenient;
+pub mod location;
+pub mod node_filters;
+pub mod prescription_drug_file_dto;
+pub mod provider_reference_file_dto;
+pub mod provider_references;
+pub mod schema_validation;
+pub mod schema_version;
 pub mod sync_array_serde;
 
-// use crate::node_filters::NodeFilters;
-use crate::in_network_file_dto::InNetworkFile;
+pub use crate::filtered_in_network_file::{
+    deserialize_filtered_allowed_amounts_file, deserialize_filtered_allowed_amounts_file_leniently,
+    deserialize_filtered_borrowed_rate_objects, deserialize_filtered_in_network_file,
+    deserialize_filtered_in_network_file_leniently,
+    deserialize_filtered_in_network_file_skipping_malformed,
+    deserialize_filtered_prescription_drug_file,
+    deserialize_filtered_prescription_drug_file_leniently, filter_nodes_skipping_malformed,
+};
+use crate::node_filters::{NdcFilters, NodeFilters};
 
-pub fn get_filtered_in_network_file(
-    bytes: &[u8],
-    // filters: &NodeFilters,
-) -> String {
+pub fn get_filtered_in_network_file(bytes: &[u8], filters: &NodeFilters) -> String {
     serde_json::to_string(
-        &serde_json::from_slice::<InNetworkFile>(bytes).expect("valid InNetworkFile json"),
+        &deserialize_filtered_in_network_file(bytes, filters).expect("valid InNetworkFile json"),
     )
     .expect("validly deserialized InNetworkFile")
 }
+
+pub fn get_filtered_allowed_amounts_file(bytes: &[u8], filters: &NodeFilters) -> String {
+    serde_json::to_string(
+        &deserialize_filtered_allowed_amounts_file(bytes, filters)
+            .expect("valid AllowedAmountsFile json"),
+    )
+    .expect("validly deserialized AllowedAmountsFile")
+}
+
+pub fn get_filtered_prescription_drug_file(bytes: &[u8], filters: &NdcFilters) -> String {
+    serde_json::to_string(
+        &deserialize_filtered_prescription_drug_file(bytes, filters)
+            .expect("valid PrescriptionDrugFile json"),
+    )
+    .expect("validly deserialized PrescriptionDrugFile")
+}
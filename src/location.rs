@@ -0,0 +1,85 @@
+use std::{
+    error::Error,
+    fmt,
+    fs::File,
+    io::{self, Read},
+};
+
+/// why a file at a local path or http(s) url couldn't be read
+#[derive(Debug)]
+pub enum LocationError {
+    Io(io::Error),
+    Http(Box<ureq::Error>),
+    Json(serde_json::Error),
+}
+
+impl fmt::Display for LocationError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            LocationError::Io(e) => write!(f, "{e}"),
+            LocationError::Http(e) => write!(f, "{e}"),
+            LocationError::Json(e) => write!(f, "{e}"),
+        }
+    }
+}
+
+impl Error for LocationError {}
+
+impl From<io::Error> for LocationError {
+    fn from(e: io::Error) -> Self {
+        LocationError::Io(e)
+    }
+}
+
+impl From<ureq::Error> for LocationError {
+    fn from(e: ureq::Error) -> Self {
+        LocationError::Http(Box::new(e))
+    }
+}
+
+impl From<serde_json::Error> for LocationError {
+    fn from(e: serde_json::Error) -> Self {
+        LocationError::Json(e)
+    }
+}
+
+pub fn is_url(location: &str) -> bool {
+    location.starts_with("http://") || location.starts_with("https://")
+}
+
+/// the file at a local path, or the body of an http(s) url as it's downloaded,
+/// along with the url's etag if the server sent one
+pub fn open_location(
+    location: &str,
+) -> Result<(Box<dyn Read + Send>, Option<String>), LocationError> {
+    if is_url(location) {
+        let response = ureq::get(location).call()?;
+        let etag = response
+            .headers()
+            .get("etag")
+            .and_then(|etag| etag.to_str().ok())
+            .map(str::to_string);
+        Ok((Box::new(response.into_body().into_reader()), etag))
+    } else {
+        Ok((Box::new(File::open(location)?), None))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn it_tells_urls_from_paths() {
+        assert!(is_url("https://example.com/index.json"));
+        assert!(is_url("http://example.com/index.json"));
+        assert!(!is_url("data/index.json"));
+        assert!(!is_url("ftp://example.com/index.json"));
+    }
+
+    #[test]
+    fn it_fails_on_missing_files() {
+        let result = open_location("does/not/exist.json");
+        assert!(matches!(result, Err(LocationError::Io(_))));
+    }
+}
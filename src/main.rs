@@ -1,9 +1,104 @@
-mod index_file_parsing;
-mod sync_array_serde;
+use rust_cms_json_parser::{
+    crawl,
+    download::{DownloadManager, DownloadOptions, DownloadRequest},
+    index_file_parsing::{
+        self,
+        csv_meta_repository::CsvMetaRepository,
+        meta_repository_trait::{FileType, MetaRepository},
+    },
+    node_filters::NodeFilters,
+    schema_validation,
+};
 
 fn main() {
-    let example_index_file_path =
-        "./price-transparency-guide/examples/table-of-contents/table-of-contents-sample.json";
-    let results = index_file_parsing::parse_index_file_from_path(example_index_file_path);
-    println!("done parsing.  results: {:?}", results);
+    let args: Vec<String> = std::env::args().collect();
+    if args.get(1).map(String::as_str) == Some("repair-csv-db") {
+        // rewrites a csv db dir that can't be opened anymore, e.g. `repair-csv-db ./db`
+        let dir = args.get(2).map_or("./db", String::as_str);
+        for report in CsvMetaRepository::repair_dir(dir).expect("csv dbs to be repaired") {
+            println!("repaired: {:?}", report);
+        }
+        return;
+    }
+    if args.get(1).map(String::as_str) == Some("crawl") {
+        // filters every in-network file an index file lists,
+        // e.g. `crawl https://example.com/index.json ./out 27447 99213`.
+        // `--retry-exhausted` tries files that failed every attempt in earlier crawls again
+        let (location, output_dir) = match (args.get(2), args.get(3)) {
+            (Some(location), Some(output_dir)) => (location, output_dir),
+            _ => panic!(
+                "usage: crawl <index file path or url> <output dir> [--retry-exhausted] [billing codes...]"
+            ),
+        };
+        let (flags, billing_codes): (Vec<String>, Vec<String>) = args[4..]
+            .iter()
+            .cloned()
+            .partition(|arg| arg == "--retry-exhausted");
+        let filters = NodeFilters::new(billing_codes);
+        let options = crawl::CrawlOptions {
+            retry_exhausted: !flags.is_empty(),
+            ..crawl::CrawlOptions::default()
+        };
+        let mut repo = CsvMetaRepository::in_dir("./db").expect("csv dbs in ./db");
+        let manifest = crawl::crawl_index_file_with_options(
+            location, &mut repo, &filters, output_dir, &options,
+        )
+        .expect("index file to be crawled");
+        println!("done crawling.  manifest: {:?}", manifest);
+        return;
+    }
+
+    if args.get(1).map(String::as_str) == Some("download") {
+        // downloads every file an index file links to, unfiltered,
+        // e.g. `download https://example.com/index.json ./downloads`
+        let (location, output_dir) = match (args.get(2), args.get(3)) {
+            (Some(location), Some(output_dir)) => (location, output_dir),
+            _ => panic!("usage: download <index file path or url> <output dir>"),
+        };
+        let mut repo = CsvMetaRepository::in_dir("./db").expect("csv dbs in ./db");
+        let results = index_file_parsing::parse_index_file(location, &mut repo)
+            .expect("index file to be parsed");
+        let requests: Vec<DownloadRequest> = repo
+            .find_files_for_index_file(results.index_file_id)
+            .iter()
+            .filter(|file| file.file_type != FileType::Index)
+            .map(|file| DownloadRequest::for_file(file, output_dir))
+            .collect();
+        let manager = DownloadManager::new(DownloadOptions::default());
+        for (request, download) in requests.iter().zip(manager.download_all(&requests)) {
+            match download {
+                Ok(download) => println!("downloaded: {:?}", download),
+                Err(e) => println!("failed to download {}: {e}", request.url),
+            }
+        }
+        return;
+    }
+
+    if args.get(1).map(String::as_str) == Some("validate") {
+        // checks an in-network file against the bundled cms schema,
+        // e.g. `validate https://example.com/in-network.json`
+        let location = args
+            .get(2)
+            .expect("usage: validate <in-network file path or url>");
+        let report = schema_validation::validate_in_network_file_at(location)
+            .expect("in-network file to be read");
+        println!(
+            "{}",
+            serde_json::to_string_pretty(&report).expect("report to be serializable")
+        );
+        return;
+    }
+
+    // an index file's path or url, defaulting to the cms example
+    let index_file_location = args.get(1).map_or(
+        "./price-transparency-guide/examples/table-of-contents/table-of-contents-sample.json",
+        String::as_str,
+    );
+    let mut repo = CsvMetaRepository::in_dir("./db").expect("csv dbs in ./db");
+    let results = index_file_parsing::parse_index_file(index_file_location, &mut repo)
+        .expect("index file to be parsed");
+    println!(
+        "{}",
+        serde_json::to_string_pretty(&results).expect("results to be serializable")
+    );
 }
@@ -1,4 +1,40 @@
-use crate::in_network_file_dto::InNetworkRateObject;
+use crate::{
+    allowed_amounts_file_dto::OutOfNetworkObject, in_network_file_dto::InNetworkRateObject,
+    prescription_drug_file_dto::PrescriptionDrug,
+};
+
+// decides which of a file's items the filtered deserializers keep
+pub trait NodeFilter<T> {
+    fn matches(&self, node: &T) -> bool;
+
+    // the field `matches` decides by, if it decides by just one string field.
+    // then items whose field can't match are skipped as soon as it's read, without deserializing them.
+    fn key_field(&self) -> Option<&'static str> {
+        None
+    }
+
+    // whether an item whose `key_field` is `value` could match
+    fn may_match_key(&self, _value: &str) -> bool {
+        true
+    }
+}
+
+// the items filters are matched against, e.g. an in-network file's rate objects
+pub trait BillingCoded {
+    fn billing_code(&self) -> &str;
+}
+
+impl BillingCoded for InNetworkRateObject {
+    fn billing_code(&self) -> &str {
+        &self.billing_code
+    }
+}
+
+impl BillingCoded for OutOfNetworkObject {
+    fn billing_code(&self) -> &str {
+        &self.billing_code
+    }
+}
 
 pub struct NodeFilters {
     billing_codes: Vec<String>,
@@ -9,14 +45,75 @@ impl NodeFilters {
         NodeFilters { billing_codes }
     }
 
-    pub fn matches(&self, o: &InNetworkRateObject) -> bool {
-        self.billing_codes.len() == 0 || self.billing_codes.contains(&o.billing_code)
+    pub fn matches(&self, o: &impl BillingCoded) -> bool {
+        self.billing_codes.is_empty() || self.billing_codes.iter().any(|c| c == o.billing_code())
+    }
+}
+
+impl<T: BillingCoded> NodeFilter<T> for NodeFilters {
+    fn matches(&self, node: &T) -> bool {
+        NodeFilters::matches(self, node)
+    }
+
+    fn key_field(&self) -> Option<&'static str> {
+        // no codes keeps everything, so there's nothing to skip
+        (!self.billing_codes.is_empty()).then_some("billing_code")
+    }
+
+    fn may_match_key(&self, billing_code: &str) -> bool {
+        self.billing_codes.iter().any(|c| c == billing_code)
+    }
+}
+
+// like `NodeFilters`, but for prescription drugs, which are keyed by ndc instead of billing code
+pub struct NdcFilters {
+    ndcs: Vec<String>,
+}
+
+impl NdcFilters {
+    pub fn new(ndcs: Vec<String>) -> Self {
+        NdcFilters {
+            ndcs: ndcs.iter().map(|ndc| normalize_ndc(ndc)).collect(),
+        }
+    }
+
+    pub fn matches(&self, drug: &PrescriptionDrug) -> bool {
+        self.ndcs.is_empty() || self.ndcs.contains(&normalize_ndc(&drug.national_drug_code))
+    }
+}
+
+impl NodeFilter<PrescriptionDrug> for NdcFilters {
+    fn matches(&self, node: &PrescriptionDrug) -> bool {
+        NdcFilters::matches(self, node)
+    }
+
+    fn key_field(&self) -> Option<&'static str> {
+        (!self.ndcs.is_empty()).then_some("national_drug_code")
+    }
+
+    fn may_match_key(&self, ndc: &str) -> bool {
+        self.ndcs.contains(&normalize_ndc(ndc))
+    }
+}
+
+/// an ndc as its 11 digit, 5-4-2 form, so the same drug matches however it's written.
+/// dashed 10 digit ndcs (4-4-2, 5-3-2 or 5-4-1) are zero padded segment by segment,
+/// anything else just has its dashes and spaces dropped.
+pub fn normalize_ndc(ndc: &str) -> String {
+    let segments: Vec<&str> = ndc.trim().split('-').collect();
+    match segments[..] {
+        [labeler, product, package] => {
+            format!("{labeler:0>5}{product:0>4}{package:0>2}")
+        }
+        _ => ndc.chars().filter(|c| c.is_ascii_digit()).collect(),
     }
 }
 
 #[cfg(test)]
 mod tests {
-    use crate::in_network_file_dto::InNetworkRateObject;
+    use crate::in_network_file_dto::{
+        BillingCodeType, InNetworkRateObject, NegotiationArrangement,
+    };
 
     pub struct FakeInNetworkRateObjectForTesting {
         obj: InNetworkRateObject,
@@ -25,9 +122,9 @@ mod tests {
         fn new() -> Self {
             Self {
                 obj: InNetworkRateObject {
-                    negotiation_arrangement: "".to_string(),
+                    negotiation_arrangement: NegotiationArrangement::Ffs,
                     name: "".to_string(),
-                    billing_code_type: "".to_string(),
+                    billing_code_type: BillingCodeType::Cpt,
                     billing_code_type_version: "".to_string(),
                     billing_code: "1".to_string(),
                     negotiated_rates: vec![],
@@ -57,6 +154,14 @@ mod tests {
         assert!(filters.matches(&o));
     }
 
+    #[test]
+    fn normalizes_ndcs_to_eleven_digits() {
+        assert_eq!(super::normalize_ndc("0002-1433-80"), "00002143380");
+        assert_eq!(super::normalize_ndc("50090-348-00"), "50090034800");
+        assert_eq!(super::normalize_ndc("50090-3480-1"), "50090348001");
+        assert_eq!(super::normalize_ndc("00002143380"), "00002143380");
+    }
+
     #[test]
     fn no_match_on_different_billing_codes() {
         let filters = super::NodeFilters::new(vec!["3".to_string(), "2".to_string()]);
@@ -0,0 +1,89 @@
+use serde::{Deserialize, Serialize};
+use serde_json::Number;
+
+// follows the cms prescription-drugs schema, whose files list drugs by national drug code (ndc)
+// with the prices negotiated at each pharmacy:
+// https://github.com/CMSgov/price-transparency-guide/tree/master/schemas/prescription-drugs
+
+#[derive(Deserialize, Debug, Serialize)]
+pub struct PrescriptionDrugFile {
+    pub reporting_entity_name: String,
+    pub reporting_entity_type: String,
+    pub plan_name: Option<String>,
+    pub plan_id_type: Option<String>,
+    pub plan_id: Option<String>,
+    pub plan_market_type: Option<String>,
+    pub last_updated_on: String,
+    pub version: String,
+
+    pub prescription_drugs: Vec<PrescriptionDrug>,
+}
+
+#[derive(Deserialize, Debug, Serialize)]
+pub struct PrescriptionDrug {
+    pub drug_name: String,
+    /// as the file gives it, with or without dashes. see `node_filters::normalize_ndc`.
+    pub national_drug_code: String,
+    pub pharmacy_prices: Vec<PharmacyPrice>,
+}
+
+// the prices negotiated with a group of pharmacies
+#[derive(Deserialize, Debug, Serialize)]
+pub struct PharmacyPrice {
+    pub pharmacies: Vec<Pharmacy>,
+    pub prices: Vec<DrugPrice>,
+}
+
+#[derive(Deserialize, Debug, Serialize)]
+pub struct Pharmacy {
+    pub npi: Vec<Number>,
+    pub pharmacy_name: Option<String>,
+}
+
+#[derive(Deserialize, Debug, Serialize)]
+pub struct DrugPrice {
+    pub negotiated_rate: Number,
+    pub negotiated_type: String,
+    pub expiration_date: String,
+    pub days_supply: Option<Number>,
+    pub dispensing_fee: Option<Number>,
+    pub additional_information: Option<String>,
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use super::*;
+    use crate::{get_filtered_prescription_drug_file, node_filters::NdcFilters};
+
+    #[test]
+    fn it_filters_prescription_drug_files_by_ndc() {
+        let bytes = fs::read("./tests/fixtures/prescription-drugs-sample.json").unwrap();
+
+        let all: PrescriptionDrugFile = serde_json::from_slice(&bytes).unwrap();
+        assert_eq!(all.prescription_drugs.len(), 2);
+        let price = &all.prescription_drugs[0].pharmacy_prices[0].prices[0];
+        assert_eq!(
+            price.dispensing_fee.as_ref().and_then(|fee| fee.as_f64()),
+            Some(1.5)
+        );
+
+        // the file's dashed 10 digit ndc is matched by its 11 digit form, and vice versa
+        let filters = NdcFilters::new(vec!["00002751001".to_string(), "0071-0155-23".to_string()]);
+        let filtered = get_filtered_prescription_drug_file(&bytes, &filters);
+        let filtered: PrescriptionDrugFile = serde_json::from_str(&filtered).unwrap();
+        let names: Vec<&str> = filtered
+            .prescription_drugs
+            .iter()
+            .map(|drug| drug.drug_name.as_str())
+            .collect();
+        assert_eq!(names, vec!["Humalog", "Lipitor"]);
+
+        let filters = NdcFilters::new(vec!["00071-0155-23".to_string()]);
+        let filtered = get_filtered_prescription_drug_file(&bytes, &filters);
+        let filtered: PrescriptionDrugFile = serde_json::from_str(&filtered).unwrap();
+        assert_eq!(filtered.prescription_drugs.len(), 1);
+        assert_eq!(filtered.prescription_drugs[0].drug_name, "Lipitor");
+    }
+}
@@ -0,0 +1,13 @@
+use serde::{Deserialize, Serialize};
+
+use crate::in_network_file_dto::ProviderGroup;
+
+// follows the cms provider-reference schema, for the files an in-network file's
+// `provider_references[].location` points to instead of listing the groups inline:
+// https://github.com/CMSgov/price-transparency-guide/tree/master/schemas/provider-reference
+
+#[derive(Deserialize, Debug, Serialize)]
+pub struct ProviderReferenceFile {
+    pub version: Option<String>,
+    pub provider_groups: Vec<ProviderGroup>,
+}
@@ -0,0 +1,191 @@
+use std::{
+    collections::HashMap,
+    error::Error,
+    fmt,
+    io::{self, BufReader, Read},
+};
+
+use serde::{
+    de::{self, DeserializeSeed, IgnoredAny, MapAccess, SeqAccess, Visitor},
+    Deserializer,
+};
+
+use crate::{
+    in_network_file_dto::{ProviderGroup, ProviderReference},
+    location::{self, LocationError},
+};
+
+/// an in-network file's provider groups by `provider_group_id`,
+/// whether they were listed inline or in a separate provider reference file
+#[derive(Debug, Default)]
+pub struct ProviderGroupLookup {
+    groups: HashMap<u64, Vec<ProviderGroup>>,
+}
+
+impl ProviderGroupLookup {
+    pub fn get(&self, provider_group_id: u64) -> Option<&[ProviderGroup]> {
+        self.groups.get(&provider_group_id).map(Vec::as_slice)
+    }
+
+    pub fn provider_group_ids(&self) -> impl Iterator<Item = u64> + '_ {
+        self.groups.keys().copied()
+    }
+
+    pub fn len(&self) -> usize {
+        self.groups.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.groups.is_empty()
+    }
+}
+
+#[derive(Debug)]
+pub enum ProviderReferenceError {
+    Io(io::Error),
+    Http(Box<ureq::Error>),
+    Json(serde_json::Error),
+    // the schema has integer ids, which lookups are keyed by
+    InvalidProviderGroupId(String),
+}
+
+impl fmt::Display for ProviderReferenceError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ProviderReferenceError::Io(e) => write!(f, "{e}"),
+            ProviderReferenceError::Http(e) => write!(f, "{e}"),
+            ProviderReferenceError::Json(e) => write!(f, "{e}"),
+            ProviderReferenceError::InvalidProviderGroupId(id) => {
+                write!(f, "provider_group_id {id} isn't a non-negative integer")
+            }
+        }
+    }
+}
+
+impl Error for ProviderReferenceError {}
+
+impl From<io::Error> for ProviderReferenceError {
+    fn from(e: io::Error) -> Self {
+        ProviderReferenceError::Io(e)
+    }
+}
+
+impl From<serde_json::Error> for ProviderReferenceError {
+    fn from(e: serde_json::Error) -> Self {
+        ProviderReferenceError::Json(e)
+    }
+}
+
+impl From<LocationError> for ProviderReferenceError {
+    fn from(e: LocationError) -> Self {
+        match e {
+            LocationError::Io(e) => ProviderReferenceError::Io(e),
+            LocationError::Http(e) => ProviderReferenceError::Http(e),
+            LocationError::Json(e) => ProviderReferenceError::Json(e),
+        }
+    }
+}
+
+/// collects every reference's provider groups, reading those with a `location` from their
+/// local path or http(s) url. a reference may have both, in which case both are kept.
+pub fn resolve_provider_references(
+    references: impl IntoIterator<Item = ProviderReference>,
+) -> Result<ProviderGroupLookup, ProviderReferenceError> {
+    let mut lookup = ProviderGroupLookup::default();
+    for reference in references {
+        let id = reference.provider_group_id.as_u64().ok_or_else(|| {
+            ProviderReferenceError::InvalidProviderGroupId(reference.provider_group_id.to_string())
+        })?;
+        let groups = lookup.groups.entry(id).or_default();
+        groups.extend(reference.provider_groups.unwrap_or_default());
+        if let Some(location) = reference.location {
+            println!("reading provider groups {id} from {location}");
+            let (reader, _) = location::open_location(&location)?;
+            for_each_provider_group(reader, |group| groups.push(group))?;
+        }
+    }
+    Ok(lookup)
+}
+
+/// hands each of a provider reference file's groups to `f` as it's read,
+/// so even huge files never have more than one group in memory
+pub fn for_each_provider_group<R, F>(reader: R, mut f: F) -> serde_json::Result<()>
+where
+    R: Read,
+    F: FnMut(ProviderGroup),
+{
+    let mut deserializer = serde_json::Deserializer::from_reader(BufReader::new(reader));
+    ProviderReferenceFileSeed(&mut f).deserialize(&mut deserializer)?;
+    deserializer.end()
+}
+
+struct ProviderReferenceFileSeed<'f, F>(&'f mut F);
+
+impl<'de, F: FnMut(ProviderGroup)> DeserializeSeed<'de> for ProviderReferenceFileSeed<'_, F> {
+    type Value = ();
+
+    fn deserialize<D>(self, deserializer: D) -> Result<(), D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        deserializer.deserialize_map(self)
+    }
+}
+
+impl<'de, F: FnMut(ProviderGroup)> Visitor<'de> for ProviderReferenceFileSeed<'_, F> {
+    type Value = ();
+
+    fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+        formatter.write_str("a provider reference file")
+    }
+
+    fn visit_map<M>(self, mut map: M) -> Result<(), M::Error>
+    where
+        M: MapAccess<'de>,
+    {
+        let mut found_groups = false;
+        while let Some(key) = map.next_key::<String>()? {
+            if key == "provider_groups" {
+                map.next_value_seed(EachProviderGroup(&mut *self.0))?;
+                found_groups = true;
+            } else {
+                map.next_value::<IgnoredAny>()?;
+            }
+        }
+        if !found_groups {
+            return Err(de::Error::missing_field("provider_groups"));
+        }
+        Ok(())
+    }
+}
+
+struct EachProviderGroup<'f, F>(&'f mut F);
+
+impl<'de, F: FnMut(ProviderGroup)> DeserializeSeed<'de> for EachProviderGroup<'_, F> {
+    type Value = ();
+
+    fn deserialize<D>(self, deserializer: D) -> Result<(), D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        deserializer.deserialize_seq(self)
+    }
+}
+
+impl<'de, F: FnMut(ProviderGroup)> Visitor<'de> for EachProviderGroup<'_, F> {
+    type Value = ();
+
+    fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+        formatter.write_str("an array of provider groups")
+    }
+
+    fn visit_seq<S>(self, mut seq: S) -> Result<(), S::Error>
+    where
+        S: SeqAccess<'de>,
+    {
+        while let Some(group) = seq.next_element()? {
+            (self.0)(group);
+        }
+        Ok(())
+    }
+}
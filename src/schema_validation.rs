@@ -0,0 +1,484 @@
+use std::{
+    collections::HashSet,
+    fmt,
+    io::{BufReader, Read},
+    sync::OnceLock,
+};
+
+use serde::{
+    de::{DeserializeSeed, MapAccess, SeqAccess, Visitor},
+    Deserializer, Serialize,
+};
+use serde_json::{Map, Value};
+
+use crate::location::{self, LocationError};
+
+// the most violations a report lists. every violation is still counted.
+pub const MAX_REPORTED_VIOLATIONS: usize = 10_000;
+
+#[derive(Debug, Serialize, PartialEq, Eq)]
+pub struct SchemaViolation {
+    /// where in the file, e.g. `$.in_network[3].negotiated_rates[0].negotiated_prices[1]`
+    pub path: String,
+    pub message: String,
+}
+
+#[derive(Debug, Serialize, Default)]
+pub struct ValidationReport {
+    pub num_rate_objects: usize,
+    pub num_violations: usize,
+    pub violations: Vec<SchemaViolation>,
+}
+
+impl ValidationReport {
+    pub fn is_valid(&self) -> bool {
+        self.num_violations == 0
+    }
+
+    fn add(&mut self, violations: Vec<SchemaViolation>) {
+        self.num_violations += violations.len();
+        let room = MAX_REPORTED_VIOLATIONS.saturating_sub(self.violations.len());
+        self.violations.extend(violations.into_iter().take(room));
+    }
+}
+
+/// checks an in-network file against the cms schema bundled as `in-network-rates.json`,
+/// one rate object at a time as it's read, so the whole file is never in memory.
+/// only json that isn't well formed is an error, anything else is reported as a violation.
+pub fn validate_in_network_file<R: Read>(reader: R) -> serde_json::Result<ValidationReport> {
+    let schema = JsonSchema::in_network_rates();
+    let mut deserializer = serde_json::Deserializer::from_reader(BufReader::new(reader));
+    let report = ValidatingInNetworkFile(schema).deserialize(&mut deserializer)?;
+    deserializer.end()?;
+    Ok(report)
+}
+
+// like `validate_in_network_file`, for a local path or an http(s) url
+pub fn validate_in_network_file_at(location: &str) -> Result<ValidationReport, LocationError> {
+    let (reader, _) = location::open_location(location)?;
+    Ok(validate_in_network_file(reader)?)
+}
+
+/// the subset of json schema the cms schemas use:
+/// `$ref` to local definitions, `type`, `enum`, `const`, `required`, `properties`, `items`,
+/// `uniqueItems`, `anyOf`, `oneOf`, `if`/`then`/`else`, `dependentRequired`,
+/// and `pattern`s that are just an anchored prefix.
+/// anything else, like `format`, is ignored.
+pub struct JsonSchema {
+    root: Value,
+}
+
+impl JsonSchema {
+    pub fn new(root: Value) -> Self {
+        JsonSchema { root }
+    }
+
+    pub fn in_network_rates() -> &'static JsonSchema {
+        static SCHEMA: OnceLock<JsonSchema> = OnceLock::new();
+        SCHEMA.get_or_init(|| {
+            let root = serde_json::from_str(include_str!("../in-network-rates.json"))
+                .expect("bundled schema to be valid json");
+            JsonSchema::new(root)
+        })
+    }
+
+    /// the violations of `value`, found at `path`, against the schema at `pointer`,
+    /// e.g. `#/definitions/in_network`, or `#` for the whole schema
+    pub fn validate(&self, pointer: &str, value: &Value, path: &str) -> Vec<SchemaViolation> {
+        let mut violations = vec![];
+        match self.resolve(pointer) {
+            Some(schema) => self.check(schema, value, path, &mut violations),
+            None => violations.push(SchemaViolation {
+                path: path.to_string(),
+                message: format!("schema has no {pointer}"),
+            }),
+        }
+        violations
+    }
+
+    fn resolve(&self, pointer: &str) -> Option<&Value> {
+        self.root.pointer(pointer.strip_prefix('#')?)
+    }
+
+    fn is_valid(&self, schema: &Value, value: &Value, path: &str) -> bool {
+        let mut violations = vec![];
+        self.check(schema, value, path, &mut violations);
+        violations.is_empty()
+    }
+
+    fn check(&self, schema: &Value, value: &Value, path: &str, out: &mut Vec<SchemaViolation>) {
+        let Some(schema) = schema.as_object() else {
+            return;
+        };
+        if let Some(pointer) = schema.get("$ref").and_then(Value::as_str) {
+            match self.resolve(pointer) {
+                Some(referenced) => self.check(referenced, value, path, out),
+                None => out.push(SchemaViolation {
+                    path: path.to_string(),
+                    message: format!("schema has no {pointer}"),
+                }),
+            }
+            return;
+        }
+
+        let mut violation = |message: String| {
+            out.push(SchemaViolation {
+                path: path.to_string(),
+                message,
+            })
+        };
+
+        if let Some(expected) = schema.get("type") {
+            let types: Vec<&str> = match expected {
+                Value::String(t) => vec![t.as_str()],
+                Value::Array(ts) => ts.iter().filter_map(Value::as_str).collect(),
+                _ => vec![],
+            };
+            if !types.is_empty() && !types.iter().any(|t| has_type(value, t)) {
+                violation(format!(
+                    "expected {}, found {}",
+                    types.join(" or "),
+                    type_of(value)
+                ));
+                // the rest of the keywords are about the expected type
+                return;
+            }
+        }
+        if let Some(allowed) = schema.get("enum").and_then(Value::as_array) {
+            if !allowed.contains(value) {
+                violation(format!(
+                    "{value} is not one of {}",
+                    Value::from(allowed.clone())
+                ));
+            }
+        }
+        if let Some(expected) = schema.get("const") {
+            if expected != value {
+                violation(format!("expected {expected}, found {value}"));
+            }
+        }
+        if let Some(pattern) = schema.get("pattern").and_then(Value::as_str) {
+            let prefix = pattern
+                .strip_prefix('^')
+                .filter(|p| !p.contains(is_regex_syntax));
+            if let (Some(prefix), Some(s)) = (prefix, value.as_str()) {
+                if !s.starts_with(prefix) {
+                    violation(format!("{value} doesn't start with {prefix:?}"));
+                }
+            }
+        }
+
+        if let Some(object) = value.as_object() {
+            self.check_object(schema, object, path, out);
+        }
+        if let Some(items) = value.as_array() {
+            self.check_array(schema, items, path, out);
+        }
+
+        let mut violation = |message: String| {
+            out.push(SchemaViolation {
+                path: path.to_string(),
+                message,
+            })
+        };
+        if let Some(options) = schema.get("anyOf").and_then(Value::as_array) {
+            if !options
+                .iter()
+                .any(|option| self.is_valid(option, value, path))
+            {
+                violation(format!("doesn't match any of {}", describe(options)));
+            }
+        }
+        if let Some(options) = schema.get("oneOf").and_then(Value::as_array) {
+            let matches = options
+                .iter()
+                .filter(|option| self.is_valid(option, value, path))
+                .count();
+            if matches != 1 {
+                violation(format!(
+                    "matches {matches} of {}, instead of exactly one",
+                    describe(options)
+                ));
+            }
+        }
+        if let Some(condition) = schema.get("if") {
+            let branch = if self.is_valid(condition, value, path) {
+                schema.get("then")
+            } else {
+                schema.get("else")
+            };
+            if let Some(branch) = branch {
+                self.check(branch, value, path, out);
+            }
+        }
+    }
+
+    fn check_object(
+        &self,
+        schema: &Map<String, Value>,
+        object: &Map<String, Value>,
+        path: &str,
+        out: &mut Vec<SchemaViolation>,
+    ) {
+        for key in schema
+            .get("required")
+            .and_then(Value::as_array)
+            .into_iter()
+            .flatten()
+            .filter_map(Value::as_str)
+        {
+            if !object.contains_key(key) {
+                out.push(SchemaViolation {
+                    path: path.to_string(),
+                    message: format!("missing required field {key:?}"),
+                });
+            }
+        }
+        if let Some(dependents) = schema.get("dependentRequired").and_then(Value::as_object) {
+            for (key, required) in dependents {
+                if !object.contains_key(key) {
+                    continue;
+                }
+                for dependent in required.as_array().into_iter().flatten() {
+                    let Some(dependent) = dependent.as_str() else {
+                        continue;
+                    };
+                    if !object.contains_key(dependent) {
+                        out.push(SchemaViolation {
+                            path: path.to_string(),
+                            message: format!("{key:?} requires {dependent:?} too"),
+                        });
+                    }
+                }
+            }
+        }
+        if let Some(properties) = schema.get("properties").and_then(Value::as_object) {
+            for (key, property) in properties {
+                if let Some(value) = object.get(key) {
+                    self.check(property, value, &format!("{path}.{key}"), out);
+                }
+            }
+        }
+    }
+
+    fn check_array(
+        &self,
+        schema: &Map<String, Value>,
+        items: &[Value],
+        path: &str,
+        out: &mut Vec<SchemaViolation>,
+    ) {
+        if let Some(item_schema) = schema.get("items") {
+            for (i, item) in items.iter().enumerate() {
+                self.check(item_schema, item, &format!("{path}[{i}]"), out);
+            }
+        }
+        if schema.get("uniqueItems") == Some(&Value::Bool(true)) {
+            let mut seen = HashSet::new();
+            for (i, item) in items.iter().enumerate() {
+                if !seen.insert(item.to_string()) {
+                    out.push(SchemaViolation {
+                        path: format!("{path}[{i}]"),
+                        message: "duplicates an earlier item".to_string(),
+                    });
+                }
+            }
+        }
+    }
+}
+
+fn has_type(value: &Value, t: &str) -> bool {
+    match t {
+        // json schema counts numbers without a fractional part as integers, e.g. `1.0`
+        "integer" => {
+            value.is_i64() || value.is_u64() || value.as_f64().is_some_and(|n| n.fract() == 0.0)
+        }
+        t => type_of(value) == t,
+    }
+}
+
+fn type_of(value: &Value) -> &'static str {
+    match value {
+        Value::Null => "null",
+        Value::Bool(_) => "boolean",
+        Value::Number(_) => "number",
+        Value::String(_) => "string",
+        Value::Array(_) => "array",
+        Value::Object(_) => "object",
+    }
+}
+
+fn is_regex_syntax(c: char) -> bool {
+    "\\.^$|?*+()[]{}".contains(c)
+}
+
+// e.g. `[{"required":["location"]},{"required":["provider_groups"]}]`
+fn describe(options: &[Value]) -> String {
+    Value::from(options.to_vec()).to_string()
+}
+
+// collects every field but `in_network`, whose rate objects are validated one at a time
+struct ValidatingInNetworkFile<'a>(&'a JsonSchema);
+
+impl<'de> DeserializeSeed<'de> for ValidatingInNetworkFile<'_> {
+    type Value = ValidationReport;
+
+    fn deserialize<D>(self, deserializer: D) -> Result<Self::Value, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        deserializer.deserialize_map(self)
+    }
+}
+
+impl<'de> Visitor<'de> for ValidatingInNetworkFile<'_> {
+    type Value = ValidationReport;
+
+    fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+        formatter.write_str("an in network file")
+    }
+
+    fn visit_map<M>(self, mut map: M) -> Result<ValidationReport, M::Error>
+    where
+        M: MapAccess<'de>,
+    {
+        let mut report = ValidationReport::default();
+        let mut fields = Map::new();
+        while let Some(key) = map.next_key::<String>()? {
+            if key == "in_network" {
+                map.next_value_seed(ValidatingRateObjects(self.0, &mut report))?;
+                fields.insert(key, Value::Array(vec![]));
+            } else {
+                fields.insert(key, map.next_value()?);
+            }
+        }
+
+        // the rest of the file, with its rate objects already checked
+        let violations = self.0.validate("#", &Value::Object(fields), "$");
+        report.add(violations);
+        Ok(report)
+    }
+}
+
+struct ValidatingRateObjects<'a, 'r>(&'a JsonSchema, &'r mut ValidationReport);
+
+impl<'de> DeserializeSeed<'de> for ValidatingRateObjects<'_, '_> {
+    type Value = ();
+
+    fn deserialize<D>(self, deserializer: D) -> Result<(), D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        deserializer.deserialize_seq(self)
+    }
+}
+
+impl<'de> Visitor<'de> for ValidatingRateObjects<'_, '_> {
+    type Value = ();
+
+    fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+        formatter.write_str("an array of in network rate objects")
+    }
+
+    fn visit_seq<S>(self, mut seq: S) -> Result<(), S::Error>
+    where
+        S: SeqAccess<'de>,
+    {
+        while let Some(rate_object) = seq.next_element::<Value>()? {
+            let path = format!("$.in_network[{}]", self.1.num_rate_objects);
+            let violations = self
+                .0
+                .validate("#/definitions/in_network", &rate_object, &path);
+            self.1.add(violations);
+            self.1.num_rate_objects += 1;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs::File;
+
+    use serde_json::json;
+
+    use super::*;
+
+    fn messages(schema: Value, value: Value) -> Vec<String> {
+        JsonSchema::new(schema)
+            .validate("#", &value, "$")
+            .into_iter()
+            .map(|v| format!("{}: {}", v.path, v.message))
+            .collect()
+    }
+
+    #[test]
+    fn it_counts_whole_numbers_as_integers() {
+        let schema = json!({ "type": "integer" });
+
+        for integer in [json!(1), json!(-1), json!(1.0)] {
+            assert!(messages(schema.clone(), integer).is_empty());
+        }
+        assert_eq!(
+            messages(schema, json!(1.5)),
+            vec!["$: expected integer, found number"]
+        );
+    }
+
+    #[test]
+    fn it_checks_conditional_requirements() {
+        let schema = json!({
+            "properties": { "billing_class": { "enum": ["professional", "institutional"] } },
+            "if": { "properties": { "billing_class": { "const": "professional" } } },
+            "then": { "required": ["service_code"] }
+        });
+
+        assert!(messages(schema.clone(), json!({ "billing_class": "institutional" })).is_empty());
+        assert_eq!(
+            messages(schema, json!({ "billing_class": "professional" })),
+            vec!["$: missing required field \"service_code\""]
+        );
+    }
+
+    #[test]
+    fn it_checks_one_of_and_unique_items() {
+        let schema = json!({
+            "properties": { "ids": { "type": "array", "uniqueItems": true } },
+            "oneOf": [{ "required": ["ids"] }, { "required": ["groups"] }]
+        });
+
+        assert_eq!(
+            messages(schema, json!({ "ids": [1, 1], "groups": [] })),
+            vec![
+                "$.ids[1]: duplicates an earlier item",
+                "$: matches 2 of [{\"required\":[\"ids\"]},{\"required\":[\"groups\"]}], instead of exactly one"
+            ]
+        );
+    }
+
+    #[test]
+    fn it_reports_schema_violations_with_their_json_paths() {
+        let validate = |path: &str| validate_in_network_file(File::open(path).unwrap()).unwrap();
+
+        // professional prices must list their service codes
+        let sample = validate("./tests/fixtures/in-network-rates-sample.json");
+        assert_eq!(sample.num_rate_objects, 3);
+        assert_eq!(
+            sample.violations,
+            vec![SchemaViolation {
+                path: "$.in_network[0].negotiated_rates[0].negotiated_prices[0]".to_string(),
+                message: "missing required field \"service_code\"".to_string(),
+            }]
+        );
+
+        let legacy = validate("./tests/fixtures/in-network-rates-legacy.json");
+        assert!(!legacy.is_valid());
+        let paths: Vec<&str> = legacy.violations.iter().map(|v| v.path.as_str()).collect();
+        assert!(paths.contains(&"$.in_network[0].negotiated_rates[0].provider_groups[0].npi"));
+        assert!(paths.contains(&"$.provider_references[0].provider_groups[0].npi[1]"));
+        assert!(legacy
+            .violations
+            .iter()
+            .any(|v| v.path == "$" && v.message.contains("version")));
+    }
+}
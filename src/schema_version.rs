@@ -0,0 +1,303 @@
+use serde::Serialize;
+use serde_json::{Map, Value};
+
+use crate::lenient;
+
+/// the schema version an in-network file declares.
+/// the dtos in `in_network_file_dto` are canonical for 1.x files, which are deserialized as is.
+/// other versions' rate objects are rewritten into that shape first, see `rate_object_adapter`.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub enum SchemaVersion {
+    /// no version, or a pre-1.0 draft
+    Legacy,
+    V1,
+    V2,
+    /// a version we don't know about yet, kept as declared
+    Unknown(String),
+}
+
+impl SchemaVersion {
+    pub fn from_declared(version: Option<&str>) -> SchemaVersion {
+        let Some(version) = version.map(str::trim).filter(|v| !v.is_empty()) else {
+            return SchemaVersion::Legacy;
+        };
+        let major = version
+            .trim_start_matches(['v', 'V'])
+            .split('.')
+            .next()
+            .and_then(|major| major.parse::<u32>().ok());
+        match major {
+            Some(0) => SchemaVersion::Legacy,
+            Some(1) => SchemaVersion::V1,
+            Some(2) => SchemaVersion::V2,
+            _ => SchemaVersion::Unknown(version.to_string()),
+        }
+    }
+
+    /// how to rewrite the version's rate objects into the canonical dtos' shape,
+    /// or `None` when they already fit them
+    pub fn rate_object_adapter(&self) -> Option<fn(&mut Value)> {
+        match self {
+            SchemaVersion::Legacy => Some(normalize_legacy_rate_object),
+            SchemaVersion::V1 => None,
+            SchemaVersion::V2 => Some(normalize_v2_rate_object),
+            // it may differ in any of the ways the versions we know do
+            SchemaVersion::Unknown(_) => Some(normalize_rate_object),
+        }
+    }
+
+    /// how to rewrite the fields of the version's files besides `in_network`,
+    /// or `None` when they already fit the dtos
+    pub fn file_fields_adapter(&self) -> Option<fn(&mut Map<String, Value>)> {
+        match self {
+            SchemaVersion::V1 => None,
+            _ => Some(normalize_in_network_file_fields),
+        }
+    }
+}
+
+// each normalization leaves canonical json as it is, so they're safe to apply to any version
+
+/// rewrites a pre-1.0 rate object into the canonical dtos' shape:
+/// - `negotiated_price` becomes `negotiated_rate`
+/// - rates, npis and provider references given as strings become numbers
+/// - a single npi becomes a list of one
+pub fn normalize_legacy_rate_object(rate_object: &mut Value) {
+    normalize_negotiated_rates(rate_object, &["negotiated_price"]);
+}
+
+/// rewrites a 2.x rate object into the canonical dtos' shape:
+/// - `cost` becomes `negotiated_rate`
+/// - rates, npis and provider references given as strings become numbers, and a single npi a list
+/// - covered services and bundled codes without a `billing_code_type_version` get an empty one
+pub fn normalize_v2_rate_object(rate_object: &mut Value) {
+    version_codes(rate_object);
+    normalize_negotiated_rates(rate_object, &["cost"]);
+}
+
+/// rewrites an in-network rate object of any schema version into the canonical dtos' shape,
+/// as both `normalize_legacy_rate_object` and `normalize_v2_rate_object` do
+pub fn normalize_rate_object(rate_object: &mut Value) {
+    version_codes(rate_object);
+    normalize_negotiated_rates(rate_object, &["negotiated_price", "cost"]);
+}
+
+fn version_codes(rate_object: &mut Value) {
+    for key in ["bundled_codes", "covered_services"] {
+        for code in array_items(rate_object.get_mut(key)) {
+            if let Some(code) = code.as_object_mut() {
+                code.entry("billing_code_type_version")
+                    .or_insert(Value::String(String::new()));
+            }
+        }
+    }
+}
+
+// `rate_keys` are what the version calls `negotiated_rate`
+fn normalize_negotiated_rates(rate_object: &mut Value, rate_keys: &[&str]) {
+    for rate in array_items(rate_object.get_mut("negotiated_rates")) {
+        for group in array_items(rate.get_mut("provider_groups")) {
+            normalize_provider_group(group);
+        }
+        if let Some(references) = rate.get_mut("provider_references") {
+            lenient::coerce_numbers(references, "", None);
+        }
+        for price in array_items(rate.get_mut("negotiated_prices")) {
+            if let Some(price) = price.as_object_mut() {
+                normalize_negotiated_price(price, rate_keys);
+            }
+        }
+    }
+}
+
+/// rewrites the fields of an in-network file besides `in_network`, see `normalize_rate_object`
+pub fn normalize_in_network_file_fields(fields: &mut Map<String, Value>) {
+    // legacy files may not declare a version at all
+    fields
+        .entry("version")
+        .or_insert(Value::String(String::new()));
+    for reference in array_items(fields.get_mut("provider_references")) {
+        if let Some(id) = reference.get_mut("provider_group_id") {
+            lenient::coerce_number(id, "", None);
+        }
+        for group in array_items(reference.get_mut("provider_groups")) {
+            normalize_provider_group(group);
+        }
+    }
+}
+
+fn normalize_negotiated_price(price: &mut Map<String, Value>, rate_keys: &[&str]) {
+    if !price.contains_key("negotiated_rate") {
+        let rate = rate_keys.iter().find_map(|key| price.remove(*key));
+        if let Some(rate) = rate {
+            price.insert("negotiated_rate".to_string(), rate);
+        }
+    }
+    if let Some(rate) = price.get_mut("negotiated_rate") {
+        lenient::coerce_number(rate, "", None);
+    }
+}
+
+fn normalize_provider_group(group: &mut Value) {
+    if let Some(npi) = group.get_mut("npi") {
+        lenient::coerce_npis(npi, "", None);
+    }
+}
+
+fn array_items(value: Option<&mut Value>) -> impl Iterator<Item = &mut Value> {
+    value
+        .and_then(Value::as_array_mut)
+        .into_iter()
+        .flat_map(|items| items.iter_mut())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs::File;
+
+    use serde_json::json;
+
+    use super::*;
+    use crate::{
+        deserialize_filtered_in_network_file, in_network_file_dto::InNetworkFile,
+        node_filters::NodeFilters,
+    };
+
+    #[test]
+    fn it_detects_declared_versions() {
+        assert_eq!(SchemaVersion::from_declared(None), SchemaVersion::Legacy);
+        assert_eq!(
+            SchemaVersion::from_declared(Some("0.9")),
+            SchemaVersion::Legacy
+        );
+        assert_eq!(
+            SchemaVersion::from_declared(Some("1.0.0")),
+            SchemaVersion::V1
+        );
+        assert_eq!(
+            SchemaVersion::from_declared(Some("v2.0")),
+            SchemaVersion::V2
+        );
+        assert_eq!(
+            SchemaVersion::from_declared(Some("3.1.0")),
+            SchemaVersion::Unknown("3.1.0".to_string())
+        );
+    }
+
+    #[test]
+    fn it_leaves_canonical_rate_objects_alone() {
+        let canonical = json!({
+            "billing_code": "27447",
+            "bundled_codes": [{ "billing_code": "1", "billing_code_type_version": "2020" }],
+            "negotiated_rates": [{
+                "provider_groups": [{ "npi": [1111111111] }],
+                "negotiated_prices": [{ "negotiated_rate": 1500.5 }]
+            }]
+        });
+        for adapter in [
+            normalize_legacy_rate_object,
+            normalize_v2_rate_object,
+            normalize_rate_object,
+        ] {
+            let mut normalized = canonical.clone();
+            adapter(&mut normalized);
+            assert_eq!(normalized, canonical);
+        }
+    }
+
+    #[test]
+    fn it_routes_versions_to_their_adapters() {
+        let rate_object = json!({
+            "bundled_codes": [{ "billing_code": "1" }],
+            "negotiated_rates": [{
+                "negotiated_prices": [{ "negotiated_price": "12.50" }, { "cost": 12.5 }]
+            }]
+        });
+        let adapt = |version: &str| {
+            let mut rate_object = rate_object.clone();
+            if let Some(adapter) = SchemaVersion::from_declared(Some(version)).rate_object_adapter()
+            {
+                adapter(&mut rate_object);
+            }
+            rate_object
+        };
+
+        assert_eq!(adapt("1.0.0"), rate_object);
+        assert_eq!(
+            adapt("0.9"),
+            json!({
+                "bundled_codes": [{ "billing_code": "1" }],
+                "negotiated_rates": [{
+                    "negotiated_prices": [{ "negotiated_rate": 12.5 }, { "cost": 12.5 }]
+                }]
+            })
+        );
+        assert_eq!(
+            adapt("2.0.0"),
+            json!({
+                "bundled_codes": [{ "billing_code": "1", "billing_code_type_version": "" }],
+                "negotiated_rates": [{
+                    "negotiated_prices": [{ "negotiated_price": "12.50" }, { "negotiated_rate": 12.5 }]
+                }]
+            })
+        );
+        assert_eq!(
+            adapt("3.0.0"),
+            json!({
+                "bundled_codes": [{ "billing_code": "1", "billing_code_type_version": "" }],
+                "negotiated_rates": [{
+                    "negotiated_prices": [{ "negotiated_rate": 12.5 }, { "negotiated_rate": 12.5 }]
+                }]
+            })
+        );
+    }
+
+    #[test]
+    fn it_normalizes_other_schema_versions_into_the_same_dtos() {
+        let all = NodeFilters::new(vec![]);
+        let parse = |path: &str| -> InNetworkFile {
+            deserialize_filtered_in_network_file(File::open(path).unwrap(), &all).unwrap()
+        };
+
+        // no version, with npis, ids and rates as strings, and `negotiated_price` for the rate
+        let legacy = parse("./tests/fixtures/in-network-rates-legacy.json");
+        assert_eq!(legacy.schema_version(), SchemaVersion::Legacy);
+        let references = legacy.provider_references.as_ref().unwrap();
+        assert_eq!(references[0].provider_group_id.as_u64(), Some(1));
+        assert_eq!(
+            references[0].provider_groups.as_ref().unwrap()[0].npi[1].as_u64(),
+            Some(2222222222)
+        );
+        let rate = &legacy.in_network[0].negotiated_rates[0];
+        assert_eq!(
+            rate.provider_groups.as_ref().unwrap()[0].npi[0].as_u64(),
+            Some(3333333333)
+        );
+        assert_eq!(
+            rate.negotiated_prices[0].negotiated_rate.as_f64(),
+            Some(1500.5)
+        );
+
+        // declared after `in_network`, with `cost` for the rate and unversioned bundled codes
+        let v2 = parse("./tests/fixtures/in-network-rates-v2.json");
+        assert_eq!(v2.schema_version(), SchemaVersion::V2);
+        let rate_object = &v2.in_network[0];
+        assert_eq!(
+            rate_object.bundled_codes.as_ref().unwrap()[0].billing_code_type_version,
+            ""
+        );
+        let rate = &rate_object.negotiated_rates[0];
+        assert_eq!(
+            rate.provider_references.as_ref().unwrap()[0].as_u64(),
+            Some(1)
+        );
+        assert_eq!(
+            rate.negotiated_prices[0].negotiated_rate.as_f64(),
+            Some(1500.5)
+        );
+
+        let v1 = parse("./tests/fixtures/in-network-rates-sample.json");
+        assert_eq!(v1.schema_version(), SchemaVersion::V1);
+        assert_eq!(v1.in_network.len(), 3);
+    }
+}
@@ -1,26 +1,43 @@
-use std::{
-    error::Error,
-    fmt,
-    marker::PhantomData,
-    sync::mpsc::{sync_channel, SyncSender},
-};
+use std::{fmt, marker::PhantomData, sync::mpsc::SyncSender};
 
 use serde::{
-    de::{SeqAccess, Visitor},
+    de::{DeserializeSeed, IgnoredAny, SeqAccess, Visitor},
     Deserialize, Deserializer,
 };
 
-use super::channel_generator::ChannelGenerator;
-
-// an implementation of a serde deserializer that returns a channel receiver immediately,
-// and in a background thread starts deserializing objects and sending them to a channel,
-// up to a max amount of memory.
-// the function that calls the deserializer then can receive deserialized objects from the channel and process them
+// an implementation of a serde deserializer that sends each object of an array to a channel
+// as it's deserialized. it runs on a background thread, and the channel is bounded,
+// so it blocks once it's read that many objects ahead, keeping memory bounded.
+// the thread that holds the receiver then can receive deserialized objects from the channel and process them,
+// e.g. through a `ChannelGenerator`. errors are returned to the background thread, not sent.
 pub struct ChannelVisitor<T> {
     pub sender: SyncSender<T>,
     pub f: PhantomData<fn() -> T>,
 }
 
+impl<T> ChannelVisitor<T> {
+    pub fn new(sender: SyncSender<T>) -> Self {
+        ChannelVisitor {
+            sender,
+            f: PhantomData,
+        }
+    }
+}
+
+impl<'de, T> DeserializeSeed<'de> for ChannelVisitor<T>
+where
+    T: Deserialize<'de> + Send,
+{
+    type Value = ();
+
+    fn deserialize<D>(self, deserializer: D) -> Result<Self::Value, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        deserializer.deserialize_seq(self)
+    }
+}
+
 impl<'de, T> Visitor<'de> for ChannelVisitor<T>
 where
     T: Deserialize<'de> + Send,
@@ -37,26 +54,11 @@ where
     {
         while let Some(n) = seq.next_element::<T>()? {
             if self.sender.send(n).is_err() {
+                // nothing's receiving anymore, so the rest is only read past
+                while seq.next_element::<IgnoredAny>()?.is_some() {}
                 break;
             }
         }
         Ok(())
     }
 }
-
-pub fn deserialize_to_channel<'de, T, D, E>(deserializer: D) -> Result<ChannelGenerator<T>, E>
-where
-    T: Deserialize<'de> + Send,
-    D: Deserializer<'de>,
-    E: Error,
-{
-    let (node_sender, node_receiver) = sync_channel::<T>(0);
-    let visitor = ChannelVisitor {
-        sender: node_sender,
-        f: PhantomData,
-    };
-    deserializer.deserialize_seq(visitor).unwrap();
-    Ok(ChannelGenerator {
-        receiver: node_receiver,
-    })
-}
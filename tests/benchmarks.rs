@@ -1,4 +1,162 @@
-#[allow(dead_code)]
-fn benchmark() {
-  todo!()
+use std::{
+    borrow::Cow,
+    time::{Duration, Instant},
+};
+
+use serde::Deserialize;
+use serde_json::{json, Value};
+
+use rust_cms_json_parser::{
+    deserialize_filtered_borrowed_rate_objects, deserialize_filtered_in_network_file,
+    in_network_file_dto::InNetworkRateObject, node_filters::NodeFilters,
+};
+
+const NUM_RATE_OBJECTS: usize = 2_000;
+const NUM_NEGOTIATED_RATES: usize = 50;
+
+// an in-network file with one rate object per billing code 0..NUM_RATE_OBJECTS,
+// each with a realistically big `negotiated_rates` array
+fn in_network_file() -> String {
+    let negotiated_rates: Vec<Value> = (0..NUM_NEGOTIATED_RATES)
+        .map(|i| {
+            json!({
+                "provider_groups": [{
+                    "npi": [1111111111u64, 2222222222u64, 3333333333u64],
+                    "tin": { "type": "ein", "value": format!("11-{i:07}") }
+                }],
+                "negotiated_prices": [{
+                    "negotiated_type": "negotiated",
+                    "negotiated_rate": 100.25 + i as f64,
+                    "expiration_date": "2022-12-31",
+                    "service_code": ["11", "22"],
+                    "billing_class": "professional"
+                }]
+            })
+        })
+        .collect();
+    let in_network: Vec<Value> = (0..NUM_RATE_OBJECTS)
+        .map(|code| {
+            json!({
+                "negotiation_arrangement": "ffs",
+                "name": "procedure",
+                "billing_code_type": "CPT",
+                "billing_code_type_version": "2022",
+                "billing_code": code.to_string(),
+                "description": "a procedure",
+                "negotiated_rates": negotiated_rates
+            })
+        })
+        .collect();
+    // written by hand, as `json!` would sort `version` after `in_network`,
+    // and files whose version isn't known by then have every rate object normalized
+    format!(
+        r#"{{"version": "1.0.0", "reporting_entity_name": "benchmark", "reporting_entity_type": "benchmark", "last_updated_on": "2022-01-01", "in_network": {}}}"#,
+        Value::Array(in_network)
+    )
+}
+
+// every rate object, deserialized in full
+#[derive(Deserialize)]
+struct UnfilteredInNetworkFile {
+    in_network: Vec<InNetworkRateObject>,
+}
+
+fn time<T>(f: impl Fn() -> T) -> (T, Duration) {
+    let start = Instant::now();
+    let result = f();
+    (result, start.elapsed())
+}
+
+// run with `cargo test --release --test benchmarks -- --ignored --nocapture`
+#[test]
+#[ignore]
+fn benchmark_filtering_rate_objects() {
+    let json = in_network_file();
+    let filters = NodeFilters::new(vec!["1000".to_string()]);
+
+    // what filtering cost before: every rate object deserialized in full, then matched
+    let (full, full_time) = time(|| {
+        let file: UnfilteredInNetworkFile = serde_json::from_str(&json).unwrap();
+        file.in_network
+            .into_iter()
+            .filter(|rate_object| filters.matches(rate_object))
+            .collect::<Vec<_>>()
+    });
+    // non-matching rate objects are skipped once their billing code is read
+    let (fast, fast_time) = time(|| {
+        deserialize_filtered_in_network_file(json.as_bytes(), &filters)
+            .unwrap()
+            .in_network
+    });
+
+    assert_eq!(full.len(), 1);
+    assert_eq!(fast.len(), 1);
+    assert_eq!(fast[0].billing_code, "1000");
+    println!(
+        "{} mb, {NUM_RATE_OBJECTS} rate objects: deserializing all {full_time:?}, skipping non-matching {fast_time:?} ({:.1}x)",
+        json.len() / 1_000_000,
+        full_time.as_secs_f64() / fast_time.as_secs_f64()
+    );
+}
+
+// when most rate objects match, they're read straight into the dtos, like without a filter
+#[test]
+#[ignore]
+fn benchmark_filtering_mostly_matching_rate_objects() {
+    let json = in_network_file();
+    let filters = NodeFilters::new((1..NUM_RATE_OBJECTS).map(|code| code.to_string()).collect());
+
+    // read the same way as the filtered file, from a reader
+    let (full, full_time) = time(|| {
+        serde_json::from_reader::<_, UnfilteredInNetworkFile>(json.as_bytes())
+            .unwrap()
+            .in_network
+            .len()
+    });
+    let (filtered, filtered_time) = time(|| {
+        deserialize_filtered_in_network_file(json.as_bytes(), &filters)
+            .unwrap()
+            .in_network
+            .len()
+    });
+
+    assert_eq!(full, NUM_RATE_OBJECTS);
+    assert_eq!(filtered, NUM_RATE_OBJECTS - 1);
+    println!(
+        "{} mb, {NUM_RATE_OBJECTS} rate objects: deserializing all {full_time:?}, filtering all but one {filtered_time:?} ({:.1}x)",
+        json.len() / 1_000_000,
+        full_time.as_secs_f64() / filtered_time.as_secs_f64()
+    );
+}
+
+#[test]
+#[ignore]
+fn benchmark_borrowing_rate_objects() {
+    let json = in_network_file();
+    // every billing code, so every rate object is read, but by way of its billing code
+    let filters = NodeFilters::new((0..NUM_RATE_OBJECTS).map(|code| code.to_string()).collect());
+
+    let (owned, owned_time) = time(|| {
+        serde_json::from_slice::<UnfilteredInNetworkFile>(json.as_bytes())
+            .unwrap()
+            .in_network
+            .into_iter()
+            .filter(|rate_object| filters.matches(rate_object))
+            .count()
+    });
+    // every string borrowed from the slice rather than allocated
+    let (borrowed, borrowed_time) = time(|| {
+        let rate_objects =
+            deserialize_filtered_borrowed_rate_objects(json.as_bytes(), &filters).unwrap();
+        assert!(matches!(rate_objects[0].name, Cow::Borrowed(_)));
+        rate_objects.len()
+    });
+
+    assert_eq!(owned, NUM_RATE_OBJECTS);
+    assert_eq!(borrowed, NUM_RATE_OBJECTS);
+    println!(
+        "{} mb, {NUM_RATE_OBJECTS} rate objects: owned {owned_time:?}, borrowed {borrowed_time:?} ({:.1}x)",
+        json.len() / 1_000_000,
+        owned_time.as_secs_f64() / borrowed_time.as_secs_f64()
+    );
 }
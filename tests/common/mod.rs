@@ -0,0 +1,301 @@
+// shared by several test crates, none of which use all of it
+#![allow(dead_code)]
+
+use std::{
+    collections::HashMap,
+    fs,
+    io::{BufRead, BufReader, Write},
+    net::{TcpListener, TcpStream},
+    sync::{Arc, Mutex},
+    thread,
+    time::Duration,
+};
+
+use tempfile::TempDir;
+
+use rust_cms_json_parser::index_file_parsing::meta_repository_trait::{FileType, MetaRepository};
+
+// a stand-in http server answering GETs for its routes' paths with their bodies, and 404 otherwise.
+// responses' etags are their body's length, and `Range: bytes=<start>-` requests are honored.
+// each connection is answered on its own thread, so downloads can overlap.
+pub struct TestServer {
+    pub url: String,
+    state: Arc<Mutex<ServerState>>,
+}
+
+#[derive(Default)]
+struct ServerState {
+    routes: HashMap<String, Route>,
+    hits: HashMap<String, usize>,
+    ranges: HashMap<String, Vec<String>>,
+    active: usize,
+    max_active: usize,
+}
+
+#[derive(Default)]
+struct Route {
+    body: Vec<u8>,
+    // statuses to answer the next requests with, instead of the body
+    failures: Vec<u16>,
+    // answers the next request with only this many bytes of the body, then hangs up
+    cut_after: Option<usize>,
+    delay: Duration,
+}
+
+struct Request {
+    path: String,
+    range_start: Option<usize>,
+    if_range: Option<String>,
+}
+
+impl TestServer {
+    pub fn start() -> TestServer {
+        let state: Arc<Mutex<ServerState>> = Arc::default();
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}", listener.local_addr().unwrap());
+
+        let server_state = state.clone();
+        thread::spawn(move || {
+            for stream in listener.incoming() {
+                let state = server_state.clone();
+                thread::spawn(move || respond(stream.unwrap(), &state));
+            }
+        });
+        TestServer { url, state }
+    }
+
+    // serves `body` at `path`, returning its full url
+    pub fn route(&self, path: &str, body: impl Into<Vec<u8>>) -> String {
+        let mut state = self.state.lock().unwrap();
+        state.routes.entry(path.to_string()).or_default().body = body.into();
+        format!("{}{path}", self.url)
+    }
+
+    // answers the next `times` requests for `path` with `status`
+    pub fn fail_next(&self, path: &str, status: u16, times: usize) {
+        let mut state = self.state.lock().unwrap();
+        let route = state.routes.entry(path.to_string()).or_default();
+        route.failures.extend(std::iter::repeat_n(status, times));
+    }
+
+    // drops the connection of the next request for `path` after `bytes` bytes of its body
+    pub fn cut_next(&self, path: &str, bytes: usize) {
+        let mut state = self.state.lock().unwrap();
+        state.routes.entry(path.to_string()).or_default().cut_after = Some(bytes);
+    }
+
+    // waits `delay` before answering each request for `path`
+    pub fn delay(&self, path: &str, delay: Duration) {
+        let mut state = self.state.lock().unwrap();
+        state.routes.entry(path.to_string()).or_default().delay = delay;
+    }
+
+    // how many requests have been made for `path`
+    pub fn hits(&self, path: &str) -> usize {
+        *self.state.lock().unwrap().hits.get(path).unwrap_or(&0)
+    }
+
+    // the `Range` headers sent with requests for `path`
+    pub fn ranges(&self, path: &str) -> Vec<String> {
+        let state = self.state.lock().unwrap();
+        state.ranges.get(path).cloned().unwrap_or_default()
+    }
+
+    // the most requests that were being answered at once
+    pub fn max_concurrent(&self) -> usize {
+        self.state.lock().unwrap().max_active
+    }
+}
+
+fn read_request(reader: &mut BufReader<TcpStream>) -> Request {
+    let mut request_line = String::new();
+    reader.read_line(&mut request_line).unwrap();
+    let path = request_line
+        .split_whitespace()
+        .nth(1)
+        .unwrap_or("")
+        .to_string();
+
+    let mut request = Request {
+        path,
+        range_start: None,
+        if_range: None,
+    };
+    let mut line = String::new();
+    while reader.read_line(&mut line).unwrap() > 2 {
+        if let Some((name, value)) = line.trim_end().split_once(':') {
+            let value = value.trim();
+            match name.to_ascii_lowercase().as_str() {
+                "range" => {
+                    request.range_start = value
+                        .strip_prefix("bytes=")
+                        .and_then(|range| range.strip_suffix('-'))
+                        .and_then(|start| start.parse().ok());
+                }
+                "if-range" => request.if_range = Some(value.to_string()),
+                _ => {}
+            }
+        }
+        line.clear();
+    }
+    request
+}
+
+fn respond(stream: TcpStream, state: &Mutex<ServerState>) {
+    let mut reader = BufReader::new(stream);
+    let request = read_request(&mut reader);
+
+    let (route, delay) = {
+        let mut state = state.lock().unwrap();
+        *state.hits.entry(request.path.clone()).or_insert(0) += 1;
+        if let Some(start) = request.range_start {
+            let ranges = state.ranges.entry(request.path.clone()).or_default();
+            ranges.push(format!("bytes={start}-"));
+        }
+        state.active += 1;
+        state.max_active = state.max_active.max(state.active);
+        match state.routes.get_mut(&request.path) {
+            Some(route) if !route.failures.is_empty() => {
+                (Err(route.failures.remove(0)), route.delay)
+            }
+            Some(route) => (
+                Ok((route.body.clone(), route.cut_after.take())),
+                route.delay,
+            ),
+            None => (Err(404), Duration::ZERO),
+        }
+    };
+    thread::sleep(delay);
+
+    let mut stream = reader.into_inner();
+    match route {
+        Err(status) => {
+            write!(
+                stream,
+                "HTTP/1.1 {status} Error\r\nContent-Length: 0\r\nConnection: close\r\n\r\n"
+            )
+            .unwrap();
+        }
+        Ok((body, cut_after)) => {
+            let etag = format!("\"{}\"", body.len());
+            // a range is ignored, and the whole body sent, when the file has changed since
+            let start = request
+                .range_start
+                .filter(|_| request.if_range.as_ref().is_none_or(|tag| *tag == etag));
+            let headers = match start {
+                Some(start) if start >= body.len() => format!(
+                    "416 Range Not Satisfiable\r\nContent-Range: bytes */{}\r\nContent-Length: 0",
+                    body.len()
+                ),
+                Some(start) => format!(
+                    "206 Partial Content\r\nContent-Range: bytes {start}-{}/{}\r\nContent-Length: {}",
+                    body.len() - 1,
+                    body.len(),
+                    body.len() - start
+                ),
+                None => format!("200 OK\r\nContent-Length: {}", body.len()),
+            };
+            write!(
+                stream,
+                "HTTP/1.1 {headers}\r\nETag: {etag}\r\nConnection: close\r\n\r\n"
+            )
+            .unwrap();
+            if !headers.starts_with("416") {
+                let body = &body[start.unwrap_or(0)..];
+                let sent = cut_after.map_or(body.len(), |cut| cut.min(body.len()));
+                // the client may hang up first, e.g. after a failed attempt
+                let _ = stream.write_all(&body[..sent]);
+            }
+        }
+    }
+    drop(stream);
+    state.lock().unwrap().active -= 1;
+}
+
+// csv dbs with only headers in a fresh temp dir, so tests don't write to ./db
+pub fn create_csv_db_dir() -> TempDir {
+    let dir = tempfile::tempdir().expect("a temp dir");
+    let headers = [
+        (
+            "files.csv",
+            "id,url,filename,reporting_entity_name,reporting_entity_type,file_type,description",
+        ),
+        ("links.csv", "id,from_id,from_type,to_id,to_type"),
+        (
+            "plans.csv",
+            "id,plan_name,plan_id_type,plan_id,plan_market_type",
+        ),
+    ];
+    for (name, header) in headers {
+        fs::write(dir.path().join(name), format!("{header}\n")).expect("csv db to be written");
+    }
+    dir
+}
+
+pub fn path_str(dir: &TempDir, name: &str) -> String {
+    dir.path().join(name).to_string_lossy().to_string()
+}
+
+pub fn count_rows(path: &str) -> usize {
+    csv::Reader::from_path(path)
+        .expect("csv db to exist")
+        .into_records()
+        .count()
+}
+
+// the rows of the checked-in db/files.csv, which was written before files had a type:
+// id, url, filename, reporting_entity_name, reporting_entity_type
+pub fn legacy_file_rows() -> Vec<csv::StringRecord> {
+    csv::Reader::from_path("db/files.csv")
+        .expect("the checked-in files db")
+        .into_records()
+        .map(|row| row.unwrap())
+        .collect()
+}
+
+// the legacy rows' types and descriptions once upgraded: the index file was named "index",
+// and allowed amount files were named by their description
+pub fn assert_legacy_files_classified(repo: &mut impl MetaRepository) {
+    let expected = [
+        ("example.com/file.json", FileType::InNetwork, ""),
+        (
+            "./price-transparency-guide/examples/table-of-contents/table-of-contents-sample.json",
+            FileType::Index,
+            "",
+        ),
+        (
+            "https://www.some_site.com/files/in-network-file-123456.json",
+            FileType::InNetwork,
+            "",
+        ),
+        (
+            "https://www.some_site.com/files/behavioral-health-0000.json",
+            FileType::InNetwork,
+            "",
+        ),
+        (
+            "https://www.some_site.com/files/allowed-amount-file-987665.json",
+            FileType::AllowedAmount,
+            "allowed amount file",
+        ),
+        (
+            "https://www.some_site.com/files/chip-in-network-file.json",
+            FileType::InNetwork,
+            "",
+        ),
+        (
+            "https://www.some_site.com/files/chip-allowed-amount-file.json",
+            FileType::AllowedAmount,
+            "allowed amount file",
+        ),
+    ];
+    assert_eq!(legacy_file_rows().len(), expected.len());
+    for (url, file_type, description) in expected {
+        let file = repo.get_file(url).expect("the legacy file to be kept");
+        assert_eq!(
+            (file.file_type, file.description.as_str()),
+            (file_type, description),
+            "{url}"
+        );
+    }
+}
@@ -0,0 +1,308 @@
+mod common;
+
+use std::{fs, time::Duration};
+
+use rust_cms_json_parser::{
+    crawl::{self, CrawlManifest, CrawlOptions},
+    index_file_parsing::{
+        csv_meta_repository::CsvMetaRepository,
+        meta_repository_trait::{FileStatus, FileType, MetaRepository},
+    },
+    node_filters::NodeFilters,
+};
+use serde_json::json;
+
+use common::TestServer;
+
+// an index file on a stand-in server, whose structures share an in-network file,
+// and where one of the in-network files it lists is missing
+fn serve_index_file(server: &TestServer) -> String {
+    let rates = fs::read("./tests/fixtures/in-network-rates-sample.json").unwrap();
+    let shared = server.route("/in-network/shared.json", rates.clone());
+    let own = server.route("/in-network/own.json", rates);
+    let missing = format!("{}/in-network/missing.json", server.url);
+    let plan = |name: &str, id: &str| {
+        json!({
+            "plan_name": name,
+            "plan_id_type": "HIOS",
+            "plan_id": id,
+            "plan_market_type": "individual"
+        })
+    };
+    let linked_file = |location: &str| json!({ "description": "rates", "location": location });
+    let index_file = json!({
+        "reporting_entity_name": "medicare",
+        "reporting_entity_type": "medicare",
+        "reporting_structure": [
+            {
+                "reporting_plans": [plan("medicaid", "1111111111"), plan("medicare", "2222222222")],
+                "in_network_files": [linked_file(&shared), linked_file(&own)],
+                "allowed_amount_file": linked_file(&format!("{}/allowed.json", server.url))
+            },
+            {
+                "reporting_plans": [plan("chip", "3333333333")],
+                "in_network_files": [linked_file(&shared), linked_file(&missing)]
+            }
+        ]
+    });
+    server.route("/index.json", index_file.to_string())
+}
+
+fn without_backoff() -> CrawlOptions {
+    CrawlOptions {
+        max_attempts: 2,
+        retry_backoff: Duration::ZERO,
+        ..CrawlOptions::default()
+    }
+}
+
+#[test]
+fn it_crawls_the_in_network_files_of_an_index_file() {
+    let server = TestServer::start();
+    let index_url = serve_index_file(&server);
+    let db_dir = tempfile::tempdir().unwrap();
+    let output_dir = tempfile::tempdir().unwrap();
+    let mut repo = CsvMetaRepository::in_dir(&db_dir).unwrap();
+
+    let manifest = crawl::crawl_index_file_with_options(
+        &index_url,
+        &mut repo,
+        &NodeFilters::new(vec!["27447".to_string()]),
+        &output_dir,
+        &without_backoff(),
+    )
+    .expect("index file to be crawled");
+
+    let crawled: Vec<(&str, &[usize], Option<usize>)> = manifest
+        .files
+        .iter()
+        .map(|f| {
+            (
+                f.url.rsplit('/').next().unwrap(),
+                &f.plan_ids[..],
+                f.num_rate_objects,
+            )
+        })
+        .collect();
+    assert_eq!(
+        crawled,
+        vec![
+            ("shared.json", &[1, 2, 3][..], Some(1)),
+            ("own.json", &[1, 2][..], Some(1)),
+            ("missing.json", &[3][..], None),
+        ]
+    );
+    assert!(manifest.files[2].error.is_some());
+    // the shared file is downloaded once, and the allowed amount file not at all
+    assert_eq!(server.hits("/in-network/shared.json"), 1);
+    assert_eq!(server.hits("/allowed.json"), 0);
+
+    let output = output_dir
+        .path()
+        .join(manifest.files[0].output.as_ref().unwrap());
+    // read back as json, since `InNetworkFile`'s own filter would drop these rate objects
+    let filtered: serde_json::Value = serde_json::from_slice(&fs::read(output).unwrap()).unwrap();
+    assert_eq!(filtered["in_network"][0]["billing_code"], "27447");
+    assert_eq!(filtered["provider_references"][0]["provider_group_id"], 1);
+    // nothing's left half written
+    let names: Vec<String> = fs::read_dir(&output_dir)
+        .unwrap()
+        .map(|entry| entry.unwrap().file_name().to_string_lossy().to_string())
+        .collect();
+    assert!(
+        !names.iter().any(|name| name.ends_with(".partial")),
+        "{names:?}"
+    );
+
+    let written: serde_json::Value = serde_json::from_slice(
+        &fs::read(output_dir.path().join(crawl::MANIFEST_FILENAME)).unwrap(),
+    )
+    .unwrap();
+    assert_eq!(written["files"][1]["plan_ids"], json!([1, 2]));
+
+    // the missing file is left to be crawled again
+    let unprocessed: Vec<usize> = repo
+        .list_unprocessed_files_of_type(FileType::InNetwork)
+        .iter()
+        .map(|f| f.id)
+        .collect();
+    assert_eq!(unprocessed, vec![manifest.files[2].file_id]);
+}
+
+#[test]
+fn it_keeps_every_rate_object_without_billing_code_filters() {
+    let server = TestServer::start();
+    let index_url = serve_index_file(&server);
+    let db_dir = tempfile::tempdir().unwrap();
+    let output_dir = tempfile::tempdir().unwrap();
+    let mut repo = CsvMetaRepository::in_dir(&db_dir).unwrap();
+
+    let manifest: CrawlManifest = crawl::crawl_index_file_with_options(
+        &index_url,
+        &mut repo,
+        &NodeFilters::new(vec![]),
+        &output_dir,
+        &without_backoff(),
+    )
+    .unwrap();
+
+    assert_eq!(manifest.files[0].num_rate_objects, Some(3));
+}
+
+#[test]
+fn it_resumes_a_crawl_where_it_left_off() {
+    let server = TestServer::start();
+    let index_url = serve_index_file(&server);
+    let db_dir = tempfile::tempdir().unwrap();
+    let output_dir = tempfile::tempdir().unwrap();
+    let filters = NodeFilters::new(vec![]);
+    let mut repo = CsvMetaRepository::in_dir(&db_dir).unwrap();
+
+    let first = crawl::crawl_index_file_with_options(
+        &index_url,
+        &mut repo,
+        &filters,
+        &output_dir,
+        &without_backoff(),
+    )
+    .unwrap();
+    let (shared_id, missing_id) = (first.files[0].file_id, first.files[2].file_id);
+    let missing = repo.get_file_state(missing_id).unwrap();
+    assert_eq!((missing.status, missing.attempts), (FileStatus::Failed, 2));
+    assert!(missing.error.unwrap().contains("404"));
+    assert_eq!(server.hits("/in-network/missing.json"), 2);
+
+    let shared = repo.get_file_state(shared_id).unwrap();
+    let rates = fs::read("./tests/fixtures/in-network-rates-sample.json").unwrap();
+    assert_eq!(shared.status, FileStatus::Parsed);
+    assert_eq!(shared.bytes_processed, rates.len() as u64);
+    assert_eq!(shared.content_hash.map(|hash| hash.len()), Some(64));
+    assert_eq!(shared.etag, Some(format!("\"{}\"", rates.len())));
+
+    // a rerun, from a fresh repository over the same csvs, only retries the missing file,
+    // given an attempt to spare
+    drop(repo);
+    server.route("/in-network/missing.json", rates);
+    let mut repo = CsvMetaRepository::in_dir(&db_dir).unwrap();
+    let second = crawl::crawl_index_file_with_options(
+        &index_url,
+        &mut repo,
+        &filters,
+        &output_dir,
+        &CrawlOptions {
+            max_attempts: 3,
+            ..without_backoff()
+        },
+    )
+    .unwrap();
+
+    assert_eq!(server.hits("/in-network/shared.json"), 1);
+    assert_eq!(server.hits("/in-network/missing.json"), 3);
+    assert_eq!(second.files[0].num_rate_objects, None);
+    assert_eq!(second.files[0].status, FileStatus::Parsed);
+    assert_eq!(second.files[2].num_rate_objects, Some(3));
+    let missing = repo.get_file_state(missing_id).unwrap();
+    assert_eq!(
+        (missing.status, missing.attempts, missing.error),
+        (FileStatus::Parsed, 3, None)
+    );
+}
+
+#[test]
+fn it_backs_off_and_caps_attempts_across_crawls() {
+    let server = TestServer::start();
+    let index_url = serve_index_file(&server);
+    let db_dir = tempfile::tempdir().unwrap();
+    let output_dir = tempfile::tempdir().unwrap();
+    let filters = NodeFilters::new(vec![]);
+    let mut repo = CsvMetaRepository::in_dir(&db_dir).unwrap();
+    let options = |max_attempts| CrawlOptions {
+        max_attempts,
+        retry_backoff: Duration::from_millis(300),
+        ..CrawlOptions::default()
+    };
+
+    let first = crawl::crawl_index_file_with_options(
+        &index_url,
+        &mut repo,
+        &filters,
+        &output_dir,
+        &options(1),
+    )
+    .unwrap();
+    let missing_id = first.files[2].file_id;
+    let failed = repo.get_file_state(missing_id).unwrap();
+    assert_eq!(failed.attempts, 1);
+    let failed_at_ms = failed.last_attempt_at_ms.unwrap();
+
+    // the retry waits out the backoff after the first crawl's attempt
+    crawl::crawl_index_file_with_options(&index_url, &mut repo, &filters, &output_dir, &options(2))
+        .unwrap();
+    assert_eq!(server.hits("/in-network/missing.json"), 2);
+    let retried = repo.get_file_state(missing_id).unwrap();
+    assert_eq!((retried.status, retried.attempts), (FileStatus::Failed, 2));
+    assert!(retried.last_attempt_at_ms.unwrap() >= failed_at_ms + 300);
+
+    // and once every attempt's been used, later crawls don't try it again
+    let third = crawl::crawl_index_file_with_options(
+        &index_url,
+        &mut repo,
+        &filters,
+        &output_dir,
+        &options(2),
+    )
+    .unwrap();
+    assert_eq!(server.hits("/in-network/missing.json"), 2);
+    assert_eq!(third.files[2].status, FileStatus::Failed);
+    assert!(third.files[2].error.as_ref().unwrap().contains("404"));
+    assert!(third.files[2].exhausted);
+    assert!(!third.files[0].exhausted);
+
+    // unless they're told to start its attempts over
+    server.route(
+        "/in-network/missing.json",
+        fs::read("./tests/fixtures/in-network-rates-sample.json").unwrap(),
+    );
+    let fourth = crawl::crawl_index_file_with_options(
+        &index_url,
+        &mut repo,
+        &filters,
+        &output_dir,
+        &CrawlOptions {
+            retry_exhausted: true,
+            ..options(2)
+        },
+    )
+    .unwrap();
+    assert_eq!(server.hits("/in-network/missing.json"), 3);
+    assert_eq!(fourth.files[2].status, FileStatus::Parsed);
+    assert!(!fourth.files[2].exhausted);
+    let retried = repo.get_file_state(missing_id).unwrap();
+    assert_eq!((retried.status, retried.attempts), (FileStatus::Parsed, 1));
+}
+
+#[test]
+fn it_records_how_much_a_failed_attempt_read() {
+    let server = TestServer::start();
+    let index_url = serve_index_file(&server);
+    server.cut_next("/in-network/shared.json", 100);
+    let db_dir = tempfile::tempdir().unwrap();
+    let output_dir = tempfile::tempdir().unwrap();
+    let mut repo = CsvMetaRepository::in_dir(&db_dir).unwrap();
+
+    let manifest = crawl::crawl_index_file_with_options(
+        &index_url,
+        &mut repo,
+        &NodeFilters::new(vec![]),
+        &output_dir,
+        &CrawlOptions {
+            max_attempts: 1,
+            ..without_backoff()
+        },
+    )
+    .unwrap();
+
+    let shared = repo.get_file_state(manifest.files[0].file_id).unwrap();
+    assert_eq!(shared.status, FileStatus::Failed);
+    assert_eq!(shared.bytes_processed, 100);
+}
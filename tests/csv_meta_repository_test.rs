@@ -0,0 +1,282 @@
+mod common;
+
+use std::fs;
+
+use rust_cms_json_parser::index_file_parsing::{
+    self,
+    csv_meta_repository::{CsvDbError, CsvMetaRepository},
+    meta_repository_trait::{
+        DbLinkInput, EntityKind, FileRowInput, FileType, MetaRepository, PlanInput,
+    },
+};
+
+use common::{count_rows, create_csv_db_dir, path_str};
+
+#[test]
+fn it_writes_to_csv_meta_repo() {
+    let db_dir = create_csv_db_dir();
+    let links = path_str(&db_dir, "links.csv");
+    let mut repo = CsvMetaRepository::in_dir(&db_dir).expect("csv dbs in temp dir");
+
+    let file_id: usize = repo.add_file(&FileRowInput {
+        url: "example.com/file.json".to_string(),
+        filename: "file.json".to_string(),
+        reporting_entity_name: "drew".to_string(),
+        reporting_entity_type: "type1".to_string(),
+        file_type: FileType::InNetwork,
+        description: String::new(),
+    });
+
+    let plan_id: usize = repo.add_plan(&PlanInput {
+        plan_name: "plan1".to_string(),
+        plan_id_type: "type1".to_string(),
+        plan_market_type: "market_type1".to_string(),
+        plan_id: "0000000".to_string(),
+    });
+
+    repo.add_link(&DbLinkInput {
+        from_id: file_id,
+        from_type: EntityKind::RateFile,
+        to_id: plan_id,
+        to_type: EntityKind::Plan,
+    });
+    repo.commit();
+
+    assert_eq!(file_id, 1);
+    assert_eq!(plan_id, 1);
+    assert_eq!(count_rows(&links), 1);
+}
+
+#[test]
+fn it_allocates_csv_ids_after_the_largest_existing_id() {
+    let db_dir = create_csv_db_dir();
+    let files = path_str(&db_dir, "files.csv");
+    fs::write(
+        &files,
+        "id,url,filename,reporting_entity_name,reporting_entity_type\n\
+        1,example.com/a.json,a.json,drew,type1\n\
+        7,example.com/b.json,b.json,drew,type1\n",
+    )
+    .unwrap();
+
+    let mut repo = CsvMetaRepository::in_dir(&db_dir).expect("csv dbs in temp dir");
+    let existing_id = repo.add_file(&FileRowInput {
+        url: "example.com/b.json".to_string(),
+        filename: "b.json".to_string(),
+        reporting_entity_name: "drew".to_string(),
+        reporting_entity_type: "type1".to_string(),
+        file_type: FileType::InNetwork,
+        description: String::new(),
+    });
+    let new_id = repo.add_file(&FileRowInput {
+        url: "example.com/c.json".to_string(),
+        filename: "c.json".to_string(),
+        reporting_entity_name: "drew".to_string(),
+        reporting_entity_type: "type1".to_string(),
+        file_type: FileType::InNetwork,
+        description: String::new(),
+    });
+    drop(repo);
+
+    assert_eq!(existing_id, 7);
+    assert_eq!(new_id, 8);
+    // dropping the repo flushes what it buffered
+    assert_eq!(count_rows(&files), 3);
+}
+
+#[test]
+fn it_queries_the_csv_meta_repo() {
+    let db_dir = create_csv_db_dir();
+    let mut repo = CsvMetaRepository::in_dir(&db_dir).expect("csv dbs in temp dir");
+    index_file_parsing::parse_index_file_from_path(
+        "./tests/fixtures/table-of-contents-sample.json",
+        &mut repo,
+    );
+
+    assert_eq!(
+        repo.get_file("https://www.some_site.com/files/behavioral-health-0000.json")
+            .map(|file| (file.id, file.filename)),
+        Some((3, "behavioral-health-0000.json".to_string()))
+    );
+    assert_eq!(repo.get_file("example.com/missing.json"), None);
+
+    // chip is plan 3, only in the second reporting structure
+    let chip_files: Vec<usize> = repo.find_files_for_plan(3).iter().map(|f| f.id).collect();
+    assert_eq!(chip_files, vec![2, 4]);
+    let index_files: Vec<usize> = repo
+        .find_files_for_index_file(1)
+        .iter()
+        .map(|f| f.id)
+        .collect();
+    assert_eq!(index_files, vec![2, 3, 4]);
+    let plans: Vec<String> = repo
+        .find_plans_for_file(2)
+        .into_iter()
+        .map(|plan| plan.plan_name)
+        .collect();
+    assert_eq!(plans, vec!["medicaid", "medicare", "chip"]);
+
+    let unprocessed: Vec<usize> = repo.list_unprocessed_files().iter().map(|f| f.id).collect();
+    assert_eq!(unprocessed, vec![2, 3, 4]);
+    repo.mark_file_processed(3);
+    repo.mark_file_processed(3);
+    let unprocessed: Vec<usize> = repo.list_unprocessed_files().iter().map(|f| f.id).collect();
+    assert_eq!(unprocessed, vec![2, 4]);
+}
+
+#[test]
+fn it_creates_missing_csv_dbs_with_headers() {
+    let db_dir = tempfile::tempdir().unwrap();
+    let mut repo = CsvMetaRepository::in_dir(&db_dir).expect("csv dbs to be created");
+    repo.add_plan(&PlanInput {
+        plan_name: "plan1".to_string(),
+        plan_id_type: "type1".to_string(),
+        plan_market_type: "market_type1".to_string(),
+        plan_id: "0000000".to_string(),
+    });
+    repo.commit();
+
+    let plans = fs::read_to_string(db_dir.path().join("plans.csv")).unwrap();
+    assert_eq!(
+        plans,
+        "id,plan_name,plan_id_type,plan_id,plan_market_type\n\
+        1,plan1,type1,0000000,market_type1\n"
+    );
+    assert_eq!(
+        fs::read_to_string(db_dir.path().join("links.csv")).unwrap(),
+        "id,from_id,from_type,to_id,to_type\n"
+    );
+}
+
+#[test]
+fn it_refuses_to_open_corrupted_csv_dbs() {
+    let db_dir = create_csv_db_dir();
+    let links = path_str(&db_dir, "links.csv");
+    fs::write(&links, "id,from_id,from_type,to_id,to_type\n1,1,1\n").unwrap();
+    assert!(matches!(
+        CsvMetaRepository::in_dir(&db_dir),
+        Err(CsvDbError::MalformedRow {
+            line: 2,
+            expected_fields: 5,
+            found_fields: 3,
+            ..
+        })
+    ));
+
+    fs::write(&links, "id,from,to\n").unwrap();
+    assert!(matches!(
+        CsvMetaRepository::in_dir(&db_dir),
+        Err(CsvDbError::UnexpectedHeader { .. })
+    ));
+}
+
+#[test]
+fn it_repairs_corrupted_csv_dbs() {
+    let db_dir = create_csv_db_dir();
+    let (files, links) = (
+        path_str(&db_dir, "files.csv"),
+        path_str(&db_dir, "links.csv"),
+    );
+    fs::write(
+        &files,
+        "id,url,filename,reporting_entity_name,reporting_entity_type\n\
+        1,example.com/a.json,a.json,drew,type1\n\
+        2,example.com/a.json,a.json,drew,type1\n\
+        3,example.com/b.json,b.json,drew,type1",
+    )
+    .unwrap();
+    fs::write(
+        &links,
+        "1,1,1\n\
+        2,1,index_file,3,rate_file\n\
+        3,2,index_file,3,rate_file\n",
+    )
+    .unwrap();
+
+    let reports = CsvMetaRepository::repair_dir(&db_dir).expect("csv dbs to be repaired");
+    let (files_report, links_report) = (&reports[0], &reports[2]);
+    assert_eq!(files_report.rows_kept, 2);
+    assert_eq!(files_report.duplicate_rows_dropped, 1);
+    assert_eq!(links_report.malformed_rows_dropped, 1);
+    // the link from the duplicate file now points at the kept one, and collapses into it
+    assert_eq!(links_report.duplicate_rows_dropped, 1);
+    assert_eq!(
+        fs::read_to_string(&links).unwrap(),
+        "id,from_id,from_type,to_id,to_type\n2,1,index_file,3,rate_file\n"
+    );
+
+    let mut repo = CsvMetaRepository::in_dir(&db_dir).expect("repaired csv dbs to open");
+    let file_id = repo.add_file(&FileRowInput {
+        url: "example.com/c.json".to_string(),
+        filename: "c.json".to_string(),
+        reporting_entity_name: "drew".to_string(),
+        reporting_entity_type: "type1".to_string(),
+        file_type: FileType::InNetwork,
+        description: String::new(),
+    });
+    assert_eq!(file_id, 4);
+}
+
+#[test]
+fn it_upgrades_csv_dbs_without_file_types() {
+    let db_dir = create_csv_db_dir();
+    let files = path_str(&db_dir, "files.csv");
+    fs::write(
+        &files,
+        "id,url,filename,reporting_entity_name,reporting_entity_type\n\
+        1,example.com/index.json,index,drew,type1\n\
+        2,example.com/a.json,a.json,drew,type1\n",
+    )
+    .unwrap();
+
+    let mut repo = CsvMetaRepository::in_dir(&db_dir).expect("csv dbs to be upgraded");
+    assert_eq!(
+        repo.get_file("example.com/index.json").unwrap().file_type,
+        FileType::Index
+    );
+    assert_eq!(
+        repo.get_file("example.com/a.json").unwrap().file_type,
+        FileType::InNetwork
+    );
+    drop(repo);
+    assert_eq!(
+        fs::read_to_string(&files).unwrap(),
+        "id,url,filename,reporting_entity_name,reporting_entity_type,file_type,description\n\
+        1,example.com/index.json,index,drew,type1,index,\n\
+        2,example.com/a.json,a.json,drew,type1,in_network,\n"
+    );
+}
+
+#[test]
+fn it_upgrades_the_checked_in_csv_db() {
+    let db_dir = tempfile::tempdir().unwrap();
+    for entry in fs::read_dir("db").unwrap() {
+        let path = entry.unwrap().path();
+        fs::copy(&path, db_dir.path().join(path.file_name().unwrap())).unwrap();
+    }
+
+    let mut repo = CsvMetaRepository::in_dir(&db_dir).expect("csv dbs to be upgraded");
+    common::assert_legacy_files_classified(&mut repo);
+}
+
+#[test]
+fn it_upgrades_csv_file_states_without_attempt_times() {
+    let db_dir = create_csv_db_dir();
+    fs::write(
+        path_str(&db_dir, "file_states.csv"),
+        "id,file_id,status,error,bytes_processed,content_hash,etag,attempts\n\
+        1,2,failed,timed out,0,,,1\n",
+    )
+    .unwrap();
+
+    let mut repo = CsvMetaRepository::in_dir(&db_dir).expect("csv dbs to be upgraded");
+    let state = repo.get_file_state(2).unwrap();
+    assert_eq!(
+        (
+            state.error.as_deref(),
+            state.attempts,
+            state.last_attempt_at_ms
+        ),
+        (Some("timed out"), 1, None)
+    );
+}
@@ -0,0 +1,150 @@
+mod common;
+
+use std::{fs, time::Duration};
+
+use rust_cms_json_parser::download::{
+    DownloadError, DownloadManager, DownloadOptions, DownloadRequest,
+};
+use sha2::{Digest, Sha256};
+
+use common::TestServer;
+
+fn body(len: usize) -> Vec<u8> {
+    (0..len).map(|i| (i % 251) as u8).collect()
+}
+
+fn sha256(bytes: &[u8]) -> String {
+    Sha256::digest(bytes)
+        .iter()
+        .map(|byte| format!("{byte:02x}"))
+        .collect()
+}
+
+fn without_backoff() -> DownloadManager {
+    DownloadManager::new(DownloadOptions {
+        max_attempts: 3,
+        retry_backoff: Duration::ZERO,
+        ..DownloadOptions::default()
+    })
+}
+
+#[test]
+fn it_downloads_and_verifies_a_file() {
+    let server = TestServer::start();
+    let rates = body(100_000);
+    let url = server.route("/in-network/rates.json", rates.clone());
+    let dir = tempfile::tempdir().unwrap();
+
+    let mut request = DownloadRequest::new(url, dir.path().join("rates.json"));
+    request.expected_size = Some(rates.len() as u64);
+    request.expected_sha256 = Some(sha256(&rates));
+    let download = without_backoff().download(&request).unwrap();
+
+    assert_eq!(fs::read(&download.path).unwrap(), rates);
+    assert_eq!(download.bytes, rates.len() as u64);
+    assert_eq!(download.sha256, sha256(&rates));
+    assert_eq!(download.etag, Some("\"100000\"".to_string()));
+    assert_eq!((download.attempts, download.resumed_from), (1, 0));
+    assert!(!dir.path().join("rates.json.partial").exists());
+}
+
+#[test]
+fn it_resumes_an_interrupted_download_with_a_range_request() {
+    let server = TestServer::start();
+    let rates = body(100_000);
+    let url = server.route("/in-network/rates.json", rates.clone());
+    server.cut_next("/in-network/rates.json", 30_000);
+    let dir = tempfile::tempdir().unwrap();
+
+    let mut request = DownloadRequest::new(url, dir.path().join("rates.json"));
+    request.expected_sha256 = Some(sha256(&rates));
+    let download = without_backoff().download(&request).unwrap();
+
+    assert_eq!(fs::read(&download.path).unwrap(), rates);
+    assert_eq!((download.attempts, download.resumed_from), (2, 30_000));
+    assert_eq!(
+        server.ranges("/in-network/rates.json"),
+        vec!["bytes=30000-"]
+    );
+}
+
+#[test]
+fn it_starts_over_when_the_file_changed_since_the_partial_download() {
+    let server = TestServer::start();
+    let rates = body(50_000);
+    let url = server.route("/in-network/rates.json", rates.clone());
+    let dir = tempfile::tempdir().unwrap();
+    // left behind by an earlier run, against an older version of the file
+    fs::write(dir.path().join("rates.json.partial"), vec![7; 20_000]).unwrap();
+    fs::write(dir.path().join("rates.json.partial.etag"), "\"40000\"").unwrap();
+
+    let request = DownloadRequest::new(url, dir.path().join("rates.json"));
+    let download = without_backoff().download(&request).unwrap();
+
+    assert_eq!(fs::read(&download.path).unwrap(), rates);
+    assert_eq!(download.resumed_from, 0);
+    assert_eq!(
+        server.ranges("/in-network/rates.json"),
+        vec!["bytes=20000-"]
+    );
+    assert!(!dir.path().join("rates.json.partial.etag").exists());
+}
+
+#[test]
+fn it_retries_server_errors_but_not_missing_files() {
+    let server = TestServer::start();
+    let url = server.route("/in-network/rates.json", body(1_000));
+    server.fail_next("/in-network/rates.json", 503, 2);
+    let dir = tempfile::tempdir().unwrap();
+    let manager = without_backoff();
+
+    let request = DownloadRequest::new(url, dir.path().join("rates.json"));
+    assert_eq!(manager.download(&request).unwrap().attempts, 3);
+
+    let missing = format!("{}/in-network/missing.json", server.url);
+    let request = DownloadRequest::new(missing, dir.path().join("missing.json"));
+    let error = manager.download(&request).unwrap_err();
+    assert!(error.to_string().contains("404"));
+    assert_eq!(server.hits("/in-network/missing.json"), 1);
+}
+
+#[test]
+fn it_fails_a_download_whose_checksum_never_matches() {
+    let server = TestServer::start();
+    let url = server.route("/in-network/rates.json", body(1_000));
+    let dir = tempfile::tempdir().unwrap();
+
+    let mut request = DownloadRequest::new(url, dir.path().join("rates.json"));
+    request.expected_sha256 = Some(sha256(b"something else"));
+    let error = without_backoff().download(&request).unwrap_err();
+
+    assert!(matches!(error, DownloadError::ChecksumMismatch { .. }));
+    assert_eq!(server.hits("/in-network/rates.json"), 3);
+    assert!(!dir.path().join("rates.json").exists());
+    assert!(!dir.path().join("rates.json.partial").exists());
+}
+
+#[test]
+fn it_limits_how_many_files_download_at_once_from_a_host() {
+    let server = TestServer::start();
+    let dir = tempfile::tempdir().unwrap();
+    let requests: Vec<DownloadRequest> = (0..4)
+        .map(|i| {
+            let path = format!("/in-network/{i}.json");
+            server.delay(&path, Duration::from_millis(100));
+            let url = server.route(&path, body(i * 100));
+            DownloadRequest::new(url, dir.path().join(format!("{i}.json")))
+        })
+        .collect();
+
+    let manager = DownloadManager::new(DownloadOptions {
+        max_connections: 8,
+        max_per_host: 2,
+        ..DownloadOptions::default()
+    });
+    let downloads = manager.download_all(&requests);
+
+    assert_eq!(server.max_concurrent(), 2);
+    let sizes: Vec<u64> = downloads.into_iter().map(|d| d.unwrap().bytes).collect();
+    assert_eq!(sizes, vec![0, 100, 200, 300]);
+}
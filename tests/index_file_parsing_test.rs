@@ -0,0 +1,274 @@
+mod common;
+
+use std::{
+    fs::{self, File},
+    path::Path,
+};
+
+use rust_cms_json_parser::{
+    index_file_parsing::{
+        self,
+        csv_meta_repository::CsvMetaRepository,
+        index_file::IndexFile,
+        meta_repository_trait::{FileType, MetaRepository, PlanInput},
+    },
+    location::LocationError,
+};
+
+use common::{count_rows, create_csv_db_dir, path_str, TestServer};
+
+#[test]
+fn it_parses_the_example_index_file() {
+    let example_index_file_path =
+        "./price-transparency-guide/examples/table-of-contents/table-of-contents-sample.json";
+    let db_dir = create_csv_db_dir();
+    let mut repo = CsvMetaRepository::in_dir(&db_dir).expect("csv dbs in temp dir");
+    index_file_parsing::parse_index_file_from_path(example_index_file_path, &mut repo);
+}
+
+#[test]
+fn it_parses_an_index_file_into_the_given_repo() {
+    let db_dir = create_csv_db_dir();
+    let (files, links, plans) = (
+        path_str(&db_dir, "files.csv"),
+        path_str(&db_dir, "links.csv"),
+        path_str(&db_dir, "plans.csv"),
+    );
+    let mut repo = CsvMetaRepository::in_dir(&db_dir).expect("csv dbs in temp dir");
+
+    let results = index_file_parsing::parse_index_file_from_path(
+        "./tests/fixtures/table-of-contents-sample.json",
+        &mut repo,
+    );
+
+    assert_eq!(results.index_file_id, 1);
+    assert_eq!(results.num_reporting_structures, 2);
+    assert_eq!(results.num_plans, 3);
+    assert_eq!(results.num_rate_files, 5);
+    assert_eq!(
+        (
+            results.num_unique_rate_files,
+            results.num_duplicate_rate_files
+        ),
+        (3, 2)
+    );
+    assert!(results.skipped_reporting_structures.is_empty());
+    // files and links shared between reporting structures are only stored once
+    assert_eq!(count_rows(&files), 4);
+    assert_eq!(count_rows(&plans), 3);
+    assert_eq!(count_rows(&links), 11);
+
+    let reparsed = index_file_parsing::parse_index_file_from_path(
+        "./tests/fixtures/table-of-contents-sample.json",
+        &mut repo,
+    );
+
+    assert_eq!(reparsed.index_file_id, results.index_file_id);
+    assert_eq!(count_rows(&files), 4);
+    assert_eq!(count_rows(&plans), 3);
+    assert_eq!(count_rows(&links), 11);
+}
+
+#[test]
+fn it_records_file_types_and_descriptions() {
+    let db_dir = create_csv_db_dir();
+    let mut repo = CsvMetaRepository::in_dir(&db_dir).expect("csv dbs in temp dir");
+    index_file_parsing::parse_index_file_from_path(
+        "./tests/fixtures/table-of-contents-sample.json",
+        &mut repo,
+    );
+
+    let allowed_amounts = repo
+        .get_file("https://www.some_site.com/files/allowed-amounts.json")
+        .unwrap();
+    assert_eq!(allowed_amounts.file_type, FileType::AllowedAmount);
+    assert_eq!(allowed_amounts.filename, "allowed-amounts.json");
+    assert_eq!(allowed_amounts.description, "allowed amounts file");
+
+    let in_network: Vec<usize> = repo
+        .list_unprocessed_files_of_type(FileType::InNetwork)
+        .iter()
+        .map(|f| f.id)
+        .collect();
+    assert_eq!(in_network, vec![2, 3]);
+}
+
+#[test]
+fn it_parses_index_files_without_optional_files() {
+    let db_dir = create_csv_db_dir();
+    let mut repo = CsvMetaRepository::in_dir(&db_dir).expect("csv dbs in temp dir");
+    let results = index_file_parsing::parse_index_file_from_path(
+        "./tests/fixtures/table-of-contents-optional-files.json",
+        &mut repo,
+    );
+
+    assert_eq!(results.version.as_deref(), Some("1.0.0"));
+    assert_eq!(results.num_reporting_structures, 3);
+    assert_eq!(results.num_rate_files, 2);
+    // the third structure's plan is the first's, and it has no files to link to
+    assert_eq!(repo.find_files_for_plan(1).len(), 1);
+    let plan_types: Vec<(String, String)> = repo
+        .find_plans_for_file(3)
+        .into_iter()
+        .map(|plan| (plan.plan_id_type, plan.plan_market_type))
+        .collect();
+    assert_eq!(
+        plan_types,
+        vec![("hios".to_string(), "individual".to_string())]
+    );
+    drop(repo);
+    assert_eq!(count_rows(&path_str(&db_dir, "files.csv")), 3);
+    assert_eq!(count_rows(&path_str(&db_dir, "plans.csv")), 2);
+}
+
+#[test]
+fn it_matches_plans_stored_with_lowercase_id_types() {
+    let db_dir = create_csv_db_dir();
+    let mut repo = CsvMetaRepository::in_dir(&db_dir).expect("csv dbs in temp dir");
+    // as stored before plan id types were an enum, verbatim from a lowercase file
+    let stored_id = repo.add_plan(&PlanInput {
+        plan_name: "individual plan".to_string(),
+        plan_id_type: "hios".to_string(),
+        plan_market_type: "individual".to_string(),
+        plan_id: "2222222222".to_string(),
+    });
+
+    let results = index_file_parsing::parse_index_file_from_path(
+        "./tests/fixtures/table-of-contents-optional-files.json",
+        &mut repo,
+    );
+
+    assert_eq!(results.reporting_structures[1].plan_ids, vec![stored_id]);
+    drop(repo);
+    assert_eq!(count_rows(&path_str(&db_dir, "plans.csv")), 2);
+}
+
+#[test]
+fn it_parses_an_index_file_from_a_url() {
+    let body = fs::read("./tests/fixtures/table-of-contents-sample.json").unwrap();
+    let server = TestServer::start();
+    let url = server.route("/files/table-of-contents-sample.json", body);
+    let db_dir = create_csv_db_dir();
+    let mut repo = CsvMetaRepository::in_dir(&db_dir).expect("csv dbs in temp dir");
+
+    let results =
+        index_file_parsing::parse_index_file(url.clone(), &mut repo).expect("index file from url");
+
+    assert_eq!(results.num_plans, 3);
+    let index = repo.get_file(&url).expect("index file recorded by its url");
+    assert_eq!((index.id, index.file_type), (1, FileType::Index));
+}
+
+#[test]
+fn it_skips_malformed_reporting_structures_and_reports_them_as_json() {
+    let plan = r#"{"plan_name": "medicaid", "plan_id_type": "HIOS", "plan_id": "1111111111", "plan_market_type": "individual"}"#;
+    let index_file = format!(
+        r#"{{
+            "reporting_entity_name": "medicare",
+            "reporting_entity_type": "medicare",
+            "reporting_structure": [
+                {{"in_network_files": [{{"description": "no plans", "location": "https://example.com/a.json"}}]}},
+                {{
+                    "reporting_plans": [{plan}],
+                    "in_network_files": [{{"description": "rates", "location": "https://example.com/b.json"}}],
+                    "allowed_amount_file": {{"description": "allowed", "location": "https://example.com/c.json"}}
+                }}
+            ]
+        }}"#
+    );
+    let db_dir = create_csv_db_dir();
+    let index_path = db_dir.path().join("index.json");
+    fs::write(&index_path, index_file).unwrap();
+    let mut repo = CsvMetaRepository::in_dir(&db_dir).expect("csv dbs in temp dir");
+
+    let results =
+        index_file_parsing::parse_index_file(index_path.to_str().unwrap(), &mut repo).unwrap();
+
+    assert_eq!(results.num_reporting_structures, 2);
+    assert_eq!(results.skipped_reporting_structures.len(), 1);
+    assert_eq!(results.skipped_reporting_structures[0].index, 0);
+    assert!(results.skipped_reporting_structures[0]
+        .error
+        .contains("reporting_plans"));
+    // nothing from the skipped structure is recorded
+    assert!(repo.get_file("https://example.com/a.json").is_none());
+
+    let report = serde_json::to_value(&results).unwrap();
+    assert_eq!(report["num_rate_files"], 2);
+    assert_eq!(
+        report["reporting_structures"],
+        serde_json::json!([{
+            "index": 1,
+            "plan_ids": [1],
+            "in_network_file_ids": [2],
+            "allowed_amount_file_id": 3
+        }])
+    );
+    assert!(report["started_at_ms"].as_u64().unwrap() > 0);
+}
+
+#[test]
+fn it_reports_index_files_that_cant_be_downloaded() {
+    let url = format!("{}/missing.json", TestServer::start().url);
+    let db_dir = create_csv_db_dir();
+    let mut repo = CsvMetaRepository::in_dir(&db_dir).expect("csv dbs in temp dir");
+
+    let result = index_file_parsing::parse_index_file(url, &mut repo);
+
+    assert!(matches!(result, Err(LocationError::Http(_))));
+    assert!(repo.list_unprocessed_files().is_empty());
+}
+
+#[test]
+fn it_reports_index_files_that_arent_well_formed() {
+    let json = fs::read_to_string("./tests/fixtures/table-of-contents-sample.json").unwrap();
+    // cut short after the first reporting structure
+    let second = json.match_indices("\"reporting_plans\"").nth(1).unwrap().0;
+    let index_path = tempfile::NamedTempFile::new().unwrap();
+    fs::write(index_path.path(), &json[..second]).unwrap();
+    let db_dir = create_csv_db_dir();
+    let mut repo = CsvMetaRepository::in_dir(&db_dir).expect("csv dbs in temp dir");
+
+    let result =
+        index_file_parsing::parse_index_file(index_path.path().to_str().unwrap(), &mut repo);
+
+    assert!(matches!(result, Err(LocationError::Json(_))), "{result:?}");
+}
+
+// #[test]
+// fn it_sends_and_receives_deserialized_items_to_channel() {
+//     let path = Path::new("price-transparency-guide")
+//         .join("examples")
+//         .join("table-of-contents")
+//         .join("table-of-contents-sample.json");
+
+//     let file = File::open(path).unwrap();
+//     let index_file_deserializer = &mut serde_json::Deserializer::from_reader(file);
+
+//     // deserialize by sending value on the channel
+//     let (sender, receiver) = channel::<ReportingStructure>();
+// let channel_visitor = ChannelVisitor { sender };
+// index_file_deserializer
+//     .deserialize_newtype_struct("Channel", channel_visitor)
+//     .unwrap();
+
+// // receive value
+// let value = receiver.recv().unwrap();
+// println!("Received value: {:?}", value);
+// }
+
+#[test]
+fn it_deserializes_via_channels() {
+    let path = Path::new("price-transparency-guide")
+        .join("examples")
+        .join("table-of-contents")
+        .join("table-of-contents-sample.json");
+
+    let file = File::open(path).unwrap();
+    let mut index_file = IndexFile::from_reader(file).unwrap();
+    println!("got index file! {:?}", index_file.reporting_entity_name);
+    for reporting_structure in index_file.reporting_structure.by_ref() {
+        println!("{:?}", reporting_structure);
+    }
+    index_file.finish().unwrap();
+}
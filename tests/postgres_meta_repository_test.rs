@@ -0,0 +1,325 @@
+#![cfg(feature = "postgres")]
+
+// these tests need a postgres server to write to, e.g.
+// `docker run -e POSTGRES_HOST_AUTH_METHOD=trust -p 5432:5432 postgres`
+// and `POSTGRES_TEST_URL="host=localhost user=postgres" cargo test --features postgres -- --ignored`.
+// they're ignored unless asked for, and fail rather than pass when POSTGRES_TEST_URL isn't set.
+
+mod common;
+
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use postgres::{Client, NoTls};
+use rust_cms_json_parser::index_file_parsing::{
+    self,
+    meta_repository_trait::{
+        DbLinkInput, EntityKind, FileRowInput, FileState, FileStatus, FileType, MetaRepository,
+        PlanInput,
+    },
+    postgres_meta_repository::PostgresMetaRepository,
+};
+
+static SCHEMA_COUNTER: AtomicUsize = AtomicUsize::new(0);
+
+// a client whose search_path is a fresh schema, so tests don't see each other's rows
+fn connect_to_fresh_schema() -> (Client, String) {
+    let url = std::env::var("POSTGRES_TEST_URL").expect("POSTGRES_TEST_URL to be set");
+    let schema = format!(
+        "meta_repo_test_{}_{}",
+        std::process::id(),
+        SCHEMA_COUNTER.fetch_add(1, Ordering::SeqCst)
+    );
+    let mut client = Client::connect(&url, NoTls).expect("postgres to be reachable");
+    client
+        .batch_execute(&format!(
+            "DROP SCHEMA IF EXISTS {schema} CASCADE;
+            CREATE SCHEMA {schema};
+            SET search_path TO {schema};"
+        ))
+        .unwrap();
+    (client, schema)
+}
+
+fn count_rows(schema: &str, table: &str) -> i64 {
+    let url = std::env::var("POSTGRES_TEST_URL").unwrap();
+    let mut client = Client::connect(&url, NoTls).unwrap();
+    client
+        .query_one(&format!("SELECT COUNT(*) FROM {schema}.{table}"), &[])
+        .unwrap()
+        .get(0)
+}
+
+#[test]
+#[ignore = "needs a postgres server at POSTGRES_TEST_URL"]
+fn it_writes_rows_only_on_commit() {
+    let (client, schema) = connect_to_fresh_schema();
+    let mut repo = PostgresMetaRepository::new(client).unwrap();
+
+    let file_id = repo.add_file(&FileRowInput {
+        url: "example.com/file.json".to_string(),
+        filename: "file.json".to_string(),
+        reporting_entity_name: "drew".to_string(),
+        reporting_entity_type: "type1".to_string(),
+        file_type: FileType::InNetwork,
+        description: String::new(),
+    });
+    let plan_id = repo.add_plan(&PlanInput {
+        plan_name: "plan1".to_string(),
+        plan_id_type: "type1".to_string(),
+        plan_market_type: "market_type1".to_string(),
+        plan_id: "0000000".to_string(),
+    });
+    repo.add_link(&DbLinkInput {
+        from_id: file_id,
+        from_type: EntityKind::RateFile,
+        to_id: plan_id,
+        to_type: EntityKind::Plan,
+    });
+    assert_eq!((file_id, plan_id), (1, 1));
+    assert_eq!(count_rows(&schema, "files"), 0);
+
+    repo.commit();
+
+    assert_eq!(count_rows(&schema, "files"), 1);
+    assert_eq!(count_rows(&schema, "plans"), 1);
+    assert_eq!(count_rows(&schema, "links"), 1);
+}
+
+#[test]
+#[ignore = "needs a postgres server at POSTGRES_TEST_URL"]
+fn it_rolls_back_an_index_file_with_a_dangling_link() {
+    let (client, schema) = connect_to_fresh_schema();
+    let mut repo = PostgresMetaRepository::new(client).unwrap();
+
+    repo.add_file(&FileRowInput {
+        url: "example.com/file.json".to_string(),
+        filename: "file.json".to_string(),
+        reporting_entity_name: "drew".to_string(),
+        reporting_entity_type: "type1".to_string(),
+        file_type: FileType::InNetwork,
+        description: String::new(),
+    });
+    repo.add_link(&DbLinkInput {
+        from_id: 5000,
+        from_type: EntityKind::Plan,
+        to_id: 1,
+        to_type: EntityKind::RateFile,
+    });
+
+    let committed = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| repo.commit()));
+    assert!(committed.is_err());
+    assert_eq!(count_rows(&schema, "files"), 0);
+}
+
+#[test]
+#[ignore = "needs a postgres server at POSTGRES_TEST_URL"]
+fn it_adds_a_rolled_back_file_again() {
+    let (client, schema) = connect_to_fresh_schema();
+    let mut repo = PostgresMetaRepository::new(client).unwrap();
+    let file = FileRowInput {
+        url: "example.com/file.json".to_string(),
+        filename: "file.json".to_string(),
+        reporting_entity_name: "drew".to_string(),
+        reporting_entity_type: "type1".to_string(),
+        file_type: FileType::InNetwork,
+        description: String::new(),
+    };
+
+    let file_id = repo.add_file(&file);
+    repo.add_link(&DbLinkInput {
+        from_id: 5000,
+        from_type: EntityKind::Plan,
+        to_id: file_id,
+        to_type: EntityKind::RateFile,
+    });
+    let committed = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| repo.commit()));
+    assert!(committed.is_err());
+
+    // the rolled back file isn't taken as stored, and gets its id back
+    assert_eq!(repo.add_file(&file), file_id);
+    repo.commit();
+    assert_eq!(count_rows(&schema, "files"), 1);
+    assert_eq!(count_rows(&schema, "links"), 0);
+    assert_eq!(repo.get_file(&file.url).unwrap().id, file_id);
+}
+
+#[test]
+#[ignore = "needs a postgres server at POSTGRES_TEST_URL"]
+fn it_keeps_a_failed_batch_to_retry() {
+    let (client, schema) = connect_to_fresh_schema();
+    let mut repo = PostgresMetaRepository::new(client).unwrap();
+
+    let file_id = repo.add_file(&FileRowInput {
+        url: "example.com/file.json".to_string(),
+        filename: "file.json".to_string(),
+        reporting_entity_name: "drew".to_string(),
+        reporting_entity_type: "type1".to_string(),
+        file_type: FileType::InNetwork,
+        description: String::new(),
+    });
+    repo.add_link(&DbLinkInput {
+        from_id: 5000,
+        from_type: EntityKind::Plan,
+        to_id: file_id,
+        to_type: EntityKind::RateFile,
+    });
+    assert!(repo.try_commit().is_err());
+    assert_eq!(count_rows(&schema, "files"), 0);
+
+    // once the missing plan exists, the same batch goes through
+    let url = std::env::var("POSTGRES_TEST_URL").unwrap();
+    Client::connect(&url, NoTls)
+        .unwrap()
+        .batch_execute(&format!(
+            "INSERT INTO {schema}.plans (id, plan_name, plan_id_type, plan_id, plan_market_type)
+            VALUES (5000, 'plan', 'ein', '1', 'group')"
+        ))
+        .unwrap();
+    repo.try_commit().unwrap();
+    assert_eq!(count_rows(&schema, "files"), 1);
+    assert_eq!(count_rows(&schema, "links"), 1);
+}
+
+#[test]
+#[ignore = "needs a postgres server at POSTGRES_TEST_URL"]
+fn it_parses_an_index_file_into_postgres() {
+    let (client, schema) = connect_to_fresh_schema();
+    let mut repo = PostgresMetaRepository::new(client).unwrap();
+
+    let results = index_file_parsing::parse_index_file_from_path(
+        "./tests/fixtures/table-of-contents-sample.json",
+        &mut repo,
+    );
+
+    assert_eq!(results.index_file_id, 1);
+    assert_eq!(count_rows(&schema, "files"), 4);
+    assert_eq!(count_rows(&schema, "plans"), 3);
+    assert_eq!(count_rows(&schema, "links"), 11);
+}
+
+#[test]
+#[ignore = "needs a postgres server at POSTGRES_TEST_URL"]
+fn it_reuses_rows_committed_by_another_repo() {
+    let (client, schema) = connect_to_fresh_schema();
+    let mut repo = PostgresMetaRepository::new(client).unwrap();
+    index_file_parsing::parse_index_file_from_path(
+        "./tests/fixtures/table-of-contents-sample.json",
+        &mut repo,
+    );
+
+    // a second repo has none of the first one's rows cached
+    let mut client = Client::connect(&std::env::var("POSTGRES_TEST_URL").unwrap(), NoTls).unwrap();
+    client
+        .batch_execute(&format!("SET search_path TO {schema}"))
+        .unwrap();
+    let mut repo = PostgresMetaRepository::new(client).unwrap();
+    let reparsed = index_file_parsing::parse_index_file_from_path(
+        "./tests/fixtures/table-of-contents-sample.json",
+        &mut repo,
+    );
+
+    assert_eq!(reparsed.index_file_id, 1);
+    assert_eq!(count_rows(&schema, "files"), 4);
+    assert_eq!(count_rows(&schema, "plans"), 3);
+    assert_eq!(count_rows(&schema, "links"), 11);
+}
+
+#[test]
+#[ignore = "needs a postgres server at POSTGRES_TEST_URL"]
+fn it_queries_the_postgres_meta_repo() {
+    let (client, _) = connect_to_fresh_schema();
+    let mut repo = PostgresMetaRepository::new(client).unwrap();
+    index_file_parsing::parse_index_file_from_path(
+        "./tests/fixtures/table-of-contents-sample.json",
+        &mut repo,
+    );
+
+    assert_eq!(
+        repo.get_file("https://www.some_site.com/files/behavioral-health-0000.json")
+            .map(|file| (file.id, file.filename)),
+        Some((3, "behavioral-health-0000.json".to_string()))
+    );
+    assert_eq!(repo.get_file("example.com/missing.json"), None);
+
+    // chip is plan 3, only in the second reporting structure
+    let chip_files: Vec<usize> = repo.find_files_for_plan(3).iter().map(|f| f.id).collect();
+    assert_eq!(chip_files, vec![2, 4]);
+    let index_files: Vec<usize> = repo
+        .find_files_for_index_file(1)
+        .iter()
+        .map(|f| f.id)
+        .collect();
+    assert_eq!(index_files, vec![2, 3, 4]);
+    let plans: Vec<String> = repo
+        .find_plans_for_file(2)
+        .into_iter()
+        .map(|plan| plan.plan_name)
+        .collect();
+    assert_eq!(plans, vec!["medicaid", "medicare", "chip"]);
+
+    let unprocessed: Vec<usize> = repo.list_unprocessed_files().iter().map(|f| f.id).collect();
+    assert_eq!(unprocessed, vec![2, 3, 4]);
+    repo.mark_file_processed(3);
+    repo.mark_file_processed(3);
+    let unprocessed: Vec<usize> = repo.list_unprocessed_files().iter().map(|f| f.id).collect();
+    assert_eq!(unprocessed, vec![2, 4]);
+}
+
+#[test]
+#[ignore = "needs a postgres server at POSTGRES_TEST_URL"]
+fn it_replaces_file_states_in_postgres() {
+    let (client, _) = connect_to_fresh_schema();
+    let mut repo = PostgresMetaRepository::new(client).unwrap();
+    index_file_parsing::parse_index_file_from_path(
+        "./tests/fixtures/table-of-contents-sample.json",
+        &mut repo,
+    );
+    assert_eq!(repo.get_file_state(2), None);
+
+    let mut state = FileState::pending(2);
+    state.status = FileStatus::Failed;
+    state.error = Some("connection reset".to_string());
+    state.attempts = 1;
+    state.last_attempt_at_ms = Some(1_700_000_000_000);
+    repo.set_file_state(&state);
+    assert_eq!(repo.get_file_state(2), Some(state.clone()));
+
+    state.status = FileStatus::Parsed;
+    state.error = None;
+    state.bytes_processed = 1024;
+    state.etag = Some("\"abc\"".to_string());
+    repo.set_file_state(&state);
+    assert_eq!(repo.get_file_state(2), Some(state));
+}
+
+#[test]
+#[ignore = "needs a postgres server at POSTGRES_TEST_URL"]
+fn it_upgrades_dbs_from_before_files_had_a_type() {
+    let (mut client, _schema) = connect_to_fresh_schema();
+    // the schema as of the third migration, holding the checked-in legacy rows
+    client
+        .batch_execute(
+            "CREATE TABLE schema_migrations (version INTEGER PRIMARY KEY);
+            INSERT INTO schema_migrations VALUES (1), (2), (3);
+            CREATE TABLE files (
+                id BIGINT GENERATED BY DEFAULT AS IDENTITY PRIMARY KEY,
+                url TEXT NOT NULL UNIQUE,
+                filename TEXT NOT NULL,
+                reporting_entity_name TEXT NOT NULL,
+                reporting_entity_type TEXT NOT NULL
+            );",
+        )
+        .unwrap();
+    for row in common::legacy_file_rows() {
+        let id: i64 = row[0].parse().unwrap();
+        client
+            .execute(
+                "INSERT INTO files VALUES ($1, $2, $3, $4, $5)",
+                &[&id, &&row[1], &&row[2], &&row[3], &&row[4]],
+            )
+            .unwrap();
+    }
+
+    let mut repo = PostgresMetaRepository::new(client).expect("the db to be upgraded");
+    common::assert_legacy_files_classified(&mut repo);
+}
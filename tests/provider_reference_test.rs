@@ -0,0 +1,93 @@
+mod common;
+
+use std::fs;
+
+use rust_cms_json_parser::{
+    in_network_file_dto::{ProviderReference, TinType},
+    provider_reference_file_dto::ProviderReferenceFile,
+    provider_references::{self, ProviderReferenceError},
+};
+use serde_json::json;
+
+use common::TestServer;
+
+const SAMPLE: &str = "./tests/fixtures/provider-reference-sample.json";
+
+fn references(value: serde_json::Value) -> Vec<ProviderReference> {
+    serde_json::from_value(value).unwrap()
+}
+
+#[test]
+fn it_deserializes_a_provider_reference_file() {
+    let file: ProviderReferenceFile = serde_json::from_slice(&fs::read(SAMPLE).unwrap()).unwrap();
+
+    assert_eq!(file.version.as_deref(), Some("1.0.0"));
+    assert_eq!(file.provider_groups.len(), 2);
+    assert!(matches!(file.provider_groups[1].tin.type_, TinType::Npi));
+}
+
+#[test]
+fn it_streams_each_provider_group() {
+    let mut tins = vec![];
+    provider_references::for_each_provider_group(fs::File::open(SAMPLE).unwrap(), |group| {
+        tins.push(group.tin.value)
+    })
+    .unwrap();
+
+    assert_eq!(tins, vec!["33-3333333", "5555555555"]);
+}
+
+#[test]
+fn it_looks_up_inline_local_and_remote_provider_groups_by_id() {
+    let server = TestServer::start();
+    let url = server.route("/provider-groups.json", fs::read(SAMPLE).unwrap());
+
+    let lookup = provider_references::resolve_provider_references(references(json!([
+        {
+            "provider_group_id": 1,
+            "provider_groups": [{ "npi": [1111111111], "tin": { "type": "ein", "value": "11-1111111" } }]
+        },
+        { "provider_group_id": 2, "location": SAMPLE },
+        { "provider_group_id": 3, "location": url }
+    ])))
+    .unwrap();
+
+    assert_eq!(lookup.len(), 3);
+    assert_eq!(lookup.get(1).unwrap()[0].tin.value, "11-1111111");
+    assert_eq!(lookup.get(2).unwrap().len(), 2);
+    let npis: Vec<u64> = lookup.get(3).unwrap()[0]
+        .npi
+        .iter()
+        .filter_map(|npi| npi.as_u64())
+        .collect();
+    assert_eq!(npis, vec![3333333333, 4444444444]);
+    assert!(lookup.get(4).is_none());
+}
+
+#[test]
+fn it_reports_provider_reference_files_that_cant_be_read() {
+    let server = TestServer::start();
+    let missing = format!("{}/missing.json", server.url);
+    let malformed = server.route("/malformed.json", r#"{"version": "1.0.0"}"#);
+
+    let error = provider_references::resolve_provider_references(references(json!([
+        { "provider_group_id": 1, "location": missing }
+    ])))
+    .unwrap_err();
+    assert!(matches!(error, ProviderReferenceError::Http(_)));
+
+    let error = provider_references::resolve_provider_references(references(json!([
+        { "provider_group_id": 1, "location": malformed }
+    ])))
+    .unwrap_err();
+    assert!(error.to_string().contains("provider_groups"), "{error}");
+
+    let error = provider_references::resolve_provider_references(references(json!([
+        { "provider_group_id": 1.5, "provider_groups": [] }
+    ])))
+    .unwrap_err();
+    assert!(matches!(
+        error,
+        ProviderReferenceError::InvalidProviderGroupId(_)
+    ));
+}
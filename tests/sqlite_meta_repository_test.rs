@@ -0,0 +1,214 @@
+#![cfg(feature = "sqlite")]
+
+mod common;
+
+use rust_cms_json_parser::index_file_parsing::{
+    self,
+    meta_repository_trait::{
+        DbLinkInput, EntityKind, FileRowInput, FileState, FileStatus, FileType, MetaRepository,
+        PlanInput,
+    },
+    sqlite_meta_repository::SqliteMetaRepository,
+};
+
+fn count_rows(db_path: &std::path::Path, table: &str) -> usize {
+    let conn = rusqlite::Connection::open(db_path).unwrap();
+    conn.query_row(&format!("SELECT COUNT(*) FROM {table}"), [], |row| {
+        row.get::<_, i64>(0)
+    })
+    .unwrap() as usize
+}
+
+#[test]
+fn it_writes_to_sqlite_meta_repo() {
+    let mut repo = SqliteMetaRepository::open_in_memory().unwrap();
+
+    let file_id = repo.add_file(&FileRowInput {
+        url: "example.com/file.json".to_string(),
+        filename: "file.json".to_string(),
+        reporting_entity_name: "drew".to_string(),
+        reporting_entity_type: "type1".to_string(),
+        file_type: FileType::InNetwork,
+        description: String::new(),
+    });
+    let plan_id = repo.add_plan(&PlanInput {
+        plan_name: "plan1".to_string(),
+        plan_id_type: "type1".to_string(),
+        plan_market_type: "market_type1".to_string(),
+        plan_id: "0000000".to_string(),
+    });
+    let link_id = repo.add_link(&DbLinkInput {
+        from_id: file_id,
+        from_type: EntityKind::RateFile,
+        to_id: plan_id,
+        to_type: EntityKind::Plan,
+    });
+
+    assert_eq!((file_id, plan_id, link_id), (1, 1, 1));
+}
+
+#[test]
+#[should_panic(expected = "link row to be upserted")]
+fn it_rejects_links_to_missing_rows() {
+    let mut repo = SqliteMetaRepository::open_in_memory().unwrap();
+    repo.add_link(&DbLinkInput {
+        from_id: 1,
+        from_type: EntityKind::Plan,
+        to_id: 2,
+        to_type: EntityKind::RateFile,
+    });
+}
+
+#[test]
+fn it_parses_an_index_file_into_a_sqlite_db() {
+    let dir = tempfile::tempdir().unwrap();
+    let db_path = dir.path().join("meta.db");
+
+    let results = {
+        let mut repo = SqliteMetaRepository::open(&db_path).unwrap();
+        index_file_parsing::parse_index_file_from_path(
+            "./tests/fixtures/table-of-contents-sample.json",
+            &mut repo,
+        )
+    };
+
+    assert_eq!(results.index_file_id, 1);
+    assert_eq!(count_rows(&db_path, "files"), 4);
+    assert_eq!(count_rows(&db_path, "plans"), 3);
+    assert_eq!(count_rows(&db_path, "links"), 11);
+
+    // reopening runs no migrations twice, reparsing adds no duplicates,
+    // and new ids are allocated after the existing rows
+    let mut repo = SqliteMetaRepository::open(&db_path).unwrap();
+    let reparsed = index_file_parsing::parse_index_file_from_path(
+        "./tests/fixtures/table-of-contents-sample.json",
+        &mut repo,
+    );
+    assert_eq!(reparsed.index_file_id, 1);
+    assert_eq!(count_rows(&db_path, "files"), 4);
+    assert_eq!(count_rows(&db_path, "links"), 11);
+
+    let file_id = repo.add_file(&FileRowInput {
+        url: "example.com/another.json".to_string(),
+        filename: "another.json".to_string(),
+        reporting_entity_name: "medicare".to_string(),
+        reporting_entity_type: "medicare".to_string(),
+        file_type: FileType::InNetwork,
+        description: String::new(),
+    });
+    assert_eq!(file_id, 5);
+}
+
+#[test]
+fn it_queries_the_sqlite_meta_repo() {
+    let mut repo = SqliteMetaRepository::open_in_memory().unwrap();
+    index_file_parsing::parse_index_file_from_path(
+        "./tests/fixtures/table-of-contents-sample.json",
+        &mut repo,
+    );
+
+    assert_eq!(
+        repo.get_file("https://www.some_site.com/files/behavioral-health-0000.json")
+            .map(|file| (file.id, file.filename)),
+        Some((3, "behavioral-health-0000.json".to_string()))
+    );
+    assert_eq!(repo.get_file("example.com/missing.json"), None);
+
+    // chip is plan 3, only in the second reporting structure
+    let chip_files: Vec<usize> = repo.find_files_for_plan(3).iter().map(|f| f.id).collect();
+    assert_eq!(chip_files, vec![2, 4]);
+    let index_files: Vec<usize> = repo
+        .find_files_for_index_file(1)
+        .iter()
+        .map(|f| f.id)
+        .collect();
+    assert_eq!(index_files, vec![2, 3, 4]);
+    let plans: Vec<String> = repo
+        .find_plans_for_file(2)
+        .into_iter()
+        .map(|plan| plan.plan_name)
+        .collect();
+    assert_eq!(plans, vec!["medicaid", "medicare", "chip"]);
+
+    let unprocessed: Vec<usize> = repo.list_unprocessed_files().iter().map(|f| f.id).collect();
+    assert_eq!(unprocessed, vec![2, 3, 4]);
+    repo.mark_file_processed(3);
+    repo.mark_file_processed(3);
+    let unprocessed: Vec<usize> = repo.list_unprocessed_files().iter().map(|f| f.id).collect();
+    assert_eq!(unprocessed, vec![2, 4]);
+}
+
+#[test]
+fn it_records_file_types_in_sqlite() {
+    let mut repo = SqliteMetaRepository::open_in_memory().unwrap();
+    index_file_parsing::parse_index_file_from_path(
+        "./tests/fixtures/table-of-contents-sample.json",
+        &mut repo,
+    );
+
+    let index = repo
+        .get_file("./tests/fixtures/table-of-contents-sample.json")
+        .unwrap();
+    assert_eq!((index.id, index.file_type), (1, FileType::Index));
+    let allowed_amounts: Vec<String> = repo
+        .list_unprocessed_files_of_type(FileType::AllowedAmount)
+        .into_iter()
+        .map(|f| f.description)
+        .collect();
+    assert_eq!(allowed_amounts, vec!["allowed amounts file"]);
+}
+
+#[test]
+fn it_replaces_file_states_in_sqlite() {
+    let mut repo = SqliteMetaRepository::open_in_memory().unwrap();
+    index_file_parsing::parse_index_file_from_path(
+        "./tests/fixtures/table-of-contents-sample.json",
+        &mut repo,
+    );
+    assert_eq!(repo.get_file_state(2), None);
+
+    let mut state = FileState::pending(2);
+    state.status = FileStatus::Failed;
+    state.error = Some("connection reset".to_string());
+    state.attempts = 1;
+    state.last_attempt_at_ms = Some(1_700_000_000_000);
+    repo.set_file_state(&state);
+    assert_eq!(repo.get_file_state(2), Some(state.clone()));
+
+    state.status = FileStatus::Parsed;
+    state.error = None;
+    state.bytes_processed = 1024;
+    state.etag = Some("\"abc\"".to_string());
+    repo.set_file_state(&state);
+    assert_eq!(repo.get_file_state(2), Some(state));
+}
+
+#[test]
+fn it_upgrades_dbs_from_before_files_had_a_type() {
+    let dir = tempfile::tempdir().unwrap();
+    let db_path = dir.path().join("meta.db");
+    // the schema as of the third migration, holding the checked-in legacy rows
+    let conn = rusqlite::Connection::open(&db_path).unwrap();
+    conn.execute_batch(
+        "CREATE TABLE files (
+            id INTEGER PRIMARY KEY,
+            url TEXT NOT NULL UNIQUE,
+            filename TEXT NOT NULL,
+            reporting_entity_name TEXT NOT NULL,
+            reporting_entity_type TEXT NOT NULL
+        );
+        PRAGMA user_version = 3;",
+    )
+    .unwrap();
+    for row in common::legacy_file_rows() {
+        conn.execute(
+            "INSERT INTO files VALUES (?1, ?2, ?3, ?4, ?5)",
+            rusqlite::params_from_iter(row.iter()),
+        )
+        .unwrap();
+    }
+    drop(conn);
+
+    let mut repo = SqliteMetaRepository::open(&db_path).expect("the db to be upgraded");
+    common::assert_legacy_files_classified(&mut repo);
+}